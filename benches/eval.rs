@@ -0,0 +1,82 @@
+use clip::{
+    eval::{eval, eval_vm, Scope},
+    lexer::Lexer,
+    parser::{ast::Program, Parser},
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// A script that spends most of its time calling functions and looking up the
+/// names of their parameters and of the functions it calls.
+const CALLS: &str = r#"= fib { [n]
+    if < n 2 {
+        n
+    } else {
+        + (fib (- n 1)) (fib (- n 2))
+    }
+}
+= add3 { [a b c] + a b c }
+= twice { [f x] f (f x) }
+= inc { [x] add3 x 1 0 }
+= sum { [n acc]
+    if == n 0 {
+        acc
+    } else {
+        sum (- n 1) (twice inc acc)
+    }
+}
++ (fib 18) (sum 500 0)
+"#;
+
+/// A script that spends most of its time joining strings together and taking
+/// slices of them, which copies them.
+const STRINGS: &str = r#"= build { [n acc]
+    if == n 0 {
+        acc
+    } else {
+        build (- n 1) (+ acc "ab")
+    }
+}
+= rotate { [s n]
+    if == n 0 {
+        s
+    } else {
+        rotate (slice (+ s s) 1 (+ (len s) 1)) (- n 1)
+    }
+}
+len (rotate (build 300 "") 300)
+"#;
+
+fn parse(source: &str) -> Program {
+    Parser::from_lexer(Lexer::new(source))
+        .parse_all()
+        .expect("benchmark script should parse")
+}
+
+fn calls(c: &mut Criterion) {
+    let mut group = c.benchmark_group("calls");
+
+    group.bench_with_input(BenchmarkId::new("tree", "calls"), CALLS, |b, s| {
+        b.iter(|| eval(parse(s), &mut Scope::new()))
+    });
+    group.bench_with_input(BenchmarkId::new("vm", "calls"), CALLS, |b, s| {
+        b.iter(|| eval_vm(parse(s), &mut Scope::new()))
+    });
+
+    group.finish();
+}
+
+fn strings(c: &mut Criterion) {
+    let mut group = c.benchmark_group("strings");
+
+    group.bench_with_input(BenchmarkId::new("tree", "strings"), STRINGS, |b, s| {
+        b.iter(|| eval(parse(s), &mut Scope::new()))
+    });
+    group.bench_with_input(BenchmarkId::new("vm", "strings"), STRINGS, |b, s| {
+        b.iter(|| eval_vm(parse(s), &mut Scope::new()))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, calls, strings);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+use clip::{lexer::Lexer, parser::Parser};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// A script with a bit of everything the lexer handles, which is repeated to
+/// make inputs of different sizes.
+const SCRIPT: &str = r#"# computes the first few fibonacci numbers
+= fib { [n]
+    "gets the nth fibonacci number"
+    if $(n < 2) {
+        n
+    } else {
+        + (fib (- n 1)) (fib (- n 2))
+    }
+}
+
+= names ["alice" "bob" "carol" "dave"]
+= lengths map names { [name]
+    println (format "hello, {}! you are number {}" name (fib 10))
+    + (len name) 1_000
+}
+= total reduce lengths (+) 0i
+assert $(total > 0) "the total should be positive"
+"#;
+
+fn lexer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lexer");
+
+    for copies in [10, 100, 1000] {
+        let source = SCRIPT.repeat(copies);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("lex", copies), &source, |b, s| {
+            b.iter(|| Lexer::new(s).lex())
+        });
+        group.bench_with_input(BenchmarkId::new("lex_reader", copies), &source, |b, s| {
+            b.iter(|| Lexer::from_reader(s.as_bytes()).lex())
+        });
+        group.bench_with_input(BenchmarkId::new("lex_trivia", copies), &source, |b, s| {
+            b.iter(|| {
+                let mut lexer = Lexer::new(s);
+                lexer.keep_trivia(true);
+                lexer.lex()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("parse", copies), &source, |b, s| {
+            b.iter(|| Parser::from_lexer(Lexer::new(s)).parse_all())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, lexer);
+criterion_main!(benches);
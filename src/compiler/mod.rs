@@ -0,0 +1,248 @@
+use crate::{
+    ir::{self, Expr, LogicKind},
+    lexer::token::Location,
+    parser::ast::{Function, Identifier, OperatorKind, Primitive, Statement},
+    sync::Shared,
+};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// A single bytecode instruction for the VM. Instructions push their result onto
+/// the VM's stack, popping any values they use from it.
+#[derive(Clone, Debug)]
+pub enum Op {
+    Constant(Primitive),
+    Load(Identifier),
+    /// Sets a variable to the value on top of the stack, leaving it there.
+    Store(Identifier),
+    Pop,
+    Function(Function),
+    /// Applies an operator to the given number of values, or pushes the operator
+    /// itself if there are none.
+    Operator(OperatorKind, usize, Location),
+    List(usize),
+    And(usize),
+    Or(usize),
+    /// Calls a function. The arguments are compiled separately because they are
+    /// only evaluated once the function is known, and in the scope it runs in,
+    /// except for the first argument of a null-safe call.
+    Call(Box<CallOp>),
+    Jump(usize),
+    /// Pops a condition and jumps if it is falsy. The location is that of the `if`
+    /// statement, for errors.
+    JumpIfFalse(usize, Location),
+    /// Pops a condition and raises an assertion error if it is falsy.
+    Assert(Box<AssertOp>),
+    Defer(Vec<Statement>),
+    /// Pops a value and pauses the task, giving it to whoever asked the generator
+    /// for its next value. Pushes `()` when the task is resumed.
+    Yield,
+    /// Runs the compiled statements of a block expression in a new scope, pushing
+    /// the value of the last one.
+    Scoped(Shared<Chunk>),
+}
+
+#[derive(Clone, Debug)]
+pub struct CallOp {
+    pub name: Identifier,
+    pub args: Vec<Shared<Chunk>>,
+    /// Whether the only argument is a literal `()`.
+    pub unit: bool,
+    /// Whether the first argument has already been pushed onto the stack, rather
+    /// than being one of `args`, and the call is skipped if it's null.
+    pub null_safe: bool,
+    pub loc: Location,
+}
+
+impl CallOp {
+    /// The number of arguments the call is made with.
+    pub fn argc(&self) -> usize {
+        self.args.len() + usize::from(self.null_safe)
+    }
+
+    /// Gets the chunk of the argument at `index`, unless it's the first argument
+    /// of a null-safe call, which is already on the stack.
+    pub fn arg(&self, index: usize) -> Option<&Shared<Chunk>> {
+        let index = index.checked_sub(usize::from(self.null_safe))?;
+        self.args.get(index)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AssertOp {
+    pub message: Option<Shared<Chunk>>,
+    pub loc: Location,
+}
+
+/// A compiled sequence of instructions, which leaves a single value on the stack.
+#[derive(Clone, Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+}
+
+impl Op {
+    /// The location in the source that the instruction came from, for the ones
+    /// that keep it.
+    pub fn loc(&self) -> Option<&Location> {
+        match self {
+            Op::Load(name) | Op::Store(name) => Some(&name.loc),
+            Op::Operator(_, _, loc) | Op::JumpIfFalse(_, loc) => Some(loc),
+            Op::Call(call) => Some(&call.loc),
+            Op::Assert(a) => Some(&a.loc),
+            _ => None,
+        }
+    }
+}
+
+impl Chunk {
+    /// Finds the location of the expression that the instruction at `pc` is part
+    /// of, for errors from instructions that don't keep one. The instruction that
+    /// uses its value comes after it, so that is looked for first.
+    pub fn loc_near(&self, pc: usize) -> Option<&Location> {
+        let (before, after) = self.code.split_at(pc.min(self.code.len()));
+        after
+            .iter()
+            .find_map(Op::loc)
+            .or_else(|| before.iter().rev().find_map(Op::loc))
+    }
+}
+
+impl Display for Chunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for (i, op) in self.code.iter().enumerate() {
+            match op {
+                Op::Constant(p) => writeln!(f, "{:04} CONSTANT {:?}", i, p)?,
+                Op::Load(n) => writeln!(f, "{:04} LOAD {}", i, n.value)?,
+                Op::Store(n) => writeln!(f, "{:04} STORE {}", i, n.value)?,
+                Op::Pop => writeln!(f, "{:04} POP", i)?,
+                Op::Function(fun) => writeln!(f, "{:04} FUNCTION {}", i, fun.params.len())?,
+                Op::Operator(k, n, _) => writeln!(f, "{:04} OPERATOR {} {}", i, k, n)?,
+                Op::List(n) => writeln!(f, "{:04} LIST {}", i, n)?,
+                Op::And(n) => writeln!(f, "{:04} AND {}", i, n)?,
+                Op::Or(n) => writeln!(f, "{:04} OR {}", i, n)?,
+                Op::Call(c) => {
+                    let op = if c.null_safe {
+                        "NULL_SAFE_CALL"
+                    } else {
+                        "CALL"
+                    };
+                    writeln!(f, "{:04} {} {} {}", i, op, c.name.value, c.argc())?;
+                    for arg in &c.args {
+                        for line in arg.to_string().lines() {
+                            writeln!(f, "     | {}", line)?;
+                        }
+                    }
+                }
+                Op::Jump(to) => writeln!(f, "{:04} JUMP {:04}", i, to)?,
+                Op::JumpIfFalse(to, _) => writeln!(f, "{:04} JUMP_IF_FALSE {:04}", i, to)?,
+                Op::Assert(_) => writeln!(f, "{:04} ASSERT", i)?,
+                Op::Defer(_) => writeln!(f, "{:04} DEFER", i)?,
+                Op::Yield => writeln!(f, "{:04} YIELD", i)?,
+                Op::Scoped(body) => writeln!(f, "{:04} SCOPED {}", i, body.code.len())?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Compiles a list of statements into a chunk that leaves the value of the last
+/// statement on the stack, or `()` if there are none.
+pub fn compile(statements: &[Statement]) -> Shared<Chunk> {
+    compile_expression(&ir::lower(statements))
+}
+
+/// Compiles a single expression into its own chunk.
+fn compile_expression(expr: &Expr) -> Shared<Chunk> {
+    let mut chunk = Chunk::default();
+    compile_expr(&mut chunk, expr);
+
+    Shared::new(chunk)
+}
+
+fn compile_expr(chunk: &mut Chunk, expr: &Expr) {
+    match expr {
+        Expr::Constant(p) => chunk.code.push(Op::Constant(p.clone())),
+        Expr::Load(i) => chunk.code.push(Op::Load(i.clone())),
+        Expr::Store(name, value) => {
+            compile_expr(chunk, value);
+            chunk.code.push(Op::Store(name.clone()));
+        }
+        Expr::Operator(kind, args, loc) => {
+            for arg in args {
+                compile_expr(chunk, arg);
+            }
+            chunk
+                .code
+                .push(Op::Operator(kind.clone(), args.len(), loc.clone()));
+        }
+        Expr::Function(f) => chunk.code.push(Op::Function(f.clone())),
+        Expr::Call(c) => {
+            let mut args = &c.args[..];
+            if c.null_safe {
+                compile_expr(chunk, &args[0]);
+                args = &args[1..];
+            }
+
+            chunk.code.push(Op::Call(Box::new(CallOp {
+                name: c.name.clone(),
+                args: args.iter().map(compile_expression).collect(),
+                unit: c.unit,
+                null_safe: c.null_safe,
+                loc: c.loc.clone(),
+            })));
+        }
+        Expr::List(items) => {
+            for item in items {
+                compile_expr(chunk, item);
+            }
+            chunk.code.push(Op::List(items.len()));
+        }
+        Expr::Logic(kind, args) => {
+            for arg in args {
+                compile_expr(chunk, arg);
+            }
+            chunk.code.push(match kind {
+                LogicKind::And => Op::And(args.len()),
+                LogicKind::Or => Op::Or(args.len()),
+            });
+        }
+        Expr::Cond(c) => {
+            compile_expr(chunk, &c.condition);
+            let branch = chunk.code.len();
+            chunk.code.push(Op::JumpIfFalse(0, c.loc.clone()));
+
+            compile_expr(chunk, &c.then);
+            let jump = chunk.code.len();
+            chunk.code.push(Op::Jump(0));
+
+            chunk.code[branch] = Op::JumpIfFalse(chunk.code.len(), c.loc.clone());
+            compile_expr(chunk, &c.otherwise);
+            chunk.code[jump] = Op::Jump(chunk.code.len());
+        }
+        Expr::Assert(a) => {
+            compile_expr(chunk, &a.condition);
+            chunk.code.push(Op::Assert(Box::new(AssertOp {
+                message: a.message.as_ref().map(compile_expression),
+                loc: a.loc.clone(),
+            })));
+        }
+        Expr::Defer(body) => chunk.code.push(Op::Defer(body.clone())),
+        Expr::Yield(value) => {
+            compile_expr(chunk, value);
+            chunk.code.push(Op::Yield);
+        }
+        Expr::Scoped(body) => chunk.code.push(Op::Scoped(compile(body))),
+        Expr::Block(exprs) => {
+            for (i, expr) in exprs.iter().enumerate() {
+                if i > 0 {
+                    chunk.code.push(Op::Pop);
+                }
+                compile_expr(chunk, expr);
+            }
+
+            if exprs.is_empty() {
+                chunk.code.push(Op::Constant(Primitive::Null));
+            }
+        }
+    }
+}
@@ -1,34 +1,472 @@
+use crate::lexer::token::{LineIndex, Location, Span, Token, TokenValue};
 use std::{
     error,
     fmt::{Display, Formatter, Result},
-    num::{ParseFloatError, ParseIntError},
+    io,
 };
 
 #[derive(Debug)]
-pub struct Error(String);
+pub enum Error {
+    /// The source contains something that isn't a valid token.
+    Lex {
+        message: String,
+        loc: Location,
+    },
+    /// The tokens don't make up a valid program. `expected` describes what should
+    /// have been there instead of `found`, if there is a single option, and `help`
+    /// suggests what was meant.
+    Parse {
+        expected: Option<String>,
+        found: TokenValue,
+        loc: Location,
+        help: Option<Box<str>>,
+    },
+    /// Expressions or blocks are nested more deeply than the parser allows.
+    TooDeep {
+        loc: Location,
+    },
+    /// The program uses a construct or name that the parser's dialect doesn't
+    /// allow.
+    Dialect {
+        message: String,
+        loc: Location,
+    },
+    /// The program uses syntax that still works but will change, which is only
+    /// reported as a warning.
+    Deprecated {
+        message: String,
+        loc: Location,
+    },
+    /// Evaluating the program failed. The trace lists the calls that the error
+    /// propagated out of.
+    Runtime {
+        kind: RuntimeErrorKind,
+        loc: Option<Location>,
+        trace: Trace,
+        help: Option<Box<str>>,
+    },
+    Io(io::Error),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeErrorKind {
+    UndefinedVariable(String),
+    UndefinedFunction(String),
+    /// A value of the given type was called as a function.
+    NotCallable(String),
+    /// An `assert` statement failed, with its message if it had one.
+    AssertionFailed(Option<String>),
+    FuelExhausted,
+    TimedOut,
+    MemoryLimitExceeded,
+    /// Function calls were nested more deeply than the runtime allows.
+    CallDepthExceeded,
+    /// A builtin was called without the capability it needs.
+    NotAllowed {
+        function: String,
+        capability: String,
+    },
+    /// Any other error, such as an operator or builtin getting the wrong types.
+    Other(String),
+}
 
 impl Error {
+    /// Creates a runtime error with a message.
     pub fn new(msg: &str) -> Self {
-        Self(String::from(msg))
+        Self::runtime(RuntimeErrorKind::Other(String::from(msg)))
+    }
+
+    pub fn runtime(kind: RuntimeErrorKind) -> Self {
+        Self::Runtime {
+            kind,
+            loc: None,
+            trace: Trace::default(),
+            help: None,
+        }
+    }
+
+    /// Creates the error for a token that wasn't expected.
+    pub fn unexpected(token: &Token) -> Self {
+        Self::Parse {
+            expected: None,
+            found: token.value.clone(),
+            loc: token.loc.clone(),
+            help: None,
+        }
+    }
+
+    /// Creates the error for finding `token` where `expected` should have been.
+    pub fn expected(expected: &str, token: &Token) -> Self {
+        Self::Parse {
+            expected: Some(expected.to_string()),
+            found: token.value.clone(),
+            loc: token.loc.clone(),
+            help: None,
+        }
+    }
+
+    /// Sets where a runtime error was raised, unless it already has a location
+    /// from somewhere more specific.
+    pub fn at(mut self, at: &Location) -> Self {
+        if let Self::Runtime {
+            loc: loc @ None, ..
+        } = &mut self
+        {
+            *loc = Some(at.clone());
+        }
+        self
+    }
+
+    /// Suggests what `name` was meant to be, if it looks like a misspelling of one
+    /// of `candidates`. Only parse and runtime errors can have a suggestion.
+    pub fn did_you_mean<S: AsRef<str>>(
+        mut self,
+        name: &str,
+        candidates: impl IntoIterator<Item = S>,
+    ) -> Self {
+        if let Self::Parse { help, .. } | Self::Runtime { help, .. } = &mut self {
+            if let Some(similar) = suggest(name, candidates) {
+                *help = Some(format!("did you mean {}?", similar).into());
+            }
+        }
+        self
+    }
+
+    /// Adds a call to a runtime error's stack trace as it propagates out of the
+    /// function `name`, which was called at `loc`.
+    pub fn in_function(mut self, name: impl Display, loc: &Location) -> Self {
+        if let Self::Runtime { trace, .. } = &mut self {
+            trace.push(format!(
+                "in function `{}` called at line {}",
+                name,
+                loc.line_start + 1
+            ));
+        }
+        self
+    }
+
+    /// Adds a call to a function value to a runtime error's stack trace, for
+    /// calls made by builtins, which don't have a name for the function. The
+    /// function is shown by where it's defined instead.
+    pub fn in_callback(mut self, defined: &Span) -> Self {
+        if let Self::Runtime { trace, .. } = &mut self {
+            trace.push(format!(
+                "in function defined at line {}",
+                defined.line_start + 1
+            ));
+        }
+        self
+    }
+
+    /// Places an error from the builtin `name`, which was called at `loc`. Errors
+    /// the builtin raised itself are shown at the call, while ones from a
+    /// function it called already have a location, so the call is added to
+    /// their stack trace instead.
+    pub fn in_builtin(self, name: &str, loc: &Location) -> Self {
+        match self.loc() {
+            Some(_) => self.in_function(name, loc),
+            None => self.at(loc),
+        }
+    }
+
+    /// Turns an error from code run by the `eval` builtin into a runtime error
+    /// without a location, so that it's shown where `eval` was called rather than
+    /// at the same place in the file. Where it happened in the code is kept in
+    /// the stack trace instead.
+    pub fn in_eval(self) -> Self {
+        let loc = self.loc().cloned();
+        let (kind, mut trace, help) = match self {
+            Self::Runtime {
+                kind, trace, help, ..
+            } => (kind, trace, help),
+            Self::Io(e) => return Self::Io(e),
+            e => (
+                RuntimeErrorKind::Other(Message(&e).to_string()),
+                Trace::default(),
+                None,
+            ),
+        };
+
+        if let Some(loc) = loc {
+            trace.push(format!(
+                "in code run by `eval`, at line {} column {}",
+                loc.line_start + 1,
+                loc.col_start + 1
+            ));
+        }
+        Self::Runtime {
+            kind,
+            loc: None,
+            trace,
+            help,
+        }
+    }
+}
+
+impl Error {
+    /// Gets where the error happened, if it's known.
+    pub fn loc(&self) -> Option<&Location> {
+        match self {
+            Self::Lex { loc, .. }
+            | Self::Parse { loc, .. }
+            | Self::TooDeep { loc }
+            | Self::Dialect { loc, .. }
+            | Self::Deprecated { loc, .. } => Some(loc),
+            Self::Runtime { loc, .. } => loc.as_ref(),
+            Self::Io(_) => None,
+        }
+    }
+
+    /// Renders the error for showing to users, with the line of `source` where it
+    /// happened and the location underlined. `file` is the name shown for the
+    /// source.
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let level = match self {
+            Self::Deprecated { .. } => "warning",
+            _ => "error",
+        };
+        let mut out = format!("{}: {}", level, Message(self));
+        let index = LineIndex::new(source);
+        let line = self.loc().and_then(|loc| {
+            let (line, col) = index.line_col(loc.start as usize);
+            Some((loc, line, col, index.line_text(line)?))
+        });
+
+        if let Some((loc, line, col, text)) = line {
+            let number = (line + 1).to_string();
+            let gutter = " ".repeat(number.len());
+            // keep tabs so the carets line up with the source
+            let indent: String = text
+                .chars()
+                .take(col)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let width = source.get(loc.range()).map_or(1, |s| s.chars().count());
+            let width = width.min(text.chars().count().saturating_sub(col)).max(1);
+
+            out.push_str(&format!(
+                "\n{gutter}--> {}:{}:{}\n{gutter} |\n{} | {}\n{gutter} | {}{}",
+                file,
+                number,
+                col + 1,
+                number,
+                text,
+                indent,
+                "^".repeat(width),
+            ));
+        }
+
+        if let Self::Runtime { trace, .. } = self {
+            out.push_str(&trace.to_string());
+        }
+
+        out
+    }
+}
+
+/// How many different calls a stack trace shows before it only counts them.
+const MAX_TRACE: usize = 32;
+
+/// The calls a runtime error propagated out of, innermost first. A call that is
+/// repeated several times in a row, as in recursion, is kept once with a count,
+/// and only the first [`MAX_TRACE`] calls are kept.
+#[derive(Debug, Default)]
+pub struct Trace(Vec<Frame>);
+
+#[derive(Debug)]
+enum Frame {
+    Call(String, usize),
+    /// How many calls there were after the ones that are kept.
+    Omitted(usize),
+}
+
+impl Trace {
+    pub fn push(&mut self, call: String) {
+        let len = self.0.len();
+        match self.0.last_mut() {
+            Some(Frame::Call(last, count)) if *last == call => *count += 1,
+            Some(Frame::Omitted(omitted)) => *omitted += 1,
+            _ if len >= MAX_TRACE => self.0.push(Frame::Omitted(1)),
+            _ => self.0.push(Frame::Call(call, 1)),
+        }
+    }
+}
+
+impl Display for Trace {
+    /// Shows each call on its own indented line, starting with a newline.
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for frame in &self.0 {
+            match frame {
+                Frame::Call(call, count) => {
+                    write!(f, "\n    {}", call)?;
+                    match count {
+                        1 => {}
+                        2 => f.write_str("\n    ... repeated 1 more time")?,
+                        _ => write!(f, "\n    ... repeated {} more times", count - 1)?,
+                    }
+                }
+                Frame::Omitted(omitted) => write!(f, "\n    ... and {} more calls", omitted)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Displays an error's message without its location or stack trace.
+struct Message<'a>(&'a Error);
+
+impl Display for Message<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.0 {
+            Error::Lex { message, .. } => f.write_str(message)?,
+            Error::Parse {
+                expected, found, ..
+            } => match (expected, found) {
+                (Some(expected), found) => write!(f, "expected {}; got {}", expected, found)?,
+                (None, TokenValue::EOF) => f.write_str("unexpected end of file")?,
+                (None, found) => write!(f, "unexpected token {}", found)?,
+            },
+            Error::TooDeep { .. } => f.write_str("expression too deeply nested")?,
+            Error::Dialect { message, .. } | Error::Deprecated { message, .. } => {
+                f.write_str(message)?
+            }
+            Error::Runtime { kind, .. } => kind.fmt(f)?,
+            Error::Io(e) => e.fmt(f)?,
+        }
+
+        match self.0 {
+            Error::Parse {
+                help: Some(help), ..
+            }
+            | Error::Runtime {
+                help: Some(help), ..
+            } => write!(f, "; {}", help),
+            _ => Ok(()),
+        }
     }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        f.write_str(&self.0)
+        Message(self).fmt(f)?;
+        if let Some(loc) = self.loc() {
+            write_loc(f, loc)?;
+        }
+        if let Self::Runtime { trace, .. } = self {
+            trace.fmt(f)?;
+        }
+
+        Ok(())
     }
 }
 
-impl error::Error for Error {}
+fn write_loc(f: &mut Formatter<'_>, loc: &Location) -> Result {
+    write!(
+        f,
+        " (at line {}, column {})",
+        loc.line_start + 1,
+        loc.col_start + 1
+    )
+}
 
-impl From<ParseFloatError> for Error {
-    fn from(value: ParseFloatError) -> Self {
-        Self::new(&value.to_string())
+impl Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::UndefinedVariable(name) => write!(f, "undefined variable {}", name),
+            Self::UndefinedFunction(name) => write!(f, "undefined function variable {}", name),
+            Self::NotCallable(ty) => write!(f, "cannot call type {} as a function", ty),
+            Self::AssertionFailed(Some(message)) => write!(f, "assertion failed: {}", message),
+            Self::AssertionFailed(None) => write!(f, "assertion failed"),
+            Self::FuelExhausted => write!(f, "fuel exhausted"),
+            Self::TimedOut => write!(f, "evaluation timed out"),
+            Self::MemoryLimitExceeded => write!(f, "memory limit exceeded"),
+            Self::CallDepthExceeded => write!(f, "call depth limit exceeded"),
+            Self::NotAllowed {
+                function,
+                capability,
+            } => write!(
+                f,
+                "function {} is not allowed without the {} capability",
+                function, capability
+            ),
+            Self::Other(message) => f.write_str(message),
+        }
     }
 }
 
-impl From<ParseIntError> for Error {
-    fn from(value: ParseIntError) -> Self {
-        Self::new(&value.to_string())
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
     }
 }
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Finds the candidate closest to `name` by edit distance, if any is close enough
+/// that `name` is likely a misspelling of it: within one edit for short names, and
+/// a third of the name's length for longer ones. Names of a single character
+/// don't get suggestions, since any other single character is one edit away.
+pub fn suggest<S: AsRef<str>>(
+    name: &str,
+    candidates: impl IntoIterator<Item = S>,
+) -> Option<String> {
+    let len = name.chars().count();
+    let max = (len / 3).max(1).min(len.saturating_sub(1));
+    let mut best: Option<(usize, String)> = None;
+
+    for candidate in candidates {
+        let candidate = candidate.as_ref();
+        if candidate == name {
+            continue;
+        }
+
+        let distance = distance(name, candidate);
+        // ties go to the first name alphabetically, since candidates often come
+        // from a hash set
+        let better = best
+            .as_ref()
+            .is_none_or(|(d, best)| (distance, candidate) < (*d, best.as_str()));
+        if distance <= max && better {
+            best = Some((distance, candidate.to_string()));
+        }
+    }
+
+    best.map(|(_, candidate)| candidate)
+}
+
+/// The number of characters that have to be inserted, removed, replaced or
+/// swapped with the one next to them to turn `a` into `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // d[i][j] is the distance between the first i characters of a and j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
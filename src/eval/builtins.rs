@@ -0,0 +1,2417 @@
+#[cfg(feature = "decimal")]
+use super::decimal;
+use super::{
+    capabilities::Capability,
+    compare::Compare,
+    csv,
+    datetime::DateTime,
+    integer::{self, IntOp},
+    iter::Iter,
+    runtime::Runtime,
+    value::Value,
+    Scope,
+};
+use crate::{
+    error::{Error, RuntimeErrorKind},
+    lexer::Lexer,
+    parser::{ast::Primitive, pragma::Pragmas, Parser},
+    sync::{Lock, Shared},
+};
+#[cfg(feature = "decimal")]
+use rust_decimal::{Decimal, RoundingStrategy};
+#[cfg(feature = "crypto")]
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::BTreeMap,
+    env,
+    f64::consts,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::ErrorKind,
+    process::{self, Command},
+    time::Duration,
+};
+
+pub type BuiltinFn = fn(Vec<Value>, &mut Scope) -> Result<Value, Error>;
+
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: &'static str,
+    func: BuiltinFn,
+}
+
+impl Builtin {
+    pub const fn new(name: &'static str, func: BuiltinFn) -> Self {
+        Self { name, func }
+    }
+
+    pub fn call(&self, args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+        (self.func)(args, scope)
+    }
+}
+
+impl Debug for Builtin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin::new("print", print),
+    Builtin::new("println", println),
+    Builtin::new("format", format),
+    Builtin::new("input", input),
+    Builtin::new("read_lines", read_lines),
+    Builtin::new("is_nan", is_nan),
+    Builtin::new("is_finite", is_finite),
+    Builtin::new("doc", doc),
+    Builtin::new("eval", eval),
+    Builtin::new("type", type_of),
+    Builtin::new("abs", abs),
+    Builtin::new("sqrt", sqrt),
+    Builtin::new("floor", floor),
+    Builtin::new("ceil", ceil),
+    Builtin::new("round", round),
+    Builtin::new("pow", pow),
+    Builtin::new("min", min),
+    Builtin::new("max", max),
+    Builtin::new("sin", sin),
+    Builtin::new("cos", cos),
+    Builtin::new("tan", tan),
+    Builtin::new("log", log),
+    Builtin::new("ln", ln),
+    Builtin::new("exp", exp),
+    Builtin::new("split", split),
+    Builtin::new("join", join),
+    Builtin::new("chars", chars),
+    Builtin::new("ord", ord),
+    Builtin::new("chr", chr),
+    Builtin::new("map", map),
+    Builtin::new("filter", filter),
+    Builtin::new("reduce", reduce),
+    Builtin::new("next", next),
+    Builtin::new("collect", collect),
+    Builtin::new("zip", zip),
+    Builtin::new("enumerate", enumerate),
+    Builtin::new("sort", sort),
+    Builtin::new("reverse", reverse),
+    Builtin::new("sum", sum),
+    Builtin::new("min_of", min_of),
+    Builtin::new("max_of", max_of),
+    Builtin::new("avg", avg),
+    Builtin::new("to_int", to_int),
+    Builtin::new("to_float", to_float),
+    Builtin::new("to_string", to_string),
+    Builtin::new("round_to", round_to),
+    Builtin::new("to_fixed", to_fixed),
+    Builtin::new("to_hex", to_hex),
+    Builtin::new("to_bin", to_bin),
+    Builtin::new("parse_bool", parse_bool),
+    Builtin::new("read_file", read_file),
+    Builtin::new("read_file_bytes", read_file_bytes),
+    Builtin::new("write_file", write_file),
+    Builtin::new("append_file", append_file),
+    Builtin::new("list_dir", list_dir),
+    Builtin::new("exists", exists),
+    Builtin::new("is_dir", is_dir),
+    Builtin::new("mkdir", mkdir),
+    Builtin::new("remove", remove),
+    Builtin::new("copy", copy),
+    Builtin::new("random", random),
+    Builtin::new("rand_int", rand_int),
+    Builtin::new("shuffle", shuffle),
+    Builtin::new("sleep", sleep),
+    Builtin::new("cwd", cwd),
+    Builtin::new("pid", pid),
+    Builtin::new("hostname", hostname),
+    Builtin::new("now", now),
+    Builtin::new("parse_time", parse_time),
+    Builtin::new("format_time", format_time),
+    Builtin::new("add_duration", add_duration),
+    Builtin::new("diff", diff),
+    Builtin::new("timestamp", timestamp),
+    Builtin::new("from_timestamp", from_timestamp),
+    Builtin::new("csv_parse", csv_parse),
+    Builtin::new("csv_write", csv_write),
+    Builtin::new("ok", ok),
+    Builtin::new("err", err),
+    Builtin::new("is_ok", is_ok),
+    Builtin::new("unwrap", unwrap),
+    Builtin::new("unwrap_or", unwrap_or),
+    Builtin::new("exec", exec),
+    Builtin::new("get", get),
+    Builtin::new("slice", slice),
+    Builtin::new("len", len),
+    Builtin::new("to_bytes", to_bytes),
+    Builtin::new("from_bytes", from_bytes),
+    Builtin::new("from_hex", from_hex),
+    #[cfg(feature = "crypto")]
+    Builtin::new("sha256", sha256),
+    #[cfg(feature = "crypto")]
+    Builtin::new("md5", md5),
+    #[cfg(feature = "http")]
+    Builtin::new("http_get", http_get),
+    #[cfg(feature = "http")]
+    Builtin::new("http_post", http_post),
+    #[cfg(feature = "uuid")]
+    Builtin::new("uuid", uuid),
+    #[cfg(feature = "toml")]
+    Builtin::new("toml_parse", toml_parse),
+    #[cfg(feature = "yaml")]
+    Builtin::new("yaml_parse", yaml_parse),
+    #[cfg(feature = "decimal")]
+    Builtin::new("to_decimal", to_decimal),
+];
+
+pub const CONSTANTS: &[(&str, Primitive)] = &[
+    ("PI", Primitive::Float(consts::PI)),
+    ("E", Primitive::Float(consts::E)),
+];
+
+/// Checks that the builtin `name` is allowed to have the side effect.
+fn require(scope: &Scope, name: &str, capability: Capability) -> Result<(), Error> {
+    if scope.runtime().borrow().capabilities.allows(capability) {
+        Ok(())
+    } else {
+        Err(Error::runtime(RuntimeErrorKind::NotAllowed {
+            function: name.to_string(),
+            capability: capability.to_string(),
+        }))
+    }
+}
+
+/// Checks that a builtin was called with exactly `count` arguments. Builtins that
+/// take no arguments can also be called with `()`, the same as functions.
+fn expect_args(name: &str, args: &[Value], count: usize) -> Result<(), Error> {
+    if count == 0 && args.len() == 1 && args[0] == Value::Primitive(Primitive::Null) {
+        return Ok(());
+    }
+
+    if args.len() != count {
+        return Err(Error::new(&format!(
+            "expected {} arguments to function {}",
+            count, name
+        )));
+    }
+
+    Ok(())
+}
+
+fn format_args(args: &[Value]) -> String {
+    if let [Value::Primitive(Primitive::Null)] = args {
+        return String::new();
+    }
+
+    args.iter().map(|v| v.value()).collect::<Vec<_>>().join(" ")
+}
+
+fn print(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    write_output(scope, &format_args(&args))
+}
+
+fn println(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    write_output(scope, &format!("{}\n", format_args(&args)))
+}
+
+fn write_output(scope: &mut Scope, text: &str) -> Result<Value, Error> {
+    scope
+        .runtime()
+        .borrow_mut()
+        .write(text)
+        .map_err(|e| Error::new(&e.to_string()))?;
+
+    Ok(Value::Primitive(Primitive::Null))
+}
+
+fn format(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    if args.is_empty() {
+        return Err(Error::new(
+            "expected at least 1 argument to function format",
+        ));
+    }
+    let template = string_arg("format", &args, 0)?;
+
+    let mut res = String::new();
+    let mut values = args[1..].iter();
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('{') if spec.is_empty() => {
+                            res.push('{');
+                            break;
+                        }
+                        Some('}') => {
+                            let Some(value) = values.next() else {
+                                return Err(Error::new(
+                                    "not enough arguments for format string placeholders",
+                                ));
+                            };
+                            res.push_str(&format_value(value, &spec)?);
+                            break;
+                        }
+                        Some(c) => spec.push(c),
+                        None => {
+                            return Err(Error::new("unterminated placeholder in format string"))
+                        }
+                    }
+                }
+            }
+            '}' => match chars.next() {
+                Some('}') => res.push('}'),
+                _ => return Err(Error::new("unmatched '}' in format string")),
+            },
+            c => res.push(c),
+        }
+    }
+
+    if values.next().is_some() {
+        return Err(Error::new(
+            "too many arguments for format string placeholders",
+        ));
+    }
+
+    Ok(Value::Primitive(Primitive::String(res.into())))
+}
+
+/// Formats a single `format` argument using the placeholder spec, which is either
+/// empty or `:.N` to show a number with `N` decimal places.
+fn format_value(value: &Value, spec: &str) -> Result<String, Error> {
+    if spec.is_empty() {
+        return Ok(value.value());
+    }
+
+    let Some(precision) = spec
+        .strip_prefix(":.")
+        .and_then(|p| p.parse::<usize>().ok())
+    else {
+        return Err(Error::new(&format!(
+            "invalid format placeholder {{{}}}",
+            spec
+        )));
+    };
+
+    fixed(value, precision)
+        .ok_or_else(|| Error::new(&format!("cannot format type {} with a precision", value)))
+}
+
+/// Shows a number with exactly `precision` decimal places, or gives `None` if
+/// the value isn't a number. `NaN` and infinities are shown as usual.
+fn fixed(value: &Value, precision: usize) -> Option<String> {
+    match value {
+        Value::Primitive(v) if integer::is_integer(v) => {
+            Some(format!("{:.*}", precision, integer::to_f64(v)))
+        }
+        Value::Primitive(Primitive::Float(v)) if v.is_finite() => {
+            Some(format!("{:.*}", precision, v))
+        }
+        Value::Primitive(Primitive::Float(_)) => Some(value.value()),
+        #[cfg(feature = "decimal")]
+        Value::Primitive(Primitive::Decimal(v)) => Some(format!(
+            "{:.*}",
+            precision,
+            v.round_dp_with_strategy(
+                u32::try_from(precision).unwrap_or(u32::MAX),
+                RoundingStrategy::MidpointAwayFromZero
+            )
+        )),
+        _ => None,
+    }
+}
+
+fn input(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    if args.len() > 1 {
+        return Err(Error::new("expected at most 1 argument to function input"));
+    }
+
+    print(args, scope)?;
+
+    match read_line(scope.runtime())? {
+        Some(line) => Ok(Value::Primitive(Primitive::String(line.into()))),
+        None => Ok(Value::Primitive(Primitive::Null)),
+    }
+}
+
+fn read_lines(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("read_lines", &args, 0)?;
+
+    Ok(Value::Iterator(Iter::lines(scope.runtime().clone())))
+}
+
+/// Reads a line from the runtime input without its line ending, or gives `None`
+/// at the end of the input.
+pub(super) fn read_line(runtime: &Shared<Lock<Runtime>>) -> Result<Option<String>, Error> {
+    let mut runtime = runtime.borrow_mut();
+    runtime.check_deadline()?;
+
+    let mut line = String::new();
+    let read = runtime
+        .read_line(&mut line)
+        .map_err(|e| Error::new(&e.to_string()))?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+
+    Ok(Some(line))
+}
+
+fn is_nan(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("is_nan", &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(Primitive::Float(v)) => {
+            Ok(Value::Primitive(Primitive::Boolean(v.is_nan())))
+        }
+        Value::Primitive(v) if integer::is_integer(v) => {
+            Ok(Value::Primitive(Primitive::Boolean(false)))
+        }
+        v => Err(Error::new(&format!("cannot check type {} for nan", v))),
+    }
+}
+
+fn is_finite(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("is_finite", &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(Primitive::Float(v)) => {
+            Ok(Value::Primitive(Primitive::Boolean(v.is_finite())))
+        }
+        Value::Primitive(v) if integer::is_integer(v) => {
+            Ok(Value::Primitive(Primitive::Boolean(true)))
+        }
+        v => Err(Error::new(&format!(
+            "cannot check type {} for finiteness",
+            v
+        ))),
+    }
+}
+
+fn doc(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("doc", &args, 1)?;
+
+    match &args[0] {
+        Value::Function(f) => Ok(match &f.doc {
+            Some(doc) => Value::Primitive(Primitive::String(doc.clone().into())),
+            None => Value::Primitive(Primitive::Null),
+        }),
+        Value::Builtin(_) | Value::Operator(_) => Ok(Value::Primitive(Primitive::Null)),
+        v => Err(Error::new(&format!(
+            "cannot get documentation of type {}",
+            v
+        ))),
+    }
+}
+
+fn eval(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("eval", &args, 1)?;
+    let code = string_arg("eval", &args, 0)?;
+
+    let program = Parser::from_lexer(Lexer::new(code))
+        .parse()
+        .map_err(Error::in_eval)?;
+    // pragmas limit the whole program, so code run by eval can't set them
+    if program.pragmas != Pragmas::default() {
+        return Err(Error::new("pragmas can't be used in code run by eval"));
+    }
+
+    // the code runs in its own scope, so its variables and deferred blocks don't
+    // outlive it
+    let mut child = scope.child();
+    super::eval_statements(&program.statements, &mut child).map_err(Error::in_eval)
+}
+
+fn type_of(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("type", &args, 1)?;
+
+    Ok(Value::Primitive(Primitive::String(
+        args[0].to_string().into(),
+    )))
+}
+
+fn abs(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("abs", &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(v) if integer::is_integer(v) => Ok(Value::Primitive(integer::abs(v)?)),
+        Value::Primitive(Primitive::Float(v)) => Ok(Value::Primitive(Primitive::Float(v.abs()))),
+        #[cfg(feature = "decimal")]
+        Value::Primitive(Primitive::Decimal(v)) => {
+            Ok(Value::Primitive(Primitive::Decimal(v.abs())))
+        }
+        v => Err(Error::new(&format!(
+            "cannot get absolute value of type {}",
+            v
+        ))),
+    }
+}
+
+fn sqrt(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("sqrt", &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(v) if integer::is_integer(v) => Ok(Value::Primitive(Primitive::Float(
+            integer::to_f64(v).sqrt(),
+        ))),
+        Value::Primitive(Primitive::Float(v)) => Ok(Value::Primitive(Primitive::Float(v.sqrt()))),
+        #[cfg(feature = "decimal")]
+        Value::Primitive(Primitive::Decimal(v)) => Ok(Value::Primitive(Primitive::Float(
+            decimal::to_f64(v).sqrt(),
+        ))),
+        v => Err(Error::new(&format!("cannot get square root of type {}", v))),
+    }
+}
+
+/// How `floor`, `ceil` and `round` round a number to an integer.
+#[derive(Clone, Copy)]
+enum Rounding {
+    Down,
+    Up,
+    /// To the nearest integer, away from zero on halves.
+    Nearest,
+}
+
+impl Rounding {
+    fn float(self, v: f64) -> f64 {
+        match self {
+            Rounding::Down => v.floor(),
+            Rounding::Up => v.ceil(),
+            Rounding::Nearest => v.round(),
+        }
+    }
+
+    #[cfg(feature = "decimal")]
+    fn decimal(self, v: &Decimal) -> Decimal {
+        match self {
+            Rounding::Down => v.floor(),
+            Rounding::Up => v.ceil(),
+            Rounding::Nearest => {
+                v.round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero)
+            }
+        }
+    }
+}
+
+/// Rounds a number to an integer, leaving integers as they are.
+fn to_integer(name: &str, args: Vec<Value>, rounding: Rounding) -> Result<Value, Error> {
+    expect_args(name, &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(v) if integer::is_integer(v) => Ok(Value::Primitive(v.clone())),
+        Value::Primitive(Primitive::Float(v)) => {
+            let res = rounding.float(*v);
+            if !res.is_finite() || res < i64::MIN as f64 || res >= i64::MAX as f64 {
+                return Err(Error::new(&format!("cannot {} {} to an integer", name, v)));
+            }
+
+            Ok(Value::Primitive(Primitive::Integer(res as i64)))
+        }
+        #[cfg(feature = "decimal")]
+        Value::Primitive(Primitive::Decimal(v)) => {
+            match decimal::to_integer(&rounding.decimal(v)) {
+                Some(res) => Ok(Value::Primitive(res)),
+                None => Err(Error::new(&format!("cannot {} {} to an integer", name, v))),
+            }
+        }
+        v => Err(Error::new(&format!("cannot {} type {}", name, v))),
+    }
+}
+
+fn floor(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    to_integer("floor", args, Rounding::Down)
+}
+
+fn ceil(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    to_integer("ceil", args, Rounding::Up)
+}
+
+fn round(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    to_integer("round", args, Rounding::Nearest)
+}
+
+fn pow(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("pow", &args, 2)?;
+
+    match (&args[0], &args[1]) {
+        (Value::Primitive(a), Value::Primitive(b))
+            if integer::is_integer(a) && integer::is_integer(b) =>
+        {
+            if integer::compare(b, &Primitive::Integer(0)).is_lt() {
+                return Ok(Value::Primitive(Primitive::Float(
+                    integer::to_f64(a).powf(integer::to_f64(b)),
+                )));
+            }
+
+            match b {
+                Primitive::Integer(b) if u32::try_from(*b).is_ok() => {
+                    Ok(Value::Primitive(integer::pow(a, *b as u32)?))
+                }
+                _ => Err(Error::new("integer overflow in pow")),
+            }
+        }
+        (Value::Primitive(Primitive::Float(a)), Value::Primitive(Primitive::Float(b))) => {
+            Ok(Value::Primitive(Primitive::Float(a.powf(*b))))
+        }
+        (Value::Primitive(Primitive::Float(a)), Value::Primitive(b)) if integer::is_integer(b) => {
+            Ok(Value::Primitive(Primitive::Float(
+                a.powf(integer::to_f64(b)),
+            )))
+        }
+        #[cfg(feature = "decimal")]
+        (Value::Primitive(Primitive::Decimal(a)), Value::Primitive(b))
+            if integer::is_integer(b) =>
+        {
+            if a.is_zero() && integer::compare(b, &Primitive::Integer(0)).is_lt() {
+                return Err(Error::new("cannot divide by 0"));
+            }
+
+            match integer::to_i64(b).and_then(|b| decimal::pow(*a, b)) {
+                Some(res) => Ok(Value::Primitive(Primitive::Decimal(res))),
+                None => Err(Error::new("decimal overflow in pow")),
+            }
+        }
+        (a, b) => Err(Error::new(&format!(
+            "cannot raise type {} to the power of type {}",
+            a, b
+        ))),
+    }
+}
+
+/// Picks one of the arguments, going through them in order and replacing the
+/// one picked so far if `replace` is true for how it compares to the next. All
+/// the arguments must be numbers of the same type, or external values that can
+/// be compared.
+fn pick_number(
+    name: &str,
+    args: Vec<Value>,
+    replace: fn(Ordering) -> bool,
+) -> Result<Value, Error> {
+    let mut args = args.into_iter();
+    let Some(mut res) = args.next() else {
+        return Err(Error::new(&format!(
+            "expected at least 1 argument to function {}",
+            name
+        )));
+    };
+
+    match &res {
+        Value::Primitive(p) if is_number(p) => {}
+        Value::External(_) => {}
+        v => return Err(Error::new(&format!("cannot get {} of type {}", name, v))),
+    }
+
+    for arg in args {
+        if res.compare(&arg)?.is_some_and(replace) {
+            res = arg;
+        }
+    }
+
+    Ok(res)
+}
+
+fn is_number(value: &Primitive) -> bool {
+    match value {
+        Primitive::Float(_) => true,
+        #[cfg(feature = "decimal")]
+        Primitive::Decimal(_) => true,
+        v => integer::is_integer(v),
+    }
+}
+
+/// Checks if both values are integers or both are floats.
+fn same_number_type(a: &Primitive, b: &Primitive) -> bool {
+    match (a, b) {
+        (Primitive::Float(_), Primitive::Float(_)) => true,
+        (a, b) => integer::is_integer(a) && integer::is_integer(b),
+    }
+}
+
+fn min(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    pick_number("min", args, Ordering::is_gt)
+}
+
+fn max(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    pick_number("max", args, Ordering::is_lt)
+}
+
+/// Applies `f` to a single number argument as a float, giving a float.
+fn float_fn(name: &str, args: Vec<Value>, f: fn(f64) -> f64) -> Result<Value, Error> {
+    expect_args(name, &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(v) if integer::is_integer(v) => {
+            Ok(Value::Primitive(Primitive::Float(f(integer::to_f64(v)))))
+        }
+        Value::Primitive(Primitive::Float(v)) => Ok(Value::Primitive(Primitive::Float(f(*v)))),
+        #[cfg(feature = "decimal")]
+        Value::Primitive(Primitive::Decimal(v)) => {
+            Ok(Value::Primitive(Primitive::Float(f(decimal::to_f64(v)))))
+        }
+        v => Err(Error::new(&format!("cannot get {} of type {}", name, v))),
+    }
+}
+
+fn sin(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    float_fn("sin", args, f64::sin)
+}
+
+fn cos(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    float_fn("cos", args, f64::cos)
+}
+
+fn tan(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    float_fn("tan", args, f64::tan)
+}
+
+fn log(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    float_fn("log", args, f64::log10)
+}
+
+fn ln(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    float_fn("ln", args, f64::ln)
+}
+
+fn exp(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    float_fn("exp", args, f64::exp)
+}
+
+fn split(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("split", &args, 2)?;
+
+    match (&args[0], &args[1]) {
+        (Value::Primitive(Primitive::String(s)), Value::Primitive(Primitive::String(sep))) => {
+            if sep.is_empty() {
+                return Err(Error::new("cannot split by an empty string"));
+            }
+
+            Ok(Value::List(
+                s.split(&**sep)
+                    .map(|v| Value::Primitive(Primitive::String(v.into())))
+                    .collect(),
+            ))
+        }
+        (Value::Primitive(Primitive::String(_)), v) => {
+            Err(Error::new(&format!("cannot split by type {}", v)))
+        }
+        (v, _) => Err(Error::new(&format!("cannot split type {}", v))),
+    }
+}
+
+fn join(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("join", &args, 2)?;
+
+    let Value::List(items) = &args[0] else {
+        return Err(Error::new(&format!("cannot join type {}", args[0])));
+    };
+    let Value::Primitive(Primitive::String(sep)) = &args[1] else {
+        return Err(Error::new(&format!("cannot join with type {}", args[1])));
+    };
+
+    let mut res = Vec::new();
+    for item in items {
+        match item {
+            Value::Primitive(Primitive::String(s)) => res.push(&**s),
+            v => return Err(Error::new(&format!("cannot join type {} in a list", v))),
+        }
+    }
+
+    Ok(Value::Primitive(Primitive::String(res.join(sep).into())))
+}
+
+fn chars(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("chars", &args, 1)?;
+    let s = string_arg("chars", &args, 0)?;
+
+    Ok(Value::List(
+        s.chars()
+            .map(|c| Value::Primitive(Primitive::String(c.to_string().into())))
+            .collect(),
+    ))
+}
+
+fn ord(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("ord", &args, 1)?;
+    let s = string_arg("ord", &args, 0)?;
+
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Value::Primitive(Primitive::Integer(c as i64))),
+        _ => Err(Error::new(&format!(
+            "expected a single character for function ord; got {:?}",
+            s
+        ))),
+    }
+}
+
+fn chr(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("chr", &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(v) if integer::is_integer(v) => {
+            let c = integer::to_i64(v)
+                .and_then(|v| u32::try_from(v).ok())
+                .and_then(char::from_u32);
+
+            match c {
+                Some(c) => Ok(Value::Primitive(Primitive::String(c.to_string().into()))),
+                None => Err(Error::new(&format!(
+                    "invalid code point {}",
+                    args[0].value()
+                ))),
+            }
+        }
+        v => Err(Error::new(&format!(
+            "expected type integer for argument 1 of function chr; got {}",
+            v
+        ))),
+    }
+}
+
+/// Gets the items of a list, or the values left in an iterator, for the builtin
+/// that would `verb` them.
+fn items<'a>(value: &'a Value, verb: &str) -> Result<Cow<'a, [Value]>, Error> {
+    match value {
+        Value::List(items) => Ok(Cow::Borrowed(items)),
+        Value::Iterator(it) => Ok(Cow::Owned(it.collect()?)),
+        v => Err(Error::new(&format!("cannot {} type {}", verb, v))),
+    }
+}
+
+fn map(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("map", &args, 2)?;
+
+    let items = items(&args[0], "map")?;
+    let mut res = Vec::new();
+    for item in items.iter() {
+        res.push(args[1].call(vec![item.clone()], scope)?);
+    }
+
+    Ok(Value::List(res))
+}
+
+fn filter(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("filter", &args, 2)?;
+
+    let items = items(&args[0], "filter")?;
+    let mut res = Vec::new();
+    for item in items.iter() {
+        if args[1].call(vec![item.clone()], scope)?.is_truthy()? {
+            res.push(item.clone());
+        }
+    }
+
+    Ok(Value::List(res))
+}
+
+fn reduce(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(Error::new("expected 2 or 3 arguments to function reduce"));
+    }
+
+    let items = items(&args[0], "reduce")?;
+    let mut items = items.iter().cloned();
+    let Some(mut acc) = args.get(2).cloned().or_else(|| items.next()) else {
+        return Err(Error::new(
+            "cannot reduce an empty list without an initial value",
+        ));
+    };
+
+    for item in items {
+        acc = args[1].call(vec![acc, item], scope)?;
+    }
+
+    Ok(acc)
+}
+
+fn next(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("next", &args, 1)?;
+
+    match &args[0] {
+        Value::Iterator(it) => Ok(it.next()?.unwrap_or(Value::Primitive(Primitive::Null))),
+        v => Err(Error::new(&format!(
+            "expected type iterator for argument 1 of function next; got {}",
+            v
+        ))),
+    }
+}
+
+fn collect(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("collect", &args, 1)?;
+
+    Ok(Value::List(items(&args[0], "collect")?.into_owned()))
+}
+
+fn zip(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    if args.len() < 2 {
+        return Err(Error::new("expected at least 2 arguments to function zip"));
+    }
+
+    let mut lists = Vec::new();
+    for arg in &args {
+        match arg {
+            Value::List(items) => lists.push(items),
+            v => return Err(Error::new(&format!("cannot zip type {}", v))),
+        }
+    }
+
+    let len = lists.iter().map(|l| l.len()).min().unwrap_or_default();
+    Ok(Value::List(
+        (0..len)
+            .map(|i| Value::List(lists.iter().map(|l| l[i].clone()).collect()))
+            .collect(),
+    ))
+}
+
+fn enumerate(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("enumerate", &args, 1)?;
+
+    let Value::List(items) = &args[0] else {
+        return Err(Error::new(&format!("cannot enumerate type {}", args[0])));
+    };
+
+    Ok(Value::List(
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                Value::List(vec![
+                    Value::Primitive(Primitive::Integer(i as i64)),
+                    v.clone(),
+                ])
+            })
+            .collect(),
+    ))
+}
+
+fn sort(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(Error::new("expected 1 or 2 arguments to function sort"));
+    }
+
+    let mut before = |a: &Value, b: &Value| match args.get(1) {
+        Some(f) => f.call(vec![a.clone(), b.clone()], scope)?.is_truthy(),
+        None => match (a, b) {
+            (Value::Primitive(Primitive::String(a)), Value::Primitive(Primitive::String(b))) => {
+                Ok(a < b)
+            }
+            (Value::Primitive(Primitive::Boolean(a)), Value::Primitive(Primitive::Boolean(b))) => {
+                Ok(a < b)
+            }
+            _ => match a.compare(b)? {
+                Some(ord) => Ok(ord.is_lt()),
+                None => Err(Error::new(&format!(
+                    "cannot sort {} with {}",
+                    a.value(),
+                    b.value()
+                ))),
+            },
+        },
+    };
+
+    match &args[0] {
+        Value::List(items) => Ok(Value::List(merge_sort(items.clone(), &mut before)?)),
+        Value::Primitive(Primitive::String(s)) => {
+            let chars = s
+                .chars()
+                .map(|c| Value::Primitive(Primitive::String(c.to_string().into())))
+                .collect();
+
+            Ok(Value::Primitive(Primitive::String(
+                merge_sort(chars, &mut before)?
+                    .iter()
+                    .map(|c| c.value())
+                    .collect::<String>()
+                    .into(),
+            )))
+        }
+        v => Err(Error::new(&format!("cannot sort type {}", v))),
+    }
+}
+
+/// Stable merge sort where `before` checks if the first value should come
+/// before the second. Unlike `slice::sort_by`, the comparison can fail and
+/// doesn't need to be a total order.
+fn merge_sort(
+    mut items: Vec<Value>,
+    before: &mut dyn FnMut(&Value, &Value) -> Result<bool, Error>,
+) -> Result<Vec<Value>, Error> {
+    if items.len() < 2 {
+        return Ok(items);
+    }
+
+    let right = merge_sort(items.split_off(items.len() / 2), before)?;
+    let left = merge_sort(items, before)?;
+
+    let mut res = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if before(r, l)? {
+            res.extend(right.next());
+        } else {
+            res.extend(left.next());
+        }
+    }
+    res.extend(left);
+    res.extend(right);
+
+    Ok(res)
+}
+
+fn reverse(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("reverse", &args, 1)?;
+
+    match &args[0] {
+        Value::List(items) => Ok(Value::List(items.iter().rev().cloned().collect())),
+        Value::Primitive(Primitive::String(s)) => Ok(Value::Primitive(Primitive::String(
+            s.chars().rev().collect::<String>().into(),
+        ))),
+        v => Err(Error::new(&format!("cannot reverse type {}", v))),
+    }
+}
+
+/// Gets the single list argument of an aggregate builtin, which must not be empty.
+fn aggregate_arg<'a>(name: &str, args: &'a [Value]) -> Result<&'a Vec<Value>, Error> {
+    expect_args(name, args, 1)?;
+
+    match &args[0] {
+        Value::List(items) if items.is_empty() => {
+            Err(Error::new(&format!("cannot get {} of an empty list", name)))
+        }
+        Value::List(items) => Ok(items),
+        v => Err(Error::new(&format!("cannot get {} of type {}", name, v))),
+    }
+}
+
+fn sum_values(name: &str, items: &[Value]) -> Result<Primitive, Error> {
+    let mut res = match &items[0] {
+        Value::Primitive(p) if is_number(p) => p.clone(),
+        v => {
+            return Err(Error::new(&format!(
+                "cannot get {} of type {} in a list",
+                name, v
+            )))
+        }
+    };
+
+    for item in items.iter().skip(1) {
+        res = match (&res, item) {
+            (Primitive::Float(a), Value::Primitive(Primitive::Float(b))) => Primitive::Float(a + b),
+            #[cfg(feature = "decimal")]
+            (Primitive::Decimal(a), Value::Primitive(Primitive::Decimal(b))) => Primitive::Decimal(
+                a.checked_add(*b)
+                    .ok_or_else(|| Error::new(&format!("decimal overflow in {}", name)))?,
+            ),
+            (a, Value::Primitive(b)) if same_number_type(a, b) => integer::apply(IntOp::Add, a, b)
+                .map_err(|_| Error::new(&format!("integer overflow in {}", name)))?,
+            _ => {
+                return Err(Error::new(&format!(
+                    "cannot add type {} with type {} in a list",
+                    res, item
+                )))
+            }
+        };
+    }
+
+    Ok(res)
+}
+
+fn sum(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("sum", &args, 1)?;
+
+    match &args[0] {
+        Value::List(items) if items.is_empty() => Ok(Value::Primitive(Primitive::Integer(0))),
+        Value::List(items) => Ok(Value::Primitive(sum_values("sum", items)?)),
+        v => Err(Error::new(&format!("cannot get sum of type {}", v))),
+    }
+}
+
+fn min_of(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    let items = aggregate_arg("min_of", &args)?;
+
+    pick_number("min_of", items.clone(), Ordering::is_gt)
+}
+
+fn max_of(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    let items = aggregate_arg("max_of", &args)?;
+
+    pick_number("max_of", items.clone(), Ordering::is_lt)
+}
+
+fn avg(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    let items = aggregate_arg("avg", &args)?;
+
+    let total = match sum_values("avg", items)? {
+        Primitive::Float(v) => v,
+        // the average of decimals is kept exact
+        #[cfg(feature = "decimal")]
+        Primitive::Decimal(v) => {
+            return Ok(Value::Primitive(Primitive::Decimal(
+                (v / Decimal::from(items.len())).normalize(),
+            )))
+        }
+        v => integer::to_f64(&v),
+    };
+
+    Ok(Value::Primitive(Primitive::Float(
+        total / items.len() as f64,
+    )))
+}
+
+fn to_int(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("to_int", &args, 1)?;
+
+    Ok(Value::Primitive(match &args[0] {
+        Value::Primitive(v) if integer::is_integer(v) => v.clone(),
+        Value::Primitive(Primitive::Float(v)) => {
+            if !v.is_finite() || *v < i64::MIN as f64 || *v >= i64::MAX as f64 {
+                Primitive::Null
+            } else {
+                Primitive::Integer(*v as i64)
+            }
+        }
+        #[cfg(feature = "decimal")]
+        Value::Primitive(Primitive::Decimal(v)) => {
+            decimal::to_integer(v).unwrap_or(Primitive::Null)
+        }
+        Value::Primitive(Primitive::String(v)) => match v.trim().parse() {
+            Ok(v) => Primitive::Integer(v),
+            Err(_) => Primitive::Null,
+        },
+        Value::Primitive(Primitive::Boolean(v)) => Primitive::Integer(*v as i64),
+        v => return Err(Error::new(&format!("cannot convert type {} to integer", v))),
+    }))
+}
+
+fn to_float(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("to_float", &args, 1)?;
+
+    Ok(Value::Primitive(match &args[0] {
+        Value::Primitive(v) if integer::is_integer(v) => Primitive::Float(integer::to_f64(v)),
+        Value::Primitive(Primitive::Float(v)) => Primitive::Float(*v),
+        #[cfg(feature = "decimal")]
+        Value::Primitive(Primitive::Decimal(v)) => Primitive::Float(decimal::to_f64(v)),
+        Value::Primitive(Primitive::String(v)) => match v.trim().parse() {
+            Ok(v) => Primitive::Float(v),
+            Err(_) => Primitive::Null,
+        },
+        v => return Err(Error::new(&format!("cannot convert type {} to float", v))),
+    }))
+}
+
+#[cfg(feature = "decimal")]
+fn to_decimal(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("to_decimal", &args, 1)?;
+
+    Ok(Value::Primitive(match &args[0] {
+        Value::Primitive(Primitive::Decimal(v)) => Primitive::Decimal(*v),
+        Value::Primitive(Primitive::Float(v)) => {
+            rust_decimal::Decimal::try_from(*v).map_or(Primitive::Null, Primitive::Decimal)
+        }
+        Value::Primitive(v) if integer::is_integer(v) => match args[0].value().parse() {
+            Ok(v) => Primitive::Decimal(v),
+            Err(_) => Primitive::Null,
+        },
+        Value::Primitive(Primitive::String(v)) => match v.trim().parse() {
+            Ok(v) => Primitive::Decimal(v),
+            Err(_) => Primitive::Null,
+        },
+        v => return Err(Error::new(&format!("cannot convert type {} to decimal", v))),
+    }))
+}
+
+fn to_string(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("to_string", &args, 1)?;
+
+    match &args[0] {
+        v @ (Value::Primitive(_) | Value::List(_) | Value::DateTime(_)) => {
+            Ok(Value::Primitive(Primitive::String(v.value().into())))
+        }
+        v => Err(Error::new(&format!("cannot convert type {} to string", v))),
+    }
+}
+
+/// Gets a non-negative integer argument giving a number of decimal places.
+fn digits_arg(name: &str, args: &[Value], index: usize) -> Result<usize, Error> {
+    match &args[index] {
+        Value::Primitive(v) if integer::is_integer(v) => integer::to_i64(v)
+            .and_then(|v| usize::try_from(v).ok())
+            .ok_or_else(|| {
+                Error::new(&format!(
+                    "cannot use {} decimal places in function {}",
+                    args[index].value(),
+                    name
+                ))
+            }),
+        v => Err(Error::new(&format!(
+            "expected type integer for argument {} of function {}; got {}",
+            index + 1,
+            name,
+            v
+        ))),
+    }
+}
+
+fn round_to(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("round_to", &args, 2)?;
+    let digits = digits_arg("round_to", &args, 1)?;
+
+    match &args[0] {
+        v @ Value::Primitive(p) if integer::is_integer(p) => Ok(v.clone()),
+        Value::Primitive(Primitive::Float(v)) if v.is_finite() => {
+            // rounding the decimal representation avoids the noise of scaling by 10^n
+            let res = format!("{:.*}", digits, v).parse::<f64>().unwrap_or(*v);
+
+            Ok(Value::Primitive(Primitive::Float(res)))
+        }
+        v @ Value::Primitive(Primitive::Float(_)) => Ok(v.clone()),
+        #[cfg(feature = "decimal")]
+        Value::Primitive(Primitive::Decimal(v)) => Ok(Value::Primitive(Primitive::Decimal(
+            v.round_dp_with_strategy(
+                u32::try_from(digits).unwrap_or(u32::MAX),
+                RoundingStrategy::MidpointAwayFromZero,
+            ),
+        ))),
+        v => Err(Error::new(&format!("cannot round type {}", v))),
+    }
+}
+
+fn to_fixed(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("to_fixed", &args, 2)?;
+    let digits = digits_arg("to_fixed", &args, 1)?;
+
+    match fixed(&args[0], digits) {
+        Some(s) => Ok(Value::Primitive(Primitive::String(s.into()))),
+        None => Err(Error::new(&format!(
+            "cannot convert type {} to fixed point",
+            args[0]
+        ))),
+    }
+}
+
+fn to_radix(name: &str, args: Vec<Value>, radix: u32) -> Result<Value, Error> {
+    expect_args(name, &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(v) if integer::is_integer(v) => Ok(Value::Primitive(Primitive::String(
+            integer::to_str_radix(v, radix).into(),
+        ))),
+        v => Err(Error::new(&format!("cannot get {} of type {}", name, v))),
+    }
+}
+
+fn to_hex(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    if let [Value::Bytes(bytes)] = args.as_slice() {
+        return Ok(Value::Primitive(Primitive::String(
+            bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+                .into(),
+        )));
+    }
+
+    to_radix("to_hex", args, 16)
+}
+
+fn to_bin(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    to_radix("to_bin", args, 2)
+}
+
+fn parse_bool(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("parse_bool", &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(Primitive::String(v)) => Ok(Value::Primitive(match v.trim() {
+            "true" => Primitive::Boolean(true),
+            "false" => Primitive::Boolean(false),
+            _ => Primitive::Null,
+        })),
+        v => Err(Error::new(&format!("cannot parse type {} as boolean", v))),
+    }
+}
+
+fn string_arg<'a>(name: &str, args: &'a [Value], index: usize) -> Result<&'a str, Error> {
+    match &args[index] {
+        Value::Primitive(Primitive::String(s)) => Ok(s),
+        v => Err(Error::new(&format!(
+            "expected type string for argument {} of function {}; got {}",
+            index + 1,
+            name,
+            v
+        ))),
+    }
+}
+
+fn read_file(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("read_file", &args, 1)?;
+    require(scope, "read_file", Capability::FsRead)?;
+    let path = string_arg("read_file", &args, 0)?;
+
+    match scope.runtime().borrow().fs.read_to_string(path) {
+        Ok(s) => Ok(Value::Primitive(Primitive::String(s.into()))),
+        Err(e) => Err(Error::new(&format!("cannot read file {}: {}", path, e))),
+    }
+}
+
+fn read_file_bytes(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("read_file_bytes", &args, 1)?;
+    require(scope, "read_file_bytes", Capability::FsRead)?;
+    let path = string_arg("read_file_bytes", &args, 0)?;
+
+    match scope.runtime().borrow().fs.read(path) {
+        Ok(bytes) => Ok(Value::Bytes(bytes)),
+        Err(e) => Err(Error::new(&format!("cannot read file {}: {}", path, e))),
+    }
+}
+
+/// Gets a string or bytes argument as bytes.
+fn bytes_arg<'a>(name: &str, args: &'a [Value], index: usize) -> Result<&'a [u8], Error> {
+    match &args[index] {
+        Value::Primitive(Primitive::String(s)) => Ok(s.as_bytes()),
+        Value::Bytes(bytes) => Ok(bytes),
+        v => Err(Error::new(&format!(
+            "expected type string or bytes for argument {} of function {}; got {}",
+            index + 1,
+            name,
+            v
+        ))),
+    }
+}
+
+fn write_file(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("write_file", &args, 2)?;
+    require(scope, "write_file", Capability::FsWrite)?;
+    let path = string_arg("write_file", &args, 0)?;
+    let contents = bytes_arg("write_file", &args, 1)?;
+
+    match scope.runtime().borrow().fs.write(path, contents) {
+        Ok(_) => Ok(Value::Primitive(Primitive::Null)),
+        Err(e) => Err(Error::new(&format!("cannot write file {}: {}", path, e))),
+    }
+}
+
+fn append_file(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("append_file", &args, 2)?;
+    require(scope, "append_file", Capability::FsWrite)?;
+    let path = string_arg("append_file", &args, 0)?;
+    let contents = bytes_arg("append_file", &args, 1)?;
+
+    match scope.runtime().borrow().fs.append(path, contents) {
+        Ok(_) => Ok(Value::Primitive(Primitive::Null)),
+        Err(e) => Err(Error::new(&format!(
+            "cannot append to file {}: {}",
+            path, e
+        ))),
+    }
+}
+
+fn list_dir(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("list_dir", &args, 1)?;
+    require(scope, "list_dir", Capability::FsRead)?;
+    let path = string_arg("list_dir", &args, 0)?;
+
+    match scope.runtime().borrow().fs.list_dir(path) {
+        Ok(names) => Ok(Value::List(
+            names
+                .into_iter()
+                .map(|v| Value::Primitive(Primitive::String(v.into())))
+                .collect(),
+        )),
+        Err(e) => Err(Error::new(&format!(
+            "cannot list directory {}: {}",
+            path, e
+        ))),
+    }
+}
+
+fn exists(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("exists", &args, 1)?;
+    require(scope, "exists", Capability::FsRead)?;
+    let path = string_arg("exists", &args, 0)?;
+
+    match scope.runtime().borrow().fs.exists(path) {
+        Ok(v) => Ok(Value::Primitive(Primitive::Boolean(v))),
+        Err(e) => Err(Error::new(&format!("cannot check path {}: {}", path, e))),
+    }
+}
+
+fn is_dir(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("is_dir", &args, 1)?;
+    require(scope, "is_dir", Capability::FsRead)?;
+    let path = string_arg("is_dir", &args, 0)?;
+
+    match scope.runtime().borrow().fs.is_dir(path) {
+        Ok(v) => Ok(Value::Primitive(Primitive::Boolean(v))),
+        Err(e) => Err(Error::new(&format!("cannot check path {}: {}", path, e))),
+    }
+}
+
+fn mkdir(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("mkdir", &args, 1)?;
+    require(scope, "mkdir", Capability::FsWrite)?;
+    let path = string_arg("mkdir", &args, 0)?;
+
+    match scope.runtime().borrow().fs.create_dir(path) {
+        Ok(_) => Ok(Value::Primitive(Primitive::Null)),
+        Err(e) => Err(Error::new(&format!(
+            "cannot create directory {}: {}",
+            path, e
+        ))),
+    }
+}
+
+fn remove(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("remove", &args, 1)?;
+    require(scope, "remove", Capability::FsWrite)?;
+    let path = string_arg("remove", &args, 0)?;
+
+    match scope.runtime().borrow().fs.remove(path) {
+        Ok(_) => Ok(Value::Primitive(Primitive::Null)),
+        Err(e) => Err(Error::new(&format!("cannot remove {}: {}", path, e))),
+    }
+}
+
+fn copy(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("copy", &args, 2)?;
+    require(scope, "copy", Capability::FsRead)?;
+    require(scope, "copy", Capability::FsWrite)?;
+    let from = string_arg("copy", &args, 0)?;
+    let to = string_arg("copy", &args, 1)?;
+
+    match scope.runtime().borrow().fs.copy(from, to) {
+        Ok(_) => Ok(Value::Primitive(Primitive::Null)),
+        Err(e) => Err(Error::new(&format!(
+            "cannot copy {} to {}: {}",
+            from, to, e
+        ))),
+    }
+}
+
+fn random(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("random", &args, 0)?;
+
+    let v = scope.runtime().borrow_mut().rng.next_f64();
+
+    Ok(Value::Primitive(Primitive::Float(v)))
+}
+
+fn rand_int(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("rand_int", &args, 2)?;
+
+    let (lo, hi) = match (&args[0], &args[1]) {
+        (Value::Primitive(lo), Value::Primitive(hi))
+            if integer::is_integer(lo) && integer::is_integer(hi) =>
+        {
+            (integer::to_i64(lo), integer::to_i64(hi))
+        }
+        (lo, hi) => {
+            return Err(Error::new(&format!(
+                "cannot get a random integer between type {} and type {}",
+                lo, hi
+            )))
+        }
+    };
+    let (Some(lo), Some(hi)) = (lo, hi) else {
+        return Err(Error::new(&format!(
+            "cannot get a random integer between {} and {}, which must fit in 64 bits",
+            args[0].value(),
+            args[1].value()
+        )));
+    };
+
+    if lo > hi {
+        return Err(Error::new(&format!(
+            "cannot get a random integer between {} and {}",
+            lo, hi
+        )));
+    }
+
+    let range = (hi as i128 - lo as i128 + 1) as u128;
+    let mut rng = scope.runtime().borrow_mut();
+    let offset = if range > u64::MAX as u128 {
+        rng.rng.next_u64()
+    } else {
+        rng.rng.below(range as u64)
+    };
+
+    Ok(Value::Primitive(Primitive::Integer(
+        (lo as i128 + offset as i128) as i64,
+    )))
+}
+
+fn shuffle(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("shuffle", &args, 1)?;
+
+    let Value::List(items) = &args[0] else {
+        return Err(Error::new(&format!("cannot shuffle type {}", args[0])));
+    };
+
+    let mut items = items.clone();
+    let mut rng = scope.runtime().borrow_mut();
+    for i in (1..items.len()).rev() {
+        let j = rng.rng.below(i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+
+    Ok(Value::List(items))
+}
+
+fn sleep(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("sleep", &args, 1)?;
+
+    let secs = match &args[0] {
+        Value::Primitive(v) if integer::is_integer(v) => integer::to_f64(v),
+        Value::Primitive(Primitive::Float(v)) => *v,
+        v => return Err(Error::new(&format!("cannot sleep for type {}", v))),
+    };
+
+    let Ok(duration) = Duration::try_from_secs_f64(secs) else {
+        return Err(Error::new(&format!("cannot sleep for {} seconds", secs)));
+    };
+    let mut runtime = scope.runtime().borrow_mut();
+    runtime.sleep(duration);
+    runtime.check_deadline()?;
+
+    Ok(Value::Primitive(Primitive::Null))
+}
+
+fn cwd(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("cwd", &args, 0)?;
+    require(scope, "cwd", Capability::Env)?;
+
+    match env::current_dir() {
+        Ok(dir) => Ok(Value::Primitive(Primitive::String(
+            dir.to_string_lossy().into_owned().into(),
+        ))),
+        Err(e) => Err(Error::new(&format!("cannot get current directory: {}", e))),
+    }
+}
+
+fn pid(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("pid", &args, 0)?;
+    require(scope, "pid", Capability::Env)?;
+
+    Ok(Value::Primitive(Primitive::Integer(process::id() as i64)))
+}
+
+fn hostname(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("hostname", &args, 0)?;
+    require(scope, "hostname", Capability::Env)?;
+
+    let name = env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+
+    Ok(Value::Primitive(match name {
+        Some(name) => Primitive::String(name.into()),
+        None => Primitive::Null,
+    }))
+}
+
+fn now(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("now", &args, 0)?;
+
+    Ok(Value::DateTime(DateTime::now()))
+}
+
+fn parse_time(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("parse_time", &args, 1)?;
+    let s = string_arg("parse_time", &args, 0)?;
+
+    Ok(match DateTime::parse(s.trim()) {
+        Some(t) => Value::DateTime(t),
+        None => Value::Primitive(Primitive::Null),
+    })
+}
+
+fn datetime_arg(name: &str, args: &[Value], index: usize) -> Result<DateTime, Error> {
+    match &args[index] {
+        Value::DateTime(t) => Ok(*t),
+        v => Err(Error::new(&format!(
+            "expected type datetime for argument {} of function {}; got {}",
+            index + 1,
+            name,
+            v
+        ))),
+    }
+}
+
+fn format_time(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("format_time", &args, 2)?;
+    let time = datetime_arg("format_time", &args, 0)?;
+    let fmt = string_arg("format_time", &args, 1)?;
+
+    match time.format(fmt) {
+        Ok(s) => Ok(Value::Primitive(Primitive::String(s.into()))),
+        Err(spec) => Err(Error::new(&format!(
+            "invalid time format specifier {}",
+            spec
+        ))),
+    }
+}
+
+/// Converts a number of seconds into nanoseconds.
+fn seconds_arg(name: &str, args: &[Value], index: usize) -> Result<i128, Error> {
+    match &args[index] {
+        Value::Primitive(v) if integer::is_integer(v) => match integer::to_i64(v) {
+            Some(v) => Ok(v as i128 * 1_000_000_000),
+            None => Err(Error::new(&format!(
+                "cannot use {} seconds in function {}",
+                args[index].value(),
+                name
+            ))),
+        },
+        Value::Primitive(Primitive::Float(v)) if v.is_finite() => Ok((v * 1e9).round() as i128),
+        v => Err(Error::new(&format!(
+            "expected a number of seconds for argument {} of function {}; got {}",
+            index + 1,
+            name,
+            v
+        ))),
+    }
+}
+
+fn add_duration(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("add_duration", &args, 2)?;
+    let time = datetime_arg("add_duration", &args, 0)?;
+    let nanos = seconds_arg("add_duration", &args, 1)?;
+
+    match time
+        .unix_nanos()
+        .checked_add(nanos)
+        .and_then(DateTime::from_unix_nanos)
+    {
+        Some(t) => Ok(Value::DateTime(t)),
+        None => Err(Error::new("time is out of range")),
+    }
+}
+
+fn diff(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("diff", &args, 2)?;
+    let a = datetime_arg("diff", &args, 0)?;
+    let b = datetime_arg("diff", &args, 1)?;
+
+    let nanos = a.unix_nanos() - b.unix_nanos();
+    Ok(Value::Primitive(Primitive::Float(nanos as f64 / 1e9)))
+}
+
+fn timestamp(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("timestamp", &args, 1)?;
+    let time = datetime_arg("timestamp", &args, 0)?;
+
+    Ok(Value::Primitive(Primitive::Integer(
+        time.unix_nanos().div_euclid(1_000_000_000) as i64,
+    )))
+}
+
+fn from_timestamp(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("from_timestamp", &args, 1)?;
+    let nanos = seconds_arg("from_timestamp", &args, 0)?;
+
+    match DateTime::from_unix_nanos(nanos) {
+        Some(t) => Ok(Value::DateTime(t)),
+        None => Err(Error::new("time is out of range")),
+    }
+}
+
+fn csv_parse(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(Error::new(
+            "expected 1 or 2 arguments to function csv_parse",
+        ));
+    }
+    let input = string_arg("csv_parse", &args, 0)?;
+    let header = match args.get(1) {
+        Some(v) => v.is_truthy()?,
+        None => false,
+    };
+
+    let string = |s: String| Value::Primitive(Primitive::String(s.into()));
+    let mut rows = csv::parse(input)?.into_iter();
+
+    if !header {
+        return Ok(Value::List(
+            rows.map(|row| Value::List(row.into_iter().map(string).collect()))
+                .collect(),
+        ));
+    }
+
+    let keys = rows.next().unwrap_or_default();
+    Ok(Value::List(
+        rows.map(|row| {
+            Value::Map(
+                keys.iter()
+                    .cloned()
+                    .zip(row.into_iter().map(string))
+                    .collect(),
+            )
+        })
+        .collect(),
+    ))
+}
+
+fn csv_write(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("csv_write", &args, 1)?;
+
+    let Value::List(items) = &args[0] else {
+        return Err(Error::new(&format!("cannot write type {} as csv", args[0])));
+    };
+
+    let field = |v: Option<&Value>| match v {
+        None | Some(Value::Primitive(Primitive::Null)) => Ok(String::new()),
+        Some(v @ Value::Primitive(_)) => Ok(v.value()),
+        Some(v) => Err(Error::new(&format!(
+            "cannot write type {} as a csv field",
+            v
+        ))),
+    };
+
+    // rows of maps are written under a header taken from the first map's keys
+    let mut header = Vec::new();
+    let mut rows = Vec::new();
+    for item in items {
+        match item {
+            Value::List(row) => rows.push(
+                row.iter()
+                    .map(|v| field(Some(v)))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Map(entries) => {
+                if rows.is_empty() {
+                    header = entries.keys().cloned().collect();
+                    rows.push(header.clone());
+                }
+                rows.push(
+                    header
+                        .iter()
+                        .map(|k| field(entries.get(k)))
+                        .collect::<Result<_, _>>()?,
+                );
+            }
+            v => return Err(Error::new(&format!("cannot write type {} as a csv row", v))),
+        }
+    }
+
+    Ok(Value::Primitive(Primitive::String(
+        csv::write(&rows).into(),
+    )))
+}
+
+fn ok(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("ok", &args, 1)?;
+
+    Ok(Value::Map(BTreeMap::from([(
+        "ok".to_string(),
+        args[0].clone(),
+    )])))
+}
+
+fn err(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("err", &args, 1)?;
+
+    Ok(Value::Map(BTreeMap::from([(
+        "err".to_string(),
+        args[0].clone(),
+    )])))
+}
+
+/// Gets a result made by `ok` or `err` as a Rust result.
+fn result_arg<'a>(name: &str, args: &'a [Value]) -> Result<Result<&'a Value, &'a Value>, Error> {
+    if let Value::Map(entries) = &args[0] {
+        if entries.len() == 1 {
+            if let Some(v) = entries.get("ok") {
+                return Ok(Ok(v));
+            }
+            if let Some(v) = entries.get("err") {
+                return Ok(Err(v));
+            }
+        }
+    }
+
+    Err(Error::new(&format!(
+        "expected a result from ok or err for argument 1 of function {}; got {}",
+        name, args[0]
+    )))
+}
+
+fn is_ok(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("is_ok", &args, 1)?;
+    let res = result_arg("is_ok", &args)?;
+
+    Ok(Value::Primitive(Primitive::Boolean(res.is_ok())))
+}
+
+fn unwrap(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("unwrap", &args, 1)?;
+
+    match result_arg("unwrap", &args)? {
+        Ok(v) => Ok(v.clone()),
+        Err(e) => Err(Error::new(&format!(
+            "called unwrap on an error: {}",
+            e.value()
+        ))),
+    }
+}
+
+fn unwrap_or(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("unwrap_or", &args, 2)?;
+
+    match result_arg("unwrap_or", &args)? {
+        Ok(v) => Ok(v.clone()),
+        Err(_) => Ok(args[1].clone()),
+    }
+}
+
+fn exec(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(Error::new("expected 1 or 2 arguments to function exec"));
+    }
+    require(scope, "exec", Capability::Exec)?;
+    let program = string_arg("exec", &args, 0)?;
+
+    let mut command = if args.len() == 1 {
+        let mut command = Command::new(if cfg!(windows) { "cmd" } else { "sh" });
+        command
+            .arg(if cfg!(windows) { "/C" } else { "-c" })
+            .arg(program);
+        command
+    } else {
+        let Value::List(items) = &args[1] else {
+            return Err(Error::new(&format!(
+                "expected type list for argument 2 of function exec; got {}",
+                args[1]
+            )));
+        };
+
+        let mut command = Command::new(program);
+        for item in items {
+            match item {
+                Value::Primitive(Primitive::String(s)) => _ = command.arg(&**s),
+                v => return Err(Error::new(&format!("cannot pass type {} to a command", v))),
+            }
+        }
+        command
+    };
+
+    let output = match scope.runtime().borrow_mut().exec(&mut command) {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::TimedOut => {
+            return Err(Error::runtime(RuntimeErrorKind::TimedOut))
+        }
+        Err(e) => return Err(Error::new(&format!("cannot run {}: {}", program, e))),
+    };
+
+    let mut res = BTreeMap::new();
+    res.insert(
+        "stdout".to_string(),
+        Value::Primitive(Primitive::String(
+            String::from_utf8_lossy(&output.stdout).into_owned().into(),
+        )),
+    );
+    res.insert(
+        "stderr".to_string(),
+        Value::Primitive(Primitive::String(
+            String::from_utf8_lossy(&output.stderr).into_owned().into(),
+        )),
+    );
+    res.insert(
+        "status".to_string(),
+        Value::Primitive(match output.status.code() {
+            Some(code) => Primitive::Integer(code as i64),
+            None => Primitive::Null,
+        }),
+    );
+
+    Ok(Value::Map(res))
+}
+
+fn get(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("get", &args, 2)?;
+
+    match (&args[0], &args[1]) {
+        (Value::List(items), Value::Primitive(Primitive::Integer(i))) => {
+            let index = if *i < 0 { items.len() as i64 + i } else { *i };
+
+            Ok(usize::try_from(index)
+                .ok()
+                .and_then(|i| items.get(i))
+                .cloned()
+                .unwrap_or(Value::Primitive(Primitive::Null)))
+        }
+        (Value::Bytes(bytes), Value::Primitive(Primitive::Integer(i))) => {
+            let index = if *i < 0 { bytes.len() as i64 + i } else { *i };
+
+            Ok(Value::Primitive(
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|i| bytes.get(i))
+                    .map_or(Primitive::Null, |b| Primitive::Integer(*b as i64)),
+            ))
+        }
+        // big integers are always out of range
+        (Value::List(_) | Value::Bytes(_), Value::Primitive(i)) if integer::is_integer(i) => {
+            Ok(Value::Primitive(Primitive::Null))
+        }
+        (Value::Map(entries), Value::Primitive(Primitive::String(key))) => Ok(entries
+            .get(&**key)
+            .cloned()
+            .unwrap_or(Value::Primitive(Primitive::Null))),
+        (Value::List(_), v) => Err(Error::new(&format!("cannot index a list with type {}", v))),
+        (Value::Bytes(_), v) => Err(Error::new(&format!("cannot index bytes with type {}", v))),
+        (Value::Map(_), v) => Err(Error::new(&format!("cannot index a map with type {}", v))),
+        (v, _) => Err(Error::new(&format!("cannot index type {}", v))),
+    }
+}
+
+#[cfg(feature = "crypto")]
+fn hex_digest(digest: &[u8]) -> Value {
+    Value::Primitive(Primitive::String(
+        digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+            .into(),
+    ))
+}
+
+#[cfg(feature = "crypto")]
+fn sha256(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("sha256", &args, 1)?;
+    let bytes = bytes_arg("sha256", &args, 0)?;
+
+    Ok(hex_digest(&Sha256::digest(bytes)))
+}
+
+#[cfg(feature = "crypto")]
+fn md5(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("md5", &args, 1)?;
+    let bytes = bytes_arg("md5", &args, 0)?;
+
+    Ok(hex_digest(&md5::Md5::digest(bytes)))
+}
+
+#[cfg(feature = "http")]
+fn http_get(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(Error::new("expected 1 or 2 arguments to function http_get"));
+    }
+    require(scope, "http_get", Capability::Network)?;
+    let url = string_arg("http_get", &args, 0)?;
+
+    let request = with_headers("http_get", ureq::get(url), args.get(1))?;
+    http_send(url, request, None, scope)
+}
+
+#[cfg(feature = "http")]
+fn http_post(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(Error::new(
+            "expected 2 or 3 arguments to function http_post",
+        ));
+    }
+    require(scope, "http_post", Capability::Network)?;
+    let url = string_arg("http_post", &args, 0)?;
+    let body = string_arg("http_post", &args, 1)?;
+
+    let request = with_headers("http_post", ureq::post(url), args.get(2))?;
+    http_send(url, request, Some(body), scope)
+}
+
+/// Adds the headers to a request, given either as a map or as a list of
+/// `[name value]` lists.
+#[cfg(feature = "http")]
+fn with_headers(
+    name: &str,
+    mut request: ureq::Request,
+    headers: Option<&Value>,
+) -> Result<ureq::Request, Error> {
+    let invalid = |v: &Value| {
+        Error::new(&format!(
+            "cannot use type {} as headers for function {}",
+            v, name
+        ))
+    };
+
+    match headers {
+        None => {}
+        Some(Value::Map(entries)) => {
+            for (key, value) in entries {
+                let Value::Primitive(Primitive::String(value)) = value else {
+                    return Err(invalid(value));
+                };
+                request = request.set(key, value);
+            }
+        }
+        Some(Value::List(items)) => {
+            for item in items {
+                let Value::List(pair) = item else {
+                    return Err(invalid(item));
+                };
+                let [Value::Primitive(Primitive::String(key)), Value::Primitive(Primitive::String(value))] =
+                    pair.as_slice()
+                else {
+                    return Err(invalid(item));
+                };
+                request = request.set(key, value);
+            }
+        }
+        Some(v) => return Err(invalid(v)),
+    }
+
+    Ok(request)
+}
+
+#[cfg(feature = "http")]
+fn http_send(
+    url: &str,
+    request: ureq::Request,
+    body: Option<&str>,
+    scope: &mut Scope,
+) -> Result<Value, Error> {
+    let mut runtime = scope.runtime().borrow_mut();
+    let response = match runtime.http(request, body) {
+        Ok(response) => response,
+        Err(e) => {
+            // a request cut short by the deadline fails with a timeout error
+            runtime.check_deadline()?;
+            return Err(Error::new(&format!("http request failed: {}", e)));
+        }
+    };
+    drop(runtime);
+
+    let mut headers = BTreeMap::new();
+    for key in response.headers_names() {
+        if let Some(value) = response.header(&key) {
+            headers.insert(key, Value::Primitive(Primitive::String(value.into())));
+        }
+    }
+
+    let status = response.status() as i64;
+    let body = match response.into_string() {
+        Ok(body) => body,
+        Err(e) => {
+            return Err(Error::new(&format!(
+                "cannot read response from {}: {}",
+                url, e
+            )))
+        }
+    };
+
+    let mut res = BTreeMap::new();
+    res.insert(
+        "status".to_string(),
+        Value::Primitive(Primitive::Integer(status)),
+    );
+    res.insert("headers".to_string(), Value::Map(headers));
+    res.insert(
+        "body".to_string(),
+        Value::Primitive(Primitive::String(body.into())),
+    );
+
+    Ok(Value::Map(res))
+}
+
+#[cfg(feature = "uuid")]
+fn uuid(args: Vec<Value>, scope: &mut Scope) -> Result<Value, Error> {
+    expect_args("uuid", &args, 0)?;
+
+    let mut bytes = [0; 16];
+    let mut runtime = scope.runtime().borrow_mut();
+    for chunk in bytes.chunks_mut(8) {
+        chunk.copy_from_slice(&runtime.rng.next_u64().to_le_bytes());
+    }
+
+    Ok(Value::Primitive(Primitive::String(
+        uuid::Builder::from_random_bytes(bytes)
+            .into_uuid()
+            .to_string()
+            .into(),
+    )))
+}
+
+#[cfg(feature = "toml")]
+fn toml_parse(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("toml_parse", &args, 1)?;
+    let input = string_arg("toml_parse", &args, 0)?;
+
+    fn convert(value: toml::Value) -> Value {
+        match value {
+            toml::Value::String(v) => Value::Primitive(Primitive::String(v.into())),
+            toml::Value::Integer(v) => Value::Primitive(Primitive::Integer(v)),
+            toml::Value::Float(v) => Value::Primitive(Primitive::Float(v)),
+            toml::Value::Boolean(v) => Value::Primitive(Primitive::Boolean(v)),
+            toml::Value::Datetime(v) => Value::Primitive(Primitive::String(v.to_string().into())),
+            toml::Value::Array(items) => Value::List(items.into_iter().map(convert).collect()),
+            toml::Value::Table(entries) => {
+                Value::Map(entries.into_iter().map(|(k, v)| (k, convert(v))).collect())
+            }
+        }
+    }
+
+    match input.parse::<toml::Table>() {
+        Ok(table) => Ok(convert(toml::Value::Table(table))),
+        Err(e) => Err(Error::new(&format!(
+            "cannot parse toml: {}",
+            e.to_string().trim_end()
+        ))),
+    }
+}
+
+#[cfg(feature = "yaml")]
+fn yaml_parse(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    use yaml_rust2::{Yaml, YamlLoader};
+
+    expect_args("yaml_parse", &args, 1)?;
+    let input = string_arg("yaml_parse", &args, 0)?;
+
+    fn convert(value: Yaml) -> Result<Value, Error> {
+        Ok(match value {
+            Yaml::String(v) => Value::Primitive(Primitive::String(v.into())),
+            Yaml::Integer(v) => Value::Primitive(Primitive::Integer(v)),
+            Yaml::Real(ref v) => match value.as_f64() {
+                Some(f) => Value::Primitive(Primitive::Float(f)),
+                None => Value::Primitive(Primitive::String(v.clone().into())),
+            },
+            Yaml::Boolean(v) => Value::Primitive(Primitive::Boolean(v)),
+            Yaml::Null => Value::Primitive(Primitive::Null),
+            Yaml::Array(items) => {
+                Value::List(items.into_iter().map(convert).collect::<Result<_, _>>()?)
+            }
+            Yaml::Hash(entries) => {
+                let mut res = BTreeMap::new();
+                for (k, v) in entries {
+                    let key = match convert(k)? {
+                        Value::Primitive(Primitive::String(s)) => s.to_string(),
+                        k @ Value::Primitive(_) => k.value(),
+                        k => {
+                            return Err(Error::new(&format!("cannot use type {} as a map key", k)))
+                        }
+                    };
+                    res.insert(key, convert(v)?);
+                }
+
+                Value::Map(res)
+            }
+            Yaml::Alias(_) | Yaml::BadValue => {
+                return Err(Error::new("cannot parse yaml: unsupported value"))
+            }
+        })
+    }
+
+    match YamlLoader::load_from_str(input) {
+        Ok(mut docs) if !docs.is_empty() => convert(docs.swap_remove(0)),
+        Ok(_) => Ok(Value::Primitive(Primitive::Null)),
+        Err(e) => Err(Error::new(&format!("cannot parse yaml: {}", e))),
+    }
+}
+
+fn slice(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(Error::new("expected 2 or 3 arguments to function slice"));
+    }
+
+    let len = match &args[0] {
+        Value::List(items) => items.len(),
+        Value::Primitive(Primitive::String(s)) => s.chars().count(),
+        Value::Bytes(bytes) => bytes.len(),
+        v => return Err(Error::new(&format!("cannot slice type {}", v))),
+    };
+
+    // negative indexes count from the end, and out of range indexes are clamped
+    let mut bounds = [0, len];
+    for (bound, arg) in bounds.iter_mut().zip(&args[1..]) {
+        match arg {
+            Value::Primitive(Primitive::Integer(i)) => {
+                let i = if *i < 0 { len as i64 + i } else { *i };
+                *bound = i.clamp(0, len as i64) as usize;
+            }
+            Value::Primitive(i) if integer::is_integer(i) => {
+                *bound = if integer::compare(i, &Primitive::Integer(0)).is_lt() {
+                    0
+                } else {
+                    len
+                };
+            }
+            v => return Err(Error::new(&format!("cannot slice with type {}", v))),
+        }
+    }
+    let [start, end] = bounds;
+    let end = end.max(start);
+
+    Ok(match &args[0] {
+        Value::List(items) => Value::List(items[start..end].to_vec()),
+        Value::Primitive(Primitive::String(s)) => Value::Primitive(Primitive::String(
+            s.chars()
+                .skip(start)
+                .take(end - start)
+                .collect::<String>()
+                .into(),
+        )),
+        Value::Bytes(bytes) => Value::Bytes(bytes[start..end].to_vec()),
+        _ => unreachable!(),
+    })
+}
+
+fn len(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("len", &args, 1)?;
+
+    let len = match &args[0] {
+        Value::List(items) => items.len(),
+        Value::Map(entries) => entries.len(),
+        Value::Primitive(Primitive::String(s)) => s.chars().count(),
+        Value::Bytes(bytes) => bytes.len(),
+        v => return Err(Error::new(&format!("cannot get length of type {}", v))),
+    };
+
+    Ok(Value::Primitive(Primitive::Integer(len as i64)))
+}
+
+fn to_bytes(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("to_bytes", &args, 1)?;
+
+    match &args[0] {
+        Value::Primitive(Primitive::String(s)) => Ok(Value::Bytes(s.as_bytes().to_vec())),
+        Value::Bytes(bytes) => Ok(Value::Bytes(bytes.clone())),
+        Value::List(items) => {
+            let mut bytes = Vec::new();
+            for item in items {
+                match item {
+                    Value::Primitive(v) if integer::is_integer(v) => {
+                        match integer::to_i64(v).and_then(|v| u8::try_from(v).ok()) {
+                            Some(b) => bytes.push(b),
+                            None => {
+                                return Err(Error::new(&format!("invalid byte {}", item.value())))
+                            }
+                        }
+                    }
+                    v => return Err(Error::new(&format!("cannot convert type {} to a byte", v))),
+                }
+            }
+
+            Ok(Value::Bytes(bytes))
+        }
+        v => Err(Error::new(&format!("cannot convert type {} to bytes", v))),
+    }
+}
+
+fn from_bytes(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("from_bytes", &args, 1)?;
+
+    match &args[0] {
+        Value::Bytes(bytes) => Ok(Value::Primitive(match String::from_utf8(bytes.clone()) {
+            Ok(s) => Primitive::String(s.into()),
+            Err(_) => Primitive::Null,
+        })),
+        v => Err(Error::new(&format!("cannot convert type {} to string", v))),
+    }
+}
+
+fn from_hex(args: Vec<Value>, _: &mut Scope) -> Result<Value, Error> {
+    expect_args("from_hex", &args, 1)?;
+    let s = string_arg("from_hex", &args, 0)?;
+
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return Ok(Value::Primitive(Primitive::Null));
+    }
+
+    let mut bytes = Vec::new();
+    for i in (0..s.len()).step_by(2) {
+        match u8::from_str_radix(&s[i..i + 2], 16) {
+            Ok(b) => bytes.push(b),
+            Err(_) => return Ok(Value::Primitive(Primitive::Null)),
+        }
+    }
+
+    Ok(Value::Bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        eval::{eval, Scope},
+        lexer::Lexer,
+        parser::Parser,
+    };
+
+    /// Runs `source`, giving its result as it's printed or the message of its
+    /// error.
+    fn run(source: &str) -> Result<String, String> {
+        let program = Parser::from_lexer(Lexer::new(source))
+            .parse()
+            .map_err(|e| e.to_string())?;
+
+        eval(program, &mut Scope::new())
+            .map(|v| v.value())
+            .map_err(|e| e.to_string())
+    }
+
+    fn fails(source: &str, message: &str) {
+        match run(source) {
+            Ok(v) => panic!("{:?} should fail, but gave {}", source, v),
+            Err(e) => assert!(e.starts_with(message), "{:?} failed with {}", source, e),
+        }
+    }
+
+    #[test]
+    fn test_sort() {
+        assert_eq!(run("sort [3 1 2]").unwrap(), "[1 2 3]");
+        assert_eq!(run("sort [2.5 -1.0]").unwrap(), "[-1 2.5]");
+        assert_eq!(run("sort \"cba\"").unwrap(), "abc");
+        assert_eq!(run("sort [true false]").unwrap(), "[false true]");
+        fails(
+            "sort [1 2.0]",
+            "cannot compare type float with type integer",
+        );
+    }
+
+    #[test]
+    fn test_min_and_max() {
+        assert_eq!(run("min_of [3 1 2]").unwrap(), "1");
+        assert_eq!(run("max_of [3 7 2]").unwrap(), "7");
+        assert_eq!(run("min 2.5 1.5").unwrap(), "1.5");
+        assert_eq!(run("max 4").unwrap(), "4");
+        fails(
+            "min_of [3 1.0]",
+            "cannot compare type integer with type float",
+        );
+        fails("max \"a\" \"b\"", "cannot get max of type string");
+    }
+
+    #[test]
+    fn test_radix() {
+        assert_eq!(run("to_hex 255").unwrap(), "ff");
+        assert_eq!(run("to_bin -5").unwrap(), "-101");
+        assert_eq!(run("to_hex 0").unwrap(), "0");
+        assert_eq!(
+            run("to_hex (- 0 9223372036854775807 1)").unwrap(),
+            "-8000000000000000"
+        );
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn test_big_integer_arguments() {
+        assert_eq!(run("to_hex (pow 2 70)").unwrap(), "400000000000000000");
+        assert_eq!(
+            run("to_hex (- 0 (pow 2 64))").unwrap(),
+            "-10000000000000000"
+        );
+        assert_eq!(
+            run("to_bin (pow 2 65)").unwrap(),
+            format!("1{}", "0".repeat(65))
+        );
+        assert_eq!(
+            run("round_to (pow 2 70) 2").unwrap(),
+            "1180591620717411303424"
+        );
+        assert_eq!(run("get [1 2] (pow 2 70)").unwrap(), "null");
+        assert_eq!(
+            run("slice [1 2 3] (- 0 (pow 2 70)) (pow 2 70)").unwrap(),
+            "[1 2 3]"
+        );
+
+        fails(
+            "chr (pow 2 70)",
+            "invalid code point 1180591620717411303424",
+        );
+        fails(
+            "rand_int 0 (pow 2 70)",
+            "cannot get a random integer between 0 and 1180591620717411303424, which must fit in 64 bits",
+        );
+        fails(
+            "round_to 1.5 (pow 2 70)",
+            "cannot use 1180591620717411303424 decimal places in function round_to",
+        );
+        fails(
+            "to_bytes [(pow 2 70)]",
+            "invalid byte 1180591620717411303424",
+        );
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn test_sort_and_pick_big_integers() {
+        assert_eq!(
+            run("sort [(pow 2 71) 1 (pow 2 70)]").unwrap(),
+            "[1 1180591620717411303424 2361183241434822606848]"
+        );
+        assert_eq!(
+            run("max_of [1 (pow 2 70)]").unwrap(),
+            "1180591620717411303424"
+        );
+        assert_eq!(run("min (pow 2 70) 3").unwrap(), "3");
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_decimal_aggregates() {
+        assert_eq!(run("sum [2d 1.5d]").unwrap(), "3.5");
+        assert_eq!(
+            run("avg [1d 2d 2d]").unwrap(),
+            "1.6666666666666666666666666667"
+        );
+        assert_eq!(run("avg [1d 2d]").unwrap(), "1.5");
+        assert_eq!(run("type (sum [0.1d 0.2d])").unwrap(), "decimal");
+        fails(
+            "sum [1d 2]",
+            "cannot add type decimal with type integer in a list",
+        );
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_decimal_rounding() {
+        assert_eq!(run("round 2.5d").unwrap(), "3");
+        assert_eq!(run("round -2.5d").unwrap(), "-3");
+        assert_eq!(run("floor -1.5d").unwrap(), "-2");
+        assert_eq!(run("ceil 1.2d").unwrap(), "2");
+        assert_eq!(run("type (round 1.5d)").unwrap(), "integer");
+        assert_eq!(run("to_int 3.9d").unwrap(), "3");
+        assert_eq!(run("round_to 1.2345d 2").unwrap(), "1.23");
+        assert_eq!(run("round_to 2.5d 0").unwrap(), "3");
+        assert_eq!(run("format \"{:.2} {:.0}\" 1.005d 2.5d").unwrap(), "1.01 3");
+        assert_eq!(run("to_fixed 1d 3").unwrap(), "1.000");
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_decimal_math() {
+        assert_eq!(run("pow 1.5d 2").unwrap(), "2.25");
+        assert_eq!(run("pow 2d -2").unwrap(), "0.25");
+        assert_eq!(run("sqrt 4d").unwrap(), "2");
+        assert_eq!(run("type (sin 0d)").unwrap(), "float");
+        fails("pow 0d -1", "cannot divide by 0");
+        fails("pow 10d 100", "decimal overflow in pow");
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_sort_and_pick_decimals() {
+        assert_eq!(run("sort [2.5d 1d 1.25d]").unwrap(), "[1 1.25 2.5]");
+        assert_eq!(run("min_of [2d 1.5d]").unwrap(), "1.5");
+        assert_eq!(run("max 2d 3.5d").unwrap(), "3.5");
+    }
+}
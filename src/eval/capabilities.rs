@@ -0,0 +1,107 @@
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+};
+
+/// The side effects that builtins are allowed to have. Everything is allowed by
+/// default; use [`Capabilities::none`] to run untrusted code in a sandbox.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Reading files and directories.
+    pub fs_read: bool,
+    /// Creating, writing and removing files and directories.
+    pub fs_write: bool,
+    /// Sending HTTP requests.
+    pub network: bool,
+    /// Reading the process environment, such as the current directory.
+    pub env: bool,
+    /// Running other programs.
+    pub exec: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Self {
+            fs_read: true,
+            fs_write: true,
+            network: true,
+            env: true,
+            exec: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Self {
+            fs_read: false,
+            fs_write: false,
+            network: false,
+            env: false,
+            exec: false,
+        }
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::FsRead => self.fs_read,
+            Capability::FsWrite => self.fs_write,
+            Capability::Network => self.network,
+            Capability::Env => self.env,
+            Capability::Exec => self.exec,
+        }
+    }
+
+    pub fn allow(&mut self, capability: Capability) {
+        match capability {
+            Capability::FsRead => self.fs_read = true,
+            Capability::FsWrite => self.fs_write = true,
+            Capability::Network => self.network = true,
+            Capability::Env => self.env = true,
+            Capability::Exec => self.exec = true,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// A single kind of side effect, named the same as the [`Capabilities`] fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    FsRead,
+    FsWrite,
+    Network,
+    Env,
+    Exec,
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Capability::FsRead => write!(f, "fs_read"),
+            Capability::FsWrite => write!(f, "fs_write"),
+            Capability::Network => write!(f, "network"),
+            Capability::Env => write!(f, "env"),
+            Capability::Exec => write!(f, "exec"),
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fs_read" => Ok(Capability::FsRead),
+            "fs_write" => Ok(Capability::FsWrite),
+            "network" => Ok(Capability::Network),
+            "env" => Ok(Capability::Env),
+            "exec" => Ok(Capability::Exec),
+            _ => Err(format!(
+                "unknown capability {s}; expected fs_read, fs_write, network, env or exec"
+            )),
+        }
+    }
+}
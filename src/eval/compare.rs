@@ -0,0 +1,138 @@
+use super::{integer, value::Value};
+use crate::{
+    error::Error,
+    parser::ast::{OperatorKind, Primitive},
+    sync::{MaybeSend, MaybeSync},
+};
+use std::{cmp::Ordering, fmt::Debug};
+
+/// Equality and ordering between values, which the comparison operators use for
+/// any type that doesn't have a function registered for them.
+pub trait Compare {
+    /// Checks if the value is equal to `other`, failing if the two can't be
+    /// compared.
+    fn equals(&self, other: &Value) -> Result<bool, Error>;
+
+    /// Orders the value relative to `other`, giving `None` if they are unordered,
+    /// such as when `other` is `null`, and failing if they can't be ordered.
+    fn compare(&self, other: &Value) -> Result<Option<Ordering>, Error>;
+}
+
+/// A value of a type defined by the program embedding clip, such as a vector or
+/// a handle to something on the host, which scripts can pass around, compare and
+/// give to builtins or operators registered for it.
+pub trait External: Compare + Debug + MaybeSend + MaybeSync {
+    /// Gets the name of the type, as shown in errors and used to look up the
+    /// operators registered for it.
+    fn type_name(&self) -> &'static str;
+
+    /// Gets the value as it is printed.
+    fn value(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+/// External values are only equal to themselves with `==` in Rust; scripts
+/// compare them with [`Compare::equals`].
+impl PartialEq for dyn External {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self, other)
+    }
+}
+
+impl Compare for Primitive {
+    fn equals(&self, other: &Value) -> Result<bool, Error> {
+        let Value::Primitive(other) = other else {
+            return Err(mismatch(self.type_name(), other.type_name()));
+        };
+
+        match (self, other) {
+            (Primitive::Null, other) => Ok(*other == Primitive::Null),
+            (_, Primitive::Null) => Ok(false),
+            (a, b) if integer::is_integer(a) && integer::is_integer(b) => Ok(a == b),
+            (a, b) if a.type_name() == b.type_name() => Ok(a == b),
+            (a, b) => Err(mismatch(a.type_name(), b.type_name())),
+        }
+    }
+
+    fn compare(&self, other: &Value) -> Result<Option<Ordering>, Error> {
+        match self {
+            Primitive::Float(_) => {}
+            #[cfg(feature = "decimal")]
+            Primitive::Decimal(_) => {}
+            v if integer::is_integer(v) => {}
+            v => return Err(Error::new(&format!("cannot compare type {}", v))),
+        }
+
+        let Value::Primitive(other) = other else {
+            return Err(mismatch(self.type_name(), other.type_name()));
+        };
+
+        match (self, other) {
+            (_, Primitive::Null) => Ok(None),
+            (a, b) if integer::is_integer(a) && integer::is_integer(b) => {
+                Ok(Some(integer::compare(a, b)))
+            }
+            (Primitive::Float(a), Primitive::Float(b)) => Ok(a.partial_cmp(b)),
+            #[cfg(feature = "decimal")]
+            (Primitive::Decimal(a), Primitive::Decimal(b)) => Ok(Some(a.cmp(b))),
+            (a, b) => Err(mismatch(a.type_name(), b.type_name())),
+        }
+    }
+}
+
+impl Compare for Value {
+    fn equals(&self, other: &Value) -> Result<bool, Error> {
+        match self {
+            Value::Primitive(p) => p.equals(other),
+            Value::External(e) => e.equals(other),
+            v => Err(Error::new(&format!("cannot compare type {}", v))),
+        }
+    }
+
+    fn compare(&self, other: &Value) -> Result<Option<Ordering>, Error> {
+        match self {
+            Value::Primitive(p) => p.compare(other),
+            Value::External(e) => e.compare(other),
+            v => Err(Error::new(&format!("cannot compare type {}", v))),
+        }
+    }
+}
+
+fn mismatch(a: &str, b: &str) -> Error {
+    Error::new(&format!("cannot compare type {} with type {}", a, b))
+}
+
+/// Applies a comparison operator by comparing the first value with each of the
+/// others in turn, giving the result of the last comparison.
+pub fn apply(kind: &OperatorKind, args: &[Value]) -> Result<Value, Error> {
+    let (first, rest) = args
+        .split_first()
+        .expect("comparison should have arguments");
+    let mut res = false;
+
+    for arg in rest {
+        res = match kind {
+            OperatorKind::Equal => first.equals(arg)?,
+            OperatorKind::Greater => first.compare(arg)?.is_some_and(Ordering::is_gt),
+            OperatorKind::GreaterEqual => first.compare(arg)?.is_some_and(Ordering::is_ge),
+            OperatorKind::Less => first.compare(arg)?.is_some_and(Ordering::is_lt),
+            OperatorKind::LessEqual => first.compare(arg)?.is_some_and(Ordering::is_le),
+            kind => unreachable!("{} is not a comparison", kind),
+        };
+    }
+
+    Ok(Value::Primitive(Primitive::Boolean(res)))
+}
+
+/// Checks if the operator compares its arguments.
+pub fn is_comparison(kind: &OperatorKind) -> bool {
+    matches!(
+        kind,
+        OperatorKind::Equal
+            | OperatorKind::Greater
+            | OperatorKind::GreaterEqual
+            | OperatorKind::Less
+            | OperatorKind::LessEqual
+    )
+}
@@ -0,0 +1,89 @@
+use crate::error::Error;
+
+/// Parses CSV text into rows of fields, skipping blank lines. Fields can be quoted
+/// with `"`, in which case they may contain commas, newlines and doubled `""`
+/// quotes.
+pub fn parse(input: &str) -> Result<Vec<Vec<String>>, Error> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = input.chars().peekable();
+    let mut line = 1;
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => {
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => {
+                            if let Some('"') = chars.peek() {
+                                chars.next();
+                                field.push('"');
+                            } else {
+                                break;
+                            }
+                        }
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => {
+                            return Err(Error::new(&format!(
+                                "unterminated quoted field in csv (at line {})",
+                                line
+                            )))
+                        }
+                    }
+                }
+            }
+            ',' => {
+                row.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                if !row.is_empty() || !field.is_empty() || quoted {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                quoted = false;
+                line += 1;
+            }
+            c => field.push(c),
+        }
+    }
+
+    if !row.is_empty() || !field.is_empty() || quoted {
+        row.push(field);
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+/// Writes rows of fields as CSV text, quoting fields where needed.
+pub fn write(rows: &[Vec<String>]) -> String {
+    let mut res = String::new();
+
+    for row in rows {
+        let fields = row
+            .iter()
+            .map(|f| {
+                if f.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", f.replace('"', "\"\""))
+                } else {
+                    f.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        res.push_str(&fields.join(","));
+        res.push('\n');
+    }
+
+    res
+}
@@ -0,0 +1,261 @@
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// The range of supported times, from the start of year 0 to the end of 9999.
+const MIN_SECS: i128 = -62_167_219_200;
+const MAX_SECS: i128 = 253_402_300_799;
+
+/// A point in time in UTC, stored as nanoseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    nanos: i128,
+}
+
+impl DateTime {
+    pub fn now() -> Self {
+        let nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_nanos() as i128,
+            Err(e) => -(e.duration().as_nanos() as i128),
+        };
+
+        Self { nanos }
+    }
+
+    /// Creates a time from nanoseconds since the Unix epoch, or gives `None` if it
+    /// is outside of years 0 to 9999.
+    pub fn from_unix_nanos(nanos: i128) -> Option<Self> {
+        (MIN_SECS..=MAX_SECS)
+            .contains(&nanos.div_euclid(NANOS_PER_SEC))
+            .then_some(Self { nanos })
+    }
+
+    pub fn unix_nanos(&self) -> i128 {
+        self.nanos
+    }
+
+    /// Parses an ISO 8601 date such as `2023-06-30`, optionally followed by a
+    /// time such as `T12:30:00`, fractional seconds and a `Z` or `+01:00` offset.
+    /// Times without an offset are taken as UTC.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut p = Cursor {
+            s: s.as_bytes(),
+            pos: 0,
+        };
+
+        let year = p.digits(4)?;
+        p.expect(b'-')?;
+        let month = p.digits(2)?;
+        p.expect(b'-')?;
+        let day = p.digits(2)?;
+
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        let (mut hour, mut minute, mut second, mut frac) = (0, 0, 0, 0);
+        let mut offset = 0;
+
+        if p.eat(b'T') || p.eat(b't') || p.eat(b' ') {
+            hour = p.digits(2)?;
+            p.expect(b':')?;
+            minute = p.digits(2)?;
+            if p.eat(b':') {
+                second = p.digits(2)?;
+                if p.eat(b'.') {
+                    frac = p.fraction()?;
+                }
+            }
+
+            if hour > 23 || minute > 59 || second > 59 {
+                return None;
+            }
+
+            let utc = p.eat(b'Z') || p.eat(b'z');
+            if let Some(sign) = p.sign().filter(|_| !utc) {
+                let h = p.digits(2)?;
+                p.expect(b':')?;
+                let m = p.digits(2)?;
+                if h > 23 || m > 59 {
+                    return None;
+                }
+                offset = sign * (h * 3600 + m * 60);
+            }
+        }
+
+        if p.pos != p.s.len() {
+            return None;
+        }
+
+        let secs =
+            days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+
+        Some(Self {
+            nanos: secs as i128 * NANOS_PER_SEC + frac as i128,
+        })
+    }
+
+    /// Formats the time using `strftime`-like specifiers: `%Y`, `%m`, `%d`, `%H`,
+    /// `%M`, `%S`, `%f` (milliseconds), `%s` (Unix seconds) and `%%`.
+    pub fn format(&self, fmt: &str) -> Result<String, String> {
+        let (year, month, day, hour, minute, second) = self.parts();
+        let mut res = String::new();
+        let mut chars = fmt.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                res.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('Y') => res.push_str(&format!("{:04}", year)),
+                Some('m') => res.push_str(&format!("{:02}", month)),
+                Some('d') => res.push_str(&format!("{:02}", day)),
+                Some('H') => res.push_str(&format!("{:02}", hour)),
+                Some('M') => res.push_str(&format!("{:02}", minute)),
+                Some('S') => res.push_str(&format!("{:02}", second)),
+                Some('f') => res.push_str(&format!("{:03}", self.subsec_nanos() / 1_000_000)),
+                Some('s') => res.push_str(&self.nanos.div_euclid(NANOS_PER_SEC).to_string()),
+                Some('%') => res.push('%'),
+                Some(c) => return Err(format!("%{}", c)),
+                None => return Err("%".to_string()),
+            }
+        }
+
+        Ok(res)
+    }
+
+    fn subsec_nanos(&self) -> i64 {
+        self.nanos.rem_euclid(NANOS_PER_SEC) as i64
+    }
+
+    fn parts(&self) -> (i64, i64, i64, i64, i64, i64) {
+        let secs = self.nanos.div_euclid(NANOS_PER_SEC) as i64;
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let rem = secs.rem_euclid(86400);
+
+        (year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+    }
+}
+
+impl Display for DateTime {
+    /// Shows the time in RFC 3339 format, such as `2023-06-30T12:30:00Z`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let (year, month, day, hour, minute, second) = self.parts();
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year, month, day, hour, minute, second
+        )?;
+
+        let nanos = self.subsec_nanos();
+        if nanos != 0 {
+            write!(f, ".{}", format!("{:09}", nanos).trim_end_matches('0'))?;
+        }
+
+        write!(f, "Z")
+    }
+}
+
+struct Cursor<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn eat(&mut self, b: u8) -> bool {
+        if self.s.get(self.pos) == Some(&b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Option<()> {
+        self.eat(b).then_some(())
+    }
+
+    fn sign(&mut self) -> Option<i64> {
+        if self.eat(b'+') {
+            Some(1)
+        } else if self.eat(b'-') {
+            Some(-1)
+        } else {
+            None
+        }
+    }
+
+    fn digits(&mut self, count: usize) -> Option<i64> {
+        let digits = self.s.get(self.pos..self.pos + count)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        self.pos += count;
+
+        Some(digits.iter().fold(0, |acc, d| acc * 10 + (d - b'0') as i64))
+    }
+
+    /// Reads up to 9 fractional digits as nanoseconds, ignoring any extra digits.
+    fn fraction(&mut self) -> Option<i64> {
+        let start = self.pos;
+        let mut nanos = 0;
+
+        while let Some(d) = self.s.get(self.pos).filter(|d| d.is_ascii_digit()) {
+            if self.pos - start < 9 {
+                nanos = nanos * 10 + (d - b'0') as i64;
+            }
+            self.pos += 1;
+        }
+
+        match self.pos - start {
+            0 => None,
+            n if n < 9 => Some(nanos * 10i64.pow(9 - n as u32)),
+            _ => Some(nanos),
+        }
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Gets the number of days since the Unix epoch for a date in the proleptic
+/// Gregorian calendar, using Howard Hinnant's `days_from_civil` algorithm.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+/// The inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400;
+
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
@@ -0,0 +1,95 @@
+#[cfg(feature = "bigint")]
+use super::integer;
+use super::value::Value;
+use crate::{
+    error::Error,
+    parser::ast::{OperatorKind, Primitive},
+};
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+/// Applies an arithmetic operator to a decimal and the rest of its arguments,
+/// which must also be decimals. Unlike floats, results are exact or an error if
+/// they overflow.
+pub fn apply_operator(
+    kind: OperatorKind,
+    val: Decimal,
+    args: &[Primitive],
+) -> Result<Value, Error> {
+    if args.is_empty() {
+        return Ok(Value::Primitive(Primitive::Decimal(-val)));
+    }
+
+    let mut res = val;
+
+    for arg in args {
+        let Primitive::Decimal(v) = arg else {
+            return Err(Error::new(&format!(
+                "cannot {} type decimal with type {}",
+                kind, arg
+            )));
+        };
+
+        if v.is_zero() && matches!(kind, OperatorKind::Divide | OperatorKind::FloorDivide) {
+            return Err(Error::new("cannot divide by 0"));
+        }
+
+        res = match kind {
+            OperatorKind::Add => res.checked_add(*v),
+            OperatorKind::Subtract => res.checked_sub(*v),
+            OperatorKind::Multiply => res.checked_mul(*v),
+            OperatorKind::Divide => res.checked_div(*v),
+            _ => res.checked_div(*v).map(|v| v.floor()),
+        }
+        .ok_or_else(|| Error::new(&format!("decimal overflow in {}", kind)))?;
+    }
+
+    Ok(Value::Primitive(Primitive::Decimal(res)))
+}
+
+/// Converts a decimal to the nearest float.
+pub fn to_f64(val: &Decimal) -> f64 {
+    f64::try_from(*val).unwrap_or(f64::NAN)
+}
+
+/// Converts the whole part of a decimal to an integer, giving `None` if it
+/// doesn't fit in an `i64` and there are no big integers.
+pub fn to_integer(val: &Decimal) -> Option<Primitive> {
+    if let Some(v) = val.trunc().to_i64() {
+        return Some(Primitive::Integer(v));
+    }
+
+    // every decimal's whole part fits in an i128
+    #[cfg(feature = "bigint")]
+    return val
+        .trunc()
+        .to_i128()
+        .map(|v| integer::from_big(BigInt::from(v)));
+
+    #[cfg(not(feature = "bigint"))]
+    None
+}
+
+/// Raises a decimal to an integer power, giving `None` if it overflows or
+/// divides by zero.
+pub fn pow(val: Decimal, exp: i64) -> Option<Decimal> {
+    let mut res = Decimal::ONE;
+    let mut base = val;
+    let mut n = exp.unsigned_abs();
+    while n > 0 {
+        if n & 1 == 1 {
+            res = res.checked_mul(base)?;
+        }
+        n >>= 1;
+        if n > 0 {
+            base = base.checked_mul(base)?;
+        }
+    }
+
+    if exp < 0 {
+        Decimal::ONE.checked_div(res)
+    } else {
+        Some(res)
+    }
+}
@@ -0,0 +1,81 @@
+use crate::sync::MaybeSend;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+/// The filesystem operations available to builtins, so that embedders can
+/// replace or restrict how scripts access files.
+pub trait FileSystem: MaybeSend {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+    fn read_to_string(&self, path: &str) -> io::Result<String>;
+    fn write(&self, path: &str, contents: &[u8]) -> io::Result<()>;
+    fn append(&self, path: &str, contents: &[u8]) -> io::Result<()>;
+    fn list_dir(&self, path: &str) -> io::Result<Vec<String>>;
+    fn exists(&self, path: &str) -> io::Result<bool>;
+    fn is_dir(&self, path: &str) -> io::Result<bool>;
+    fn create_dir(&self, path: &str) -> io::Result<()>;
+    fn remove(&self, path: &str) -> io::Result<()>;
+    fn copy(&self, from: &str, to: &str) -> io::Result<()>;
+}
+
+/// Accesses the real filesystem through [`std::fs`].
+#[derive(Debug, Default)]
+pub struct StdFileSystem;
+
+impl FileSystem for StdFileSystem {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn append(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(contents)
+    }
+
+    fn list_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(path)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        Path::new(path).try_exists()
+    }
+
+    fn is_dir(&self, path: &str) -> io::Result<bool> {
+        Ok(Path::new(path).is_dir())
+    }
+
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        if fs::symlink_metadata(path)?.is_dir() {
+            fs::remove_dir(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn copy(&self, from: &str, to: &str) -> io::Result<()> {
+        fs::copy(from, to).map(|_| ())
+    }
+}
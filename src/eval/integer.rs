@@ -0,0 +1,211 @@
+use crate::{error::Error, parser::ast::Primitive};
+use std::cmp::Ordering;
+
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
+#[cfg(feature = "bigint")]
+use num_integer::Integer;
+#[cfg(feature = "bigint")]
+use num_traits::{Signed, ToPrimitive, Zero};
+
+/// An arithmetic operator on integers. Results that don't fit in an `i64` are
+/// promoted to big integers with the `bigint` feature, or are errors without it.
+#[derive(Clone, Copy, Debug)]
+pub enum IntOp {
+    Add,
+    Subtract,
+    Multiply,
+    FloorDivide,
+}
+
+impl IntOp {
+    #[cfg(not(feature = "bigint"))]
+    fn name(self) -> &'static str {
+        match self {
+            IntOp::Add => "addition",
+            IntOp::Subtract => "subtraction",
+            IntOp::Multiply => "multiplication",
+            IntOp::FloorDivide => "floor division",
+        }
+    }
+
+    fn checked(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            IntOp::Add => a.checked_add(b),
+            IntOp::Subtract => a.checked_sub(b),
+            IntOp::Multiply => a.checked_mul(b),
+            IntOp::FloorDivide => {
+                let res = a.checked_div(b)?;
+
+                Some(if a % b != 0 && (a < 0) != (b < 0) {
+                    res - 1
+                } else {
+                    res
+                })
+            }
+        }
+    }
+
+    #[cfg(feature = "bigint")]
+    fn big(self, a: &BigInt, b: &BigInt) -> BigInt {
+        match self {
+            IntOp::Add => a + b,
+            IntOp::Subtract => a - b,
+            IntOp::Multiply => a * b,
+            IntOp::FloorDivide => a.div_floor(b),
+        }
+    }
+}
+
+/// Checks if the primitive is an integer of any size.
+pub fn is_integer(value: &Primitive) -> bool {
+    match value {
+        Primitive::Integer(_) => true,
+        #[cfg(feature = "bigint")]
+        Primitive::BigInt(_) => true,
+        _ => false,
+    }
+}
+
+/// Applies `op` to two integers. Dividing by zero must be checked beforehand.
+pub fn apply(op: IntOp, a: &Primitive, b: &Primitive) -> Result<Primitive, Error> {
+    if let (Primitive::Integer(a), Primitive::Integer(b)) = (a, b) {
+        if let Some(res) = op.checked(*a, *b) {
+            return Ok(Primitive::Integer(res));
+        }
+    }
+
+    #[cfg(feature = "bigint")]
+    return Ok(from_big(op.big(&to_big(a), &to_big(b))));
+
+    #[cfg(not(feature = "bigint"))]
+    Err(Error::new(&format!("integer overflow in {}", op.name())))
+}
+
+pub fn negate(value: &Primitive) -> Result<Primitive, Error> {
+    apply(IntOp::Subtract, &Primitive::Integer(0), value)
+}
+
+pub fn abs(value: &Primitive) -> Result<Primitive, Error> {
+    if let Primitive::Integer(v) = value {
+        if let Some(res) = v.checked_abs() {
+            return Ok(Primitive::Integer(res));
+        }
+    }
+
+    #[cfg(feature = "bigint")]
+    return Ok(from_big(to_big(value).abs()));
+
+    #[cfg(not(feature = "bigint"))]
+    Err(Error::new("integer overflow in abs"))
+}
+
+/// Raises an integer to a non-negative power.
+pub fn pow(base: &Primitive, exp: u32) -> Result<Primitive, Error> {
+    if let Primitive::Integer(v) = base {
+        if let Some(res) = v.checked_pow(exp) {
+            return Ok(Primitive::Integer(res));
+        }
+    }
+
+    #[cfg(feature = "bigint")]
+    return Ok(from_big(to_big(base).pow(exp)));
+
+    #[cfg(not(feature = "bigint"))]
+    Err(Error::new("integer overflow in pow"))
+}
+
+/// Divides two integers, giving an integer if the division is exact and a float
+/// otherwise. Dividing by zero must be checked beforehand.
+pub fn divide(a: &Primitive, b: &Primitive) -> Primitive {
+    if let (Primitive::Integer(a), Primitive::Integer(b)) = (a, b) {
+        match (a.checked_rem(*b), a.checked_div(*b)) {
+            (Some(0), Some(q)) => return Primitive::Integer(q),
+            (Some(_), _) => return Primitive::Float(*a as f64 / *b as f64),
+            // only i64::MIN / -1 overflows, which is exact
+            (None, _) => {}
+        }
+    }
+
+    #[cfg(feature = "bigint")]
+    {
+        let (a, b) = (to_big(a), to_big(b));
+        if (&a % &b).is_zero() {
+            return from_big(a / b);
+        }
+    }
+
+    Primitive::Float(to_f64(a) / to_f64(b))
+}
+
+pub fn compare(a: &Primitive, b: &Primitive) -> Ordering {
+    match (a, b) {
+        (Primitive::Integer(a), Primitive::Integer(b)) => a.cmp(b),
+        #[cfg(feature = "bigint")]
+        _ => to_big(a).cmp(&to_big(b)),
+        #[cfg(not(feature = "bigint"))]
+        _ => unreachable!(),
+    }
+}
+
+pub fn to_f64(value: &Primitive) -> f64 {
+    match value {
+        Primitive::Integer(v) => *v as f64,
+        #[cfg(feature = "bigint")]
+        Primitive::BigInt(v) => v.to_f64().unwrap_or(f64::NAN),
+        _ => unreachable!(),
+    }
+}
+
+/// Gets the integer as an `i64`, giving `None` for a big integer, which never
+/// fits in one.
+pub fn to_i64(value: &Primitive) -> Option<i64> {
+    match value {
+        Primitive::Integer(v) => Some(*v),
+        _ => None,
+    }
+}
+
+/// Writes the integer in base `radix`, with a `-` before it if it's negative.
+pub fn to_str_radix(value: &Primitive, radix: u32) -> String {
+    match value {
+        Primitive::Integer(v) => {
+            let mut n = v.unsigned_abs();
+            let mut digits = Vec::new();
+            loop {
+                digits.extend(char::from_digit((n % radix as u64) as u32, radix));
+                n /= radix as u64;
+                if n == 0 {
+                    break;
+                }
+            }
+            if *v < 0 {
+                digits.push('-');
+            }
+
+            digits.iter().rev().collect()
+        }
+        #[cfg(feature = "bigint")]
+        Primitive::BigInt(v) => v.to_str_radix(radix),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(feature = "bigint")]
+fn to_big(value: &Primitive) -> BigInt {
+    match value {
+        Primitive::Integer(v) => BigInt::from(*v),
+        Primitive::BigInt(v) => v.clone(),
+        _ => unreachable!(),
+    }
+}
+
+/// Turns a big integer back into an `i64` if it fits, so that the two are never
+/// used for the same value.
+#[cfg(feature = "bigint")]
+pub fn from_big(value: BigInt) -> Primitive {
+    match value.to_i64() {
+        Some(v) => Primitive::Integer(v),
+        None => Primitive::BigInt(value),
+    }
+}
@@ -0,0 +1,132 @@
+use super::{
+    builtins,
+    runtime::Runtime,
+    value::Value,
+    vm::{Task, Vm},
+    Scope,
+};
+use crate::{
+    error::Error,
+    lexer::token::Location,
+    parser::ast::{Function, Primitive},
+    symbol::Symbol,
+    sync::{Lock, Shared},
+};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    mem,
+};
+
+/// A sequence of values that are only worked out as they're asked for, such as
+/// the values a generator function yields. Copies of an iterator share where it
+/// is, so taking a value from one takes it from all of them.
+#[derive(Clone)]
+pub struct Iter(Shared<Lock<State>>);
+
+enum State {
+    /// A call to a generator function, paused at its last `yield` or not started
+    /// yet. Generators always run on the VM, which can pause in the middle of a
+    /// function, whichever backend called them.
+    Generator {
+        vm: Box<Vm>,
+        task: Task,
+        /// The name the generator was called by and where, for stack traces,
+        /// unless it was called by a builtin.
+        call: Option<(Symbol, Location)>,
+    },
+    /// The lines left in the input of a runtime.
+    Lines(Shared<Lock<Runtime>>),
+    /// The iterator is working out its next value.
+    Running,
+    Done,
+}
+
+impl Iter {
+    /// Creates an iterator over the values yielded by the generator `fun`, which
+    /// was called as `call`. `scope` is the scope of the call, with the arguments
+    /// bound to the parameters.
+    pub fn generator(fun: &Function, scope: Scope, call: Option<(Symbol, &Location)>) -> Self {
+        let mut vm = Box::<Vm>::default();
+        let chunk = vm.compile_body(fun);
+        let task = vm.start(chunk, &scope);
+
+        Self::new(State::Generator {
+            vm,
+            task,
+            call: call.map(|(name, loc)| (name, loc.clone())),
+        })
+    }
+
+    /// Creates an iterator over the lines left in the input of `runtime`, which
+    /// are read as they're asked for.
+    pub fn lines(runtime: Shared<Lock<Runtime>>) -> Self {
+        Self::new(State::Lines(runtime))
+    }
+
+    fn new(state: State) -> Self {
+        Self(Shared::new(Lock::new(state)))
+    }
+
+    /// Gets the next value, or `None` once there are none left. An iterator that
+    /// fails is finished, so it gives `None` after the error.
+    pub fn next(&self) -> Result<Option<Value>, Error> {
+        // the state is taken out while it runs, so that a generator asking itself
+        // for a value fails instead of borrowing it twice
+        let state = mem::replace(&mut *self.0.borrow_mut(), State::Running);
+        let (state, result) = match state {
+            State::Generator {
+                mut vm,
+                mut task,
+                call,
+            } => match vm.next(&mut task) {
+                Ok(Some(value)) => (State::Generator { vm, task, call }, Ok(Some(value))),
+                Ok(None) => (State::Done, Ok(None)),
+                Err(e) => match call {
+                    Some((name, loc)) => (State::Done, Err(e.in_function(name, &loc))),
+                    None => (State::Done, Err(e)),
+                },
+            },
+            State::Lines(runtime) => match builtins::read_line(&runtime) {
+                Ok(Some(line)) => (
+                    State::Lines(runtime),
+                    Ok(Some(Value::Primitive(Primitive::String(line.into())))),
+                ),
+                Ok(None) => (State::Done, Ok(None)),
+                Err(e) => (State::Done, Err(e)),
+            },
+            State::Running => {
+                return Err(Error::new(
+                    "cannot take a value from an iterator while it is running",
+                ))
+            }
+            State::Done => (State::Done, Ok(None)),
+        };
+        *self.0.borrow_mut() = state;
+
+        result
+    }
+
+    /// Takes all the values that are left.
+    pub fn collect(&self) -> Result<Vec<Value>, Error> {
+        let mut values = Vec::new();
+        while let Some(value) = self.next()? {
+            values.push(value);
+        }
+
+        Ok(values)
+    }
+}
+
+impl Debug for Iter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Iter")
+    }
+}
+
+impl PartialEq for Iter {
+    /// Iterators are only equal to their copies, since they'd have to be run to
+    /// compare their values.
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
@@ -1,53 +1,425 @@
 use crate::{
     error::Error,
-    parser::ast::{Identifier, Primitive, Program, Statement},
+    parser::{
+        ast::{Identifier, OperatorKind, Primitive, Program, Statement},
+        pragma::Pragmas,
+    },
+    symbol::Symbol,
+    sync::{Lock, MaybeSend, Shared},
+};
+use capabilities::Capabilities;
+use filesystem::FileSystem;
+use ops::OperatorFn;
+use random::Rng;
+use runtime::Runtime;
+use snapshot::Snapshot;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    io::{BufRead, Write},
+    mem,
+    time::{Duration, Instant},
 };
-use std::collections::HashMap;
 use value::Value;
 
+pub mod builtins;
+pub mod capabilities;
+pub mod compare;
+pub mod csv;
+pub mod datetime;
+#[cfg(feature = "decimal")]
+pub mod decimal;
+pub mod filesystem;
+pub mod integer;
+pub mod iter;
 pub mod ops;
+pub mod program;
+pub mod random;
+pub mod runtime;
+pub mod snapshot;
 pub mod value;
+pub mod vm;
 
 pub fn eval(program: Program, scope: &mut Scope) -> Result<Value, Error> {
-    let mut result = Value::Primitive(Primitive::Null);
+    scope.apply_pragmas(&program.pragmas);
+    eval_statements(&program.statements, scope)
+}
 
-    for stmt in &program.statements {
-        match stmt {
-            Statement::Assign(a) => result = Value::eval_assign(a, scope)?,
-            Statement::If(i) => result = Value::eval_if_condition(i, scope)?,
-            Statement::Expression(e) => result = Value::eval_expr(e, scope)?,
+fn eval_statements(statements: &[Statement], scope: &mut Scope) -> Result<Value, Error> {
+    let mut result = Ok(Value::Primitive(Primitive::Null));
+
+    for stmt in statements {
+        result = Value::eval_statement(stmt, scope);
+        if result.is_err() {
+            break;
         }
     }
 
-    Ok(result)
+    Value::eval_deferred(result, scope)
+}
+
+/// Evaluates a program like [`eval`], but fails with an "evaluation timed out"
+/// error if it takes longer than `timeout`. A deadline the scope already has is
+/// kept if it's sooner, and is put back afterwards.
+pub fn eval_with_timeout(
+    program: Program,
+    scope: &mut Scope,
+    timeout: Duration,
+) -> Result<Value, Error> {
+    let previous = scope.runtime().borrow().deadline;
+    let deadline = Instant::now() + timeout;
+    scope.set_deadline(Some(previous.map_or(deadline, |d| d.min(deadline))));
+    let result = eval(program, scope);
+    scope.set_deadline(previous);
+
+    result
+}
+
+/// Evaluates a program by compiling it to bytecode and running it on the [`vm::Vm`]
+/// instead of walking the syntax tree.
+pub fn eval_vm(program: Program, scope: &mut Scope) -> Result<Value, Error> {
+    scope.apply_pragmas(&program.pragmas);
+    let chunk = crate::compiler::compile(&program.statements);
+
+    vm::Vm::default().run(chunk, scope)
+}
+
+/// Evaluates a program on the VM like [`eval_vm`], but gives control back to the
+/// executor every `steps` instructions so that a long-running script doesn't
+/// stop other tasks, such as a GUI or server, from making progress.
+pub async fn eval_async(program: Program, scope: &mut Scope, steps: usize) -> Result<Value, Error> {
+    scope.apply_pragmas(&program.pragmas);
+    let mut vm = vm::Vm::default();
+    let mut task = vm.start(crate::compiler::compile(&program.statements), scope);
+
+    loop {
+        if let Some(result) = vm.resume(&mut task, steps) {
+            return result;
+        }
+        vm::YieldNow::default().await;
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Scope {
-    store: HashMap<String, Value>,
-    outer: Option<Box<Scope>>,
+    frame: Shared<Lock<Frame>>,
+    deferred: Vec<Vec<Statement>>,
+    runtime: Shared<Lock<Runtime>>,
+}
+
+/// The variables of a single scope. Frames are shared between a scope and its
+/// children, so creating a scope for a function call doesn't copy its outer
+/// variables.
+#[derive(Debug, Default)]
+struct Frame {
+    store: HashMap<Symbol, Value>,
+    outer: Option<Shared<Lock<Frame>>>,
+}
+
+impl Frame {
+    fn get(&self, key: Symbol) -> Option<Value> {
+        match self.store.get(&key) {
+            Some(v) => Some(v.clone()),
+            None => match &self.outer {
+                Some(o) => o.borrow().get(key),
+                None => None,
+            },
+        }
+    }
+
+    /// Adds the variables in this frame and its outer frames to `into`, except
+    /// for builtins and those hidden by an inner frame.
+    fn collect(&self, into: &mut HashMap<Symbol, Value>) {
+        for (k, v) in &self.store {
+            if !matches!(v, Value::Builtin(_)) {
+                into.entry(*k).or_insert_with(|| v.clone());
+            }
+        }
+
+        if let Some(outer) = &self.outer {
+            outer.borrow().collect(into);
+        }
+    }
+
+    /// Adds the names of the variables in this frame and its outer frames to
+    /// `into`, including builtins.
+    fn names(&self, into: &mut HashSet<Symbol>) {
+        into.extend(self.store.keys());
+
+        if let Some(outer) = &self.outer {
+            outer.borrow().names(into);
+        }
+    }
 }
 
 impl Scope {
     pub fn new() -> Self {
+        let mut store = HashMap::new();
+        for builtin in builtins::BUILTINS {
+            store.insert(
+                Symbol::intern(builtin.name),
+                Value::Builtin(builtin.clone()),
+            );
+        }
+        for (name, value) in builtins::CONSTANTS {
+            store.insert(Symbol::intern(name), Value::Primitive(value.clone()));
+        }
+
         Self {
+            frame: Shared::new(Lock::new(Frame { store, outer: None })),
+            deferred: Vec::new(),
+            runtime: Default::default(),
+        }
+    }
+
+    /// Creates a scope for a function call that can see this scope's variables.
+    pub fn child(&self) -> Self {
+        let frame = Frame {
             store: Default::default(),
-            outer: None,
+            outer: Some(self.frame.clone()),
+        };
+
+        Self {
+            frame: Shared::new(Lock::new(frame)),
+            deferred: Vec::new(),
+            runtime: self.runtime.clone(),
         }
     }
 
-    pub fn get(&self, key: &Identifier) -> Option<&Value> {
-        match self.store.get(&key.value) {
-            Some(v) => Some(v),
-            None => match &self.outer {
-                Some(o) => o.get(key),
-                None => None,
-            },
+    /// Creates a scope with the same variables and runtime, but that runs its own
+    /// deferred blocks.
+    fn share(&self) -> Self {
+        Self {
+            frame: self.frame.clone(),
+            deferred: Vec::new(),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    /// Sets the reader used by builtins such as `input` instead of stdin.
+    pub fn set_input<R: BufRead + MaybeSend + 'static>(&mut self, input: R) {
+        self.runtime.borrow_mut().set_input(Box::new(input));
+    }
+
+    /// Sets the writer used by `print`, `println` and `input` instead of stdout.
+    /// Use a [`runtime::Capture`] to read back what was printed.
+    pub fn set_output<W: Write + MaybeSend + 'static>(&mut self, output: W) {
+        self.runtime.borrow_mut().set_output(Box::new(output));
+    }
+
+    /// Sets the filesystem used by the file builtins.
+    pub fn set_file_system<F: FileSystem + 'static>(&mut self, fs: F) {
+        self.runtime.borrow_mut().fs = Box::new(fs);
+    }
+
+    /// Seeds the random number generator used by the random builtins so that
+    /// runs are reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.runtime.borrow_mut().rng = Rng::new(seed);
+    }
+
+    /// Sets the side effects that builtins are allowed to have.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.runtime.borrow_mut().capabilities = capabilities;
+    }
+
+    /// Makes the operator call `handler` when its first argument is of the type
+    /// `type_name`, such as `"list"`, replacing the built-in behavior if there is
+    /// one. Operators whose arguments are all literals are still folded with the
+    /// built-in behavior unless folding is turned off.
+    pub fn register_operator(
+        &mut self,
+        kind: OperatorKind,
+        type_name: &'static str,
+        handler: OperatorFn,
+    ) {
+        self.runtime
+            .borrow_mut()
+            .operators
+            .register(kind, type_name, handler);
+    }
+
+    /// Limits evaluation to `fuel` steps, after which it fails with a "fuel
+    /// exhausted" error. Each statement, expression and VM instruction is a step.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.runtime.borrow_mut().fuel = fuel;
+    }
+
+    /// Limits how deeply functions can call each other, after which calls fail
+    /// with a "call depth limit exceeded" error instead of overflowing the stack.
+    /// It's [`runtime::DEFAULT_MAX_DEPTH`] by default. The VM doesn't use the
+    /// stack for calls, so it only counts calls made by builtins such as `map`.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.runtime.borrow_mut().max_depth = depth;
+    }
+
+    /// Applies the limits that a program sets for itself with pragmas, which can
+    /// lower the scope's fuel and take away its builtin functions. The evaluation
+    /// functions do this before running a program.
+    pub fn apply_pragmas(&mut self, pragmas: &Pragmas) {
+        if let Some(fuel) = pragmas.fuel {
+            self.set_fuel(Some(self.fuel().map_or(fuel, |f| f.min(fuel))));
+        }
+
+        if pragmas.no_builtins {
+            let mut frame = Some(self.frame.clone());
+            while let Some(f) = frame {
+                f.borrow_mut()
+                    .store
+                    .retain(|_, v| !matches!(v, Value::Builtin(_)));
+                frame = f.borrow().outer.clone();
+            }
         }
     }
 
+    /// Gets the number of steps evaluation can still take, if it is limited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.runtime.borrow().fuel
+    }
+
+    /// Makes evaluation fail with an "evaluation timed out" error if it is still
+    /// running at `deadline`.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.runtime.borrow_mut().deadline = deadline;
+    }
+
+    /// Limits the total size of the strings, lists, maps and bytes created by
+    /// operators and builtins to roughly `bytes`, after which evaluation fails
+    /// with a "memory limit exceeded" error. Memory isn't given back when values
+    /// are dropped, so this is a limit on how much a program can allocate.
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.runtime.borrow_mut().set_memory_limit(bytes);
+    }
+
+    /// Starts or stops recording a [`Snapshot`] of the variables after each
+    /// statement, which takes a copy of every visible variable each time. Only the
+    /// tree-walking interpreter records snapshots.
+    pub fn set_snapshots(&mut self, enabled: bool) {
+        self.runtime.borrow_mut().snapshots = enabled.then(Vec::new);
+    }
+
+    /// Takes the snapshots recorded so far, leaving recording on if it was.
+    pub fn take_snapshots(&mut self) -> Vec<Snapshot> {
+        self.runtime
+            .borrow_mut()
+            .snapshots
+            .as_mut()
+            .map(mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Records the variables visible after `stmt`, if snapshots are on.
+    fn snapshot(&self, stmt: &Statement) {
+        let mut runtime = self.runtime.borrow_mut();
+        let Some(snapshots) = &mut runtime.snapshots else {
+            return;
+        };
+
+        let mut variables = HashMap::new();
+        self.frame.borrow().collect(&mut variables);
+        snapshots.push(Snapshot {
+            loc: snapshot::statement_loc(stmt),
+            variables,
+        });
+    }
+
+    /// Applies an operator to already evaluated arguments, using the functions
+    /// registered for it.
+    fn apply_operator(&self, kind: OperatorKind, args: Vec<Value>) -> Result<Value, Error> {
+        self.runtime.borrow().operators.apply(kind, args)
+    }
+
+    fn apply_binary(&self, kind: &OperatorKind, a: Value, b: Value) -> Result<Value, Error> {
+        self.runtime.borrow().operators.apply_binary(kind, a, b)
+    }
+
+    /// Counts the size of a newly created value towards the memory limit.
+    fn track(&self, value: Value) -> Result<Value, Error> {
+        let mut runtime = self.runtime.borrow_mut();
+        if runtime.has_memory_limit() {
+            runtime.allocate(value.heap_size())?;
+        }
+
+        Ok(value)
+    }
+
+    fn step(&self) -> Result<(), Error> {
+        self.runtime.borrow_mut().step()
+    }
+
+    pub fn runtime(&self) -> &Shared<Lock<Runtime>> {
+        &self.runtime
+    }
+
+    pub fn get(&self, key: &Identifier) -> Option<Value> {
+        self.frame.borrow().get(key.value)
+    }
+
+    /// Gets the names of every variable that can be used from this scope,
+    /// including builtins.
+    pub fn names(&self) -> HashSet<Symbol> {
+        let mut names = HashSet::new();
+        self.frame.borrow().names(&mut names);
+
+        names
+    }
+
     pub fn set(&mut self, key: &Identifier, value: &Value) {
-        self.store.insert(key.value.clone(), value.clone());
+        self.frame
+            .borrow_mut()
+            .store
+            .insert(key.value, value.clone());
+    }
+
+    /// Binds a variable by name, such as to pass configuration to a script
+    /// without putting it in the source.
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.frame
+            .borrow_mut()
+            .store
+            .insert(Symbol::intern(name), value);
+    }
+
+    /// Creates a scope with the builtins and the given variables, such as from a
+    /// `HashMap<String, Value>`.
+    pub fn with_globals<I, K>(globals: I) -> Self
+    where
+        I: IntoIterator<Item = (K, Value)>,
+        K: AsRef<str>,
+    {
+        globals.into_iter().collect()
+    }
+
+    /// Binds each of the named environment variables to its value as a string,
+    /// skipping those that aren't set or aren't valid unicode.
+    pub fn define_env<I, S>(&mut self, names: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for name in names {
+            let name = name.as_ref();
+            if let Ok(value) = env::var(name) {
+                self.define(name, Value::Primitive(Primitive::String(value.into())));
+            }
+        }
+    }
+}
+
+impl<K: AsRef<str>> FromIterator<(K, Value)> for Scope {
+    fn from_iter<I: IntoIterator<Item = (K, Value)>>(iter: I) -> Self {
+        let mut scope = Self::new();
+        scope.extend(iter);
+
+        scope
+    }
+}
+
+impl<K: AsRef<str>> Extend<(K, Value)> for Scope {
+    fn extend<I: IntoIterator<Item = (K, Value)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.define(name.as_ref(), value);
+        }
     }
 }
 
@@ -1,321 +1,202 @@
-use super::{value::Value, Scope};
+use super::{
+    compare,
+    integer::{self, IntOp},
+    value::Value,
+};
 use crate::{
     error::Error,
-    parser::ast::{Operator, OperatorKind, Primitive},
+    parser::ast::{OperatorKind, Primitive},
 };
-
-pub fn eval_operator(op: Operator, scope: &mut Scope) -> Result<Value, Error> {
-    if op.kind == OperatorKind::Inverse {
-        if op.args.len() != 1 {
-            return Err(Error::new(
-                "expected exactly one argument for inverse operator",
-            ));
-        }
-
-        return match Value::eval_expr(&op.args[0], scope)? {
-            Value::Primitive(v) => match v {
-                Primitive::Boolean(b) => Ok(Value::Primitive(Primitive::Boolean(!b))),
-                _ => Err(Error::new(&format!("cannot inverse type {}", v))),
-            },
-            t => Err(Error::new(&format!("cannot inverse type {}", t))),
-        };
-    }
-
-    if op.args.len() < 2 && op.kind != OperatorKind::Subtract {
-        return Err(Error::new(&format!(
-            "expected at least 2 arguments for {} operator",
-            op.kind
-        )));
-    }
-
-    let mut values = Vec::new();
-    for arg in &op.args {
-        match Value::eval_expr(arg, scope)? {
-            Value::Primitive(v) => values.push(v),
-            t => return Err(Error::new(&format!("cannot compare type {}", t))),
-        }
-    }
-
-    match op.kind {
-        OperatorKind::Equal => eval_operator_equal(values),
-        OperatorKind::Greater => eval_operator_greater(values),
-        OperatorKind::GreaterEqual => eval_operator_greater_equal(values),
-        OperatorKind::Less => eval_operator_less(values),
-        OperatorKind::LessEqual => eval_operator_less_equal(values),
-        OperatorKind::Add => eval_operator_add(values),
-        OperatorKind::Subtract => eval_operator_subtract(values),
-        OperatorKind::Multiply => eval_operator_multiply(values),
-        OperatorKind::Divide => eval_operator_divide(values),
-        OperatorKind::Inverse => unreachable!(),
-    }
+use std::{collections::HashMap, sync::LazyLock};
+
+/// Applies an operator to arguments whose first one has the type the function
+/// was registered for. The rest of the arguments can be of any type.
+pub type OperatorFn = fn(OperatorKind, Vec<Value>) -> Result<Value, Error>;
+
+/// The functions that implement each operator, keyed by the operator and the
+/// type name of its first argument, as given by [`Value::type_name`]. Comparisons
+/// without a registered function use the [`Compare`](compare::Compare) trait.
+#[derive(Clone, Debug)]
+pub struct Operators {
+    handlers: HashMap<(OperatorKind, &'static str), OperatorFn>,
+    /// Whether the operators on integers and floats are the built-in ones, which
+    /// lets [`Operators::apply_binary`] skip looking them up.
+    builtin_numbers: bool,
 }
 
-fn eval_operator_equal(values: Vec<Primitive>) -> Result<Value, Error> {
-    match &values[0] {
-        Primitive::Integer(val) => {
-            let mut res = false;
+static BUILTIN: LazyLock<Operators> = LazyLock::new(Operators::default);
 
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Integer(v) => res = val == v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type integer with type {}",
-                            arg
-                        )))
-                    }
-                }
-            }
+/// Applies the operator to already evaluated arguments, using only the built-in
+/// operator functions.
+pub fn apply_operator(kind: OperatorKind, args: Vec<Value>) -> Result<Value, Error> {
+    BUILTIN.apply(kind, args)
+}
 
-            Ok(Value::Primitive(Primitive::Boolean(res)))
+impl Operators {
+    /// Makes the operator call `handler` when its first argument is of the type
+    /// `type_name`, replacing the function already registered for it, if any.
+    pub fn register(&mut self, kind: OperatorKind, type_name: &'static str, handler: OperatorFn) {
+        if matches!(type_name, "integer" | "float") {
+            self.builtin_numbers = false;
         }
-        Primitive::Float(val) => {
-            let mut res = false;
+        self.handlers.insert((kind, type_name), handler);
+    }
 
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Float(v) => res = val == v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type float with type {}",
-                            arg
-                        )))
-                    }
+    /// Applies the operator to two arguments. Operators on two integers or two
+    /// floats are worked out directly, rather than going through the registered
+    /// functions, since they make up most of the operators in a typical program.
+    pub fn apply_binary(&self, kind: &OperatorKind, a: Value, b: Value) -> Result<Value, Error> {
+        if self.builtin_numbers {
+            if let (Value::Primitive(x), Value::Primitive(y)) = (&a, &b) {
+                if let Some(res) = binary(kind, x, y) {
+                    return Ok(Value::Primitive(res));
                 }
             }
-
-            Ok(Value::Primitive(Primitive::Boolean(res)))
         }
-        Primitive::String(val) => {
-            let mut res = false;
 
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::String(v) => res = val == v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type string with type {}",
-                            arg
-                        )))
-                    }
-                }
-            }
+        self.apply(kind.clone(), vec![a, b])
+    }
 
-            Ok(Value::Primitive(Primitive::Boolean(res)))
+    /// Applies the operator to already evaluated arguments.
+    pub fn apply(&self, kind: OperatorKind, args: Vec<Value>) -> Result<Value, Error> {
+        if kind == OperatorKind::Inverse && args.len() != 1 {
+            return Err(Error::new(
+                "expected exactly one argument for inverse operator",
+            ));
         }
-        Primitive::Boolean(val) => {
-            let mut res = false;
-
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Boolean(v) => res = val == v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type boolean with type {}",
-                            arg
-                        )))
-                    }
-                }
-            }
 
-            Ok(Value::Primitive(Primitive::Boolean(res)))
+        if args.is_empty()
+            || args.len() < 2 && !matches!(kind, OperatorKind::Subtract | OperatorKind::Inverse)
+        {
+            return Err(Error::new(&format!(
+                "expected at least 2 arguments for {} operator",
+                kind
+            )));
         }
-        Primitive::Null => {
-            let mut res = false;
-
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Null => res = true,
-                    _ => res = false,
-                }
-            }
 
-            Ok(Value::Primitive(Primitive::Boolean(res)))
+        let type_name = args[0].type_name();
+        match self.handlers.get(&(kind.clone(), type_name)) {
+            Some(handler) => handler(kind, args),
+            None if compare::is_comparison(&kind) => compare::apply(&kind, &args),
+            None => Err(Error::new(&format!("cannot {} type {}", kind, type_name))),
         }
     }
 }
 
-fn eval_operator_greater(values: Vec<Primitive>) -> Result<Value, Error> {
-    match &values[0] {
-        Primitive::Integer(val) => {
-            let mut res = false;
+impl Default for Operators {
+    fn default() -> Self {
+        use OperatorKind::*;
 
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Integer(v) => res = val > v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type integer with type {}",
-                            arg
-                        )))
-                    }
-                }
+        let mut operators = Self {
+            handlers: HashMap::new(),
+            builtin_numbers: true,
+        };
+        for kind in [Add, Subtract, Multiply, Divide, FloorDivide] {
+            operators.register(kind.clone(), "integer", eval_primitive);
+            #[cfg(feature = "decimal")]
+            operators.register(kind.clone(), "decimal", eval_decimal);
+            if kind != FloorDivide {
+                operators.register(kind, "float", eval_primitive);
             }
-
-            Ok(Value::Primitive(Primitive::Boolean(res)))
         }
-        Primitive::Float(val) => {
-            let mut res = false;
-
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Float(v) => res = val > v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type float with type {}",
-                            arg
-                        )))
-                    }
-                }
-            }
+        operators.register(Add, "string", eval_primitive);
+        operators.register(Inverse, "boolean", eval_primitive);
 
-            Ok(Value::Primitive(Primitive::Boolean(res)))
-        }
-        val => Err(Error::new(&format!("cannot compare type {}", val))),
+        operators.builtin_numbers = true;
+        operators
     }
 }
 
-fn eval_operator_greater_equal(values: Vec<Primitive>) -> Result<Value, Error> {
-    match &values[0] {
-        Primitive::Integer(val) => {
-            let mut res = false;
-
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Integer(v) => res = val >= v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type integer with type {}",
-                            arg
-                        )))
-                    }
+/// Works out an operator on two integers or two floats, giving `None` for any
+/// other types, or if the result needs a big integer or an error, so that the
+/// registered function can handle it.
+fn binary(kind: &OperatorKind, a: &Primitive, b: &Primitive) -> Option<Primitive> {
+    match (a, b) {
+        (Primitive::Integer(x), Primitive::Integer(y)) => {
+            let (x, y) = (*x, *y);
+            Some(match kind {
+                OperatorKind::Equal => Primitive::Boolean(x == y),
+                OperatorKind::Greater => Primitive::Boolean(x > y),
+                OperatorKind::GreaterEqual => Primitive::Boolean(x >= y),
+                OperatorKind::Less => Primitive::Boolean(x < y),
+                OperatorKind::LessEqual => Primitive::Boolean(x <= y),
+                OperatorKind::Add => Primitive::Integer(x.checked_add(y)?),
+                OperatorKind::Subtract => Primitive::Integer(x.checked_sub(y)?),
+                OperatorKind::Multiply => Primitive::Integer(x.checked_mul(y)?),
+                OperatorKind::Divide if y != 0 => integer::divide(a, b),
+                OperatorKind::FloorDivide if y != 0 => {
+                    integer::apply(IntOp::FloorDivide, a, b).ok()?
                 }
-            }
-
-            Ok(Value::Primitive(Primitive::Boolean(res)))
+                _ => return None,
+            })
         }
-        Primitive::Float(val) => {
-            let mut res = false;
-
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Float(v) => res = val >= v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type float with type {}",
-                            arg
-                        )))
-                    }
-                }
-            }
-
-            Ok(Value::Primitive(Primitive::Boolean(res)))
+        (Primitive::Float(x), Primitive::Float(y)) => {
+            let (x, y) = (*x, *y);
+            Some(match kind {
+                OperatorKind::Equal => Primitive::Boolean(x == y),
+                OperatorKind::Greater => Primitive::Boolean(x > y),
+                OperatorKind::GreaterEqual => Primitive::Boolean(x >= y),
+                OperatorKind::Less => Primitive::Boolean(x < y),
+                OperatorKind::LessEqual => Primitive::Boolean(x <= y),
+                OperatorKind::Add => Primitive::Float(x + y),
+                OperatorKind::Subtract => Primitive::Float(x - y),
+                OperatorKind::Multiply => Primitive::Float(x * y),
+                OperatorKind::Divide => Primitive::Float(x / y),
+                _ => return None,
+            })
         }
-        val => Err(Error::new(&format!("cannot compare type {}", val))),
+        _ => None,
     }
 }
 
-fn eval_operator_less(values: Vec<Primitive>) -> Result<Value, Error> {
-    match &values[0] {
-        Primitive::Integer(val) => {
-            let mut res = false;
-
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Integer(v) => res = val < v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type integer with type {}",
-                            arg
-                        )))
-                    }
-                }
-            }
-
-            Ok(Value::Primitive(Primitive::Boolean(res)))
-        }
-        Primitive::Float(val) => {
-            let mut res = false;
-
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Float(v) => res = val < v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type float with type {}",
-                            arg
-                        )))
-                    }
-                }
-            }
-
-            Ok(Value::Primitive(Primitive::Boolean(res)))
+/// Gets the primitives out of the arguments, which must all be primitives.
+fn primitives(args: Vec<Value>) -> Result<Vec<Primitive>, Error> {
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Value::Primitive(v) => values.push(v),
+            t => return Err(Error::new(&format!("cannot compare type {}", t))),
         }
-        val => Err(Error::new(&format!("cannot compare type {}", val))),
     }
+
+    Ok(values)
 }
 
-fn eval_operator_less_equal(values: Vec<Primitive>) -> Result<Value, Error> {
-    match &values[0] {
-        Primitive::Integer(val) => {
-            let mut res = false;
+fn eval_primitive(kind: OperatorKind, args: Vec<Value>) -> Result<Value, Error> {
+    let values = primitives(args)?;
 
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Integer(v) => res = val <= v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type integer with type {}",
-                            arg
-                        )))
-                    }
-                }
-            }
-
-            Ok(Value::Primitive(Primitive::Boolean(res)))
-        }
-        Primitive::Float(val) => {
-            let mut res = false;
+    match kind {
+        OperatorKind::Add => eval_operator_add(values),
+        OperatorKind::Subtract => eval_operator_subtract(values),
+        OperatorKind::Multiply => eval_operator_multiply(values),
+        OperatorKind::Divide => eval_operator_divide(values),
+        OperatorKind::FloorDivide => eval_operator_floor_divide(values),
+        OperatorKind::Inverse => match &values[0] {
+            Primitive::Boolean(b) => Ok(Value::Primitive(Primitive::Boolean(!b))),
+            v => Err(Error::new(&format!("cannot inverse type {}", v))),
+        },
+        kind => unreachable!("{} is not registered for primitives", kind),
+    }
+}
 
-            for arg in values.iter().skip(1) {
-                match arg {
-                    Primitive::Float(v) => res = val <= v,
-                    Primitive::Null => res = false,
-                    _ => {
-                        return Err(Error::new(&format!(
-                            "cannot compare type float with type {}",
-                            arg
-                        )))
-                    }
-                }
-            }
+#[cfg(feature = "decimal")]
+fn eval_decimal(kind: OperatorKind, args: Vec<Value>) -> Result<Value, Error> {
+    let values = primitives(args)?;
+    let Primitive::Decimal(val) = values[0] else {
+        unreachable!()
+    };
 
-            Ok(Value::Primitive(Primitive::Boolean(res)))
-        }
-        val => Err(Error::new(&format!("cannot compare type {}", val))),
-    }
+    super::decimal::apply_operator(kind, val, &values[1..])
 }
 
 fn eval_operator_add(values: Vec<Primitive>) -> Result<Value, Error> {
     match &values[0] {
-        Primitive::Integer(val) => {
-            let mut res = Vec::new();
-            res.push(*val);
+        val if integer::is_integer(val) => {
+            let mut res = val.clone();
 
             for arg in values.iter().skip(1) {
                 match arg {
-                    Primitive::Integer(v) => res.push(*v),
+                    v if integer::is_integer(v) => res = integer::apply(IntOp::Add, &res, v)?,
                     _ => {
                         return Err(Error::new(&format!(
                             "cannot add type integer with type {}",
@@ -325,7 +206,7 @@ fn eval_operator_add(values: Vec<Primitive>) -> Result<Value, Error> {
                 }
             }
 
-            Ok(Value::Primitive(Primitive::Integer(res.iter().sum())))
+            Ok(Value::Primitive(res))
         }
         Primitive::Float(val) => {
             let mut res = Vec::new();
@@ -346,7 +227,7 @@ fn eval_operator_add(values: Vec<Primitive>) -> Result<Value, Error> {
             Ok(Value::Primitive(Primitive::Float(res.iter().sum())))
         }
         Primitive::String(val) => {
-            let mut res = val.clone();
+            let mut res = val.to_string();
 
             for arg in values.iter().skip(1) {
                 match arg {
@@ -360,7 +241,7 @@ fn eval_operator_add(values: Vec<Primitive>) -> Result<Value, Error> {
                 }
             }
 
-            Ok(Value::Primitive(Primitive::String(res)))
+            Ok(Value::Primitive(Primitive::String(res.into())))
         }
         val => Err(Error::new(&format!("cannot add type {}", val))),
     }
@@ -369,17 +250,19 @@ fn eval_operator_add(values: Vec<Primitive>) -> Result<Value, Error> {
 fn eval_operator_subtract(values: Vec<Primitive>) -> Result<Value, Error> {
     if values.len() == 1 {
         return match &values[0] {
-            Primitive::Integer(val) => Ok(Value::Primitive(Primitive::Integer(-val))),
+            val if integer::is_integer(val) => Ok(Value::Primitive(integer::negate(val)?)),
             Primitive::Float(val) => Ok(Value::Primitive(Primitive::Float(-val))),
             _ => unreachable!(),
         };
     }
 
     match &values[0] {
-        Primitive::Integer(mut val) => {
+        val if integer::is_integer(val) => {
+            let mut res = val.clone();
+
             for arg in values.iter().skip(1) {
                 match arg {
-                    Primitive::Integer(v) => val -= v,
+                    v if integer::is_integer(v) => res = integer::apply(IntOp::Subtract, &res, v)?,
                     _ => {
                         return Err(Error::new(&format!(
                             "cannot subtract type integer with type {}",
@@ -389,7 +272,7 @@ fn eval_operator_subtract(values: Vec<Primitive>) -> Result<Value, Error> {
                 }
             }
 
-            Ok(Value::Primitive(Primitive::Integer(val)))
+            Ok(Value::Primitive(res))
         }
         Primitive::Float(mut val) => {
             for arg in values.iter().skip(1) {
@@ -412,10 +295,12 @@ fn eval_operator_subtract(values: Vec<Primitive>) -> Result<Value, Error> {
 
 fn eval_operator_multiply(values: Vec<Primitive>) -> Result<Value, Error> {
     match &values[0] {
-        Primitive::Integer(mut val) => {
+        val if integer::is_integer(val) => {
+            let mut res = val.clone();
+
             for arg in values.iter().skip(1) {
                 match arg {
-                    Primitive::Integer(v) => val *= v,
+                    v if integer::is_integer(v) => res = integer::apply(IntOp::Multiply, &res, v)?,
                     _ => {
                         return Err(Error::new(&format!(
                             "cannot multiply type integer with type {}",
@@ -425,7 +310,7 @@ fn eval_operator_multiply(values: Vec<Primitive>) -> Result<Value, Error> {
                 }
             }
 
-            Ok(Value::Primitive(Primitive::Integer(val)))
+            Ok(Value::Primitive(res))
         }
         Primitive::Float(mut val) => {
             for arg in values.iter().skip(1) {
@@ -448,60 +333,70 @@ fn eval_operator_multiply(values: Vec<Primitive>) -> Result<Value, Error> {
 
 fn eval_operator_divide(values: Vec<Primitive>) -> Result<Value, Error> {
     match &values[0] {
-        Primitive::Integer(mut val) => {
-            let mut has_zero = val == 0;
+        val if integer::is_integer(val) => {
+            let mut res = val.clone();
 
+            for arg in values.iter().skip(1) {
+                if !integer::is_integer(arg) {
+                    return Err(Error::new(&format!(
+                        "cannot divide type integer with type {}",
+                        arg
+                    )));
+                }
+
+                if *arg == Primitive::Integer(0) {
+                    return Err(Error::new("cannot divide by 0"));
+                }
+
+                res = match res {
+                    Primitive::Float(n) => Primitive::Float(n / integer::to_f64(arg)),
+                    n => integer::divide(&n, arg),
+                };
+            }
+
+            Ok(Value::Primitive(res))
+        }
+        Primitive::Float(mut val) => {
             for arg in values.iter().skip(1) {
                 match arg {
-                    Primitive::Integer(v) => {
-                        if *v == 0 {
-                            if has_zero || val == 0 {
-                                return Err(Error::new("cannot divide 0 by 0"));
-                            } else if val == 1 {
-                                return Err(Error::new("infinity division"));
-                            }
-                            has_zero = true;
-                        }
-                        val /= v;
-                    }
+                    Primitive::Float(v) => val /= v,
                     _ => {
                         return Err(Error::new(&format!(
-                            "cannot divide type integer with type {}",
+                            "cannot divide type float with type {}",
                             arg
                         )))
                     }
                 }
             }
 
-            Ok(Value::Primitive(Primitive::Integer(val)))
+            Ok(Value::Primitive(Primitive::Float(val)))
         }
-        Primitive::Float(mut val) => {
-            let mut has_zero = val == 0.0;
+        val => Err(Error::new(&format!("cannot divide type {}", val))),
+    }
+}
+
+fn eval_operator_floor_divide(values: Vec<Primitive>) -> Result<Value, Error> {
+    match &values[0] {
+        val if integer::is_integer(val) => {
+            let mut res = val.clone();
 
             for arg in values.iter().skip(1) {
                 match arg {
-                    Primitive::Float(v) => {
-                        if *v == 0.0 {
-                            if has_zero || val == 0.0 {
-                                return Err(Error::new("cannot divide 0.0 by 0.0"));
-                            } else if val == 1.0 {
-                                return Err(Error::new("infinity division"));
-                            }
-                            has_zero = true;
-                        }
-                        val /= v;
+                    Primitive::Integer(0) => return Err(Error::new("cannot divide by 0")),
+                    v if integer::is_integer(v) => {
+                        res = integer::apply(IntOp::FloorDivide, &res, v)?
                     }
                     _ => {
                         return Err(Error::new(&format!(
-                            "cannot divide type float with type {}",
+                            "cannot floor divide type integer with type {}",
                             arg
                         )))
                     }
                 }
             }
 
-            Ok(Value::Primitive(Primitive::Float(val)))
+            Ok(Value::Primitive(res))
         }
-        val => Err(Error::new(&format!("cannot divide type {}", val))),
+        val => Err(Error::new(&format!("cannot floor divide type {}", val))),
     }
 }
@@ -0,0 +1,65 @@
+use super::{value::Value, vm::Vm, Scope};
+use crate::{
+    compiler::{self, Chunk},
+    error::Error,
+    lexer::{token::Token, Lexer},
+    optimizer,
+    parser::{ast::Program, Parser},
+    sync::Shared,
+};
+use std::cell::{OnceCell, RefCell};
+
+/// A script that is lexed, parsed and optimized once so that it can be evaluated
+/// many times, such as against a different scope for each input. The bytecode
+/// for [`CompiledProgram::eval_vm`] is compiled the first time it is needed and
+/// kept along with the compiled function bodies.
+#[derive(Debug)]
+pub struct CompiledProgram {
+    tokens: Vec<Token>,
+    program: Program,
+    chunk: OnceCell<Shared<Chunk>>,
+    vm: RefCell<Vm>,
+}
+
+impl CompiledProgram {
+    pub fn new(source: &str) -> Result<Self, Error> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.lex();
+        if let Some(e) = lexer.take_errors().into_iter().next() {
+            return Err(e);
+        }
+        let mut program = Parser::new(tokens.clone()).parse()?;
+        optimizer::fold_constants(&mut program);
+
+        Ok(Self {
+            tokens,
+            program,
+            chunk: OnceCell::new(),
+            vm: RefCell::new(Vm::default()),
+        })
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Evaluates the program with the tree-walking interpreter, like [`super::eval`].
+    pub fn eval(&self, scope: &mut Scope) -> Result<Value, Error> {
+        scope.apply_pragmas(&self.program.pragmas);
+        super::eval_statements(&self.program.statements, scope)
+    }
+
+    /// Evaluates the program on the VM, like [`super::eval_vm`].
+    pub fn eval_vm(&self, scope: &mut Scope) -> Result<Value, Error> {
+        scope.apply_pragmas(&self.program.pragmas);
+        let chunk = self
+            .chunk
+            .get_or_init(|| compiler::compile(&self.program.statements));
+
+        self.vm.borrow_mut().run(chunk.clone(), scope)
+    }
+}
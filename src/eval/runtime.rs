@@ -0,0 +1,306 @@
+use super::{
+    capabilities::Capabilities,
+    filesystem::{FileSystem, StdFileSystem},
+    ops::Operators,
+    random::Rng,
+    snapshot::Snapshot,
+};
+use crate::{
+    error::{Error, RuntimeErrorKind},
+    sync::{Lock, MaybeSend, Shared},
+};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::{self, BufRead, ErrorKind, Read, Write},
+    process::{self, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A reader for the `input` builtins, which has to be `Send` with the `sync`
+/// feature.
+pub trait Input: BufRead + MaybeSend {}
+
+impl<T: BufRead + MaybeSend> Input for T {}
+
+/// A writer for the `print` builtins, which has to be `Send` with the `sync`
+/// feature.
+pub trait Output: Write + MaybeSend {}
+
+impl<T: Write + MaybeSend> Output for T {}
+
+/// An output that keeps everything written to it, for reading back what a script
+/// printed. Clones share the same buffer, so one can be given to a scope and
+/// another kept to read from.
+#[derive(Clone, Debug, Default)]
+pub struct Capture(Shared<Lock<Vec<u8>>>);
+
+impl Capture {
+    /// Gets everything written so far, replacing invalid UTF-8.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How many steps are taken between checks of the deadline.
+const DEADLINE_INTERVAL: u64 = 1024;
+
+/// How often a command run with a deadline is checked on.
+const EXEC_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How deeply function calls can be nested by default. Each call takes a few
+/// kilobytes of the Rust stack in the tree-walking interpreter, so this fits in
+/// the 8 MB main thread stack of a release build.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// State shared by a scope and all of the scopes created from it while a program
+/// is being evaluated.
+pub struct Runtime {
+    input: Option<Box<dyn Input>>,
+    output: Option<Box<dyn Output>>,
+    pub fs: Box<dyn FileSystem>,
+    pub rng: Rng,
+    pub capabilities: Capabilities,
+    pub operators: Operators,
+    /// The number of steps evaluation can still take, or `None` if there is no
+    /// limit.
+    pub fuel: Option<u64>,
+    /// The time evaluation must finish by, if any.
+    pub deadline: Option<Instant>,
+    /// The snapshots taken after each statement, if they are being recorded.
+    pub snapshots: Option<Vec<Snapshot>>,
+    /// How deeply function calls that use the Rust stack can be nested.
+    pub max_depth: usize,
+    depth: usize,
+    steps: u64,
+    memory_limit: Option<usize>,
+    allocated: usize,
+}
+
+impl Runtime {
+    pub fn set_input(&mut self, input: Box<dyn Input>) {
+        self.input = Some(input);
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Output>) {
+        self.output = Some(output);
+    }
+
+    /// Uses up one step of fuel, failing if there is none left or if the
+    /// deadline has passed. The deadline is only checked every so often.
+    pub fn step(&mut self) -> Result<(), Error> {
+        self.steps = self.steps.wrapping_add(1);
+        if self.steps.is_multiple_of(DEADLINE_INTERVAL) {
+            self.check_deadline()?;
+        }
+
+        match &mut self.fuel {
+            Some(0) => Err(Error::runtime(RuntimeErrorKind::FuelExhausted)),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a function call that is about to use the Rust stack, failing if
+    /// calls are already nested as deeply as they can be. Each call that succeeds
+    /// has to be followed by [`Runtime::leave_call`] once it returns.
+    pub fn enter_call(&mut self) -> Result<(), Error> {
+        if self.depth >= self.max_depth {
+            return Err(Error::runtime(RuntimeErrorKind::CallDepthExceeded));
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn leave_call(&mut self) {
+        self.depth -= 1;
+    }
+
+    pub fn check_deadline(&self) -> Result<(), Error> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                Err(Error::runtime(RuntimeErrorKind::TimedOut))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Limits the number of bytes that values can take up in total, starting the
+    /// count from zero.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+        self.allocated = 0;
+    }
+
+    pub fn has_memory_limit(&self) -> bool {
+        self.memory_limit.is_some()
+    }
+
+    /// Counts `bytes` towards the memory limit, failing if it is exceeded.
+    pub fn allocate(&mut self, bytes: usize) -> Result<(), Error> {
+        self.allocated = self.allocated.saturating_add(bytes);
+
+        match self.memory_limit {
+            Some(limit) if self.allocated > limit => {
+                Err(Error::runtime(RuntimeErrorKind::MemoryLimitExceeded))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Pauses evaluation for `duration`, or until the deadline if that is sooner.
+    pub fn sleep(&mut self, duration: Duration) {
+        let duration = match self.deadline {
+            Some(deadline) => duration.min(deadline.saturating_duration_since(Instant::now())),
+            None => duration,
+        };
+
+        thread::sleep(duration);
+    }
+
+    /// Runs `command` to completion, capturing its output. If there is a
+    /// deadline, the command is killed once it passes, failing with a
+    /// [`ErrorKind::TimedOut`] error.
+    pub fn exec(&mut self, command: &mut Command) -> io::Result<process::Output> {
+        let Some(deadline) = self.deadline else {
+            return command.output();
+        };
+
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // the pipes are read while waiting, so the command can't get stuck writing
+        // to a full one
+        let stdout = read_pipe(child.stdout.take());
+        let stderr = read_pipe(child.stderr.take());
+
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                // the readers are left behind, since anything the command started
+                // could keep the pipes open
+                child.kill()?;
+                child.wait()?;
+                return Err(io::Error::new(ErrorKind::TimedOut, "evaluation timed out"));
+            }
+            thread::sleep(EXEC_POLL_INTERVAL.min(deadline - now));
+        };
+
+        Ok(process::Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+
+    /// Sends an HTTP request with an optional body. Error statuses are returned
+    /// as responses instead of errors. If there is a deadline, the request times
+    /// out when it passes.
+    #[cfg(feature = "http")]
+    pub fn http(
+        &mut self,
+        request: ureq::Request,
+        body: Option<&str>,
+    ) -> Result<ureq::Response, Box<ureq::Error>> {
+        let request = match self.deadline {
+            Some(deadline) => request.timeout(deadline.saturating_duration_since(Instant::now())),
+            None => request,
+        };
+        let res = match body {
+            Some(body) => request.send_string(body),
+            None => request.call(),
+        };
+
+        match res {
+            Err(ureq::Error::Status(_, response)) => Ok(response),
+            res => res.map_err(Box::new),
+        }
+    }
+
+    /// Writes and flushes text to the output, falling back to stdout if no output
+    /// was set.
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        match &mut self.output {
+            Some(output) => {
+                output.write_all(text.as_bytes())?;
+                output.flush()
+            }
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(text.as_bytes())?;
+                stdout.flush()
+            }
+        }
+    }
+
+    /// Reads a line from the input, falling back to stdin if no input was set.
+    pub fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        match &mut self.input {
+            Some(input) => input.read_line(buf),
+            None => io::stdin().read_line(buf),
+        }
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self {
+            input: None,
+            output: None,
+            fs: Box::new(StdFileSystem),
+            rng: Rng::from_time(),
+            capabilities: Capabilities::all(),
+            operators: Operators::default(),
+            fuel: None,
+            deadline: None,
+            snapshots: None,
+            steps: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            depth: 0,
+            memory_limit: None,
+            allocated: 0,
+        }
+    }
+}
+
+impl Debug for Runtime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("Runtime").finish_non_exhaustive()
+    }
+}
+
+/// Reads everything from a pipe of a child process on another thread.
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
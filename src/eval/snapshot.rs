@@ -0,0 +1,63 @@
+use super::value::Value;
+use crate::{
+    lexer::token::Location,
+    parser::ast::{Expression, Statement},
+    symbol::Symbol,
+};
+use std::collections::HashMap;
+
+/// The variables that were visible right after a statement was evaluated, so
+/// that a program can be stepped through backwards once it has run.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    /// Where the statement is, if it has a location.
+    pub loc: Option<Location>,
+    /// The variables by name, not including builtins.
+    pub variables: HashMap<Symbol, Value>,
+}
+
+impl Snapshot {
+    /// Gets the variables sorted by name.
+    pub fn sorted(&self) -> Vec<(Symbol, &Value)> {
+        let mut variables: Vec<_> = self.variables.iter().map(|(k, v)| (*k, v)).collect();
+        variables.sort_by_key(|(k, _)| k.as_str());
+
+        variables
+    }
+}
+
+/// Gets the location of a statement, where there is one.
+pub fn statement_loc(stmt: &Statement) -> Option<Location> {
+    match stmt {
+        Statement::Assign(a) => Some(a.name.loc.clone()),
+        Statement::If(i) => Some(i.loc.clone()),
+        Statement::Assert(a) => Some(a.loc.clone()),
+        Statement::Defer(_) => None,
+        Statement::Yield(y) => Some(y.loc.clone()),
+        Statement::Expression(e) => match e {
+            Expression::Identifier(i) => Some(i.loc.clone()),
+            Expression::Operator(o) => Some(o.loc.clone()),
+            Expression::Call(c) => Some(c.loc.clone()),
+            _ => None,
+        },
+    }
+}
+
+/// Finds the snapshots where a variable got a new value, along with that value.
+/// A variable that went out of scope and came back counts as a new value.
+pub fn changes(snapshots: &[Snapshot], name: Symbol) -> Vec<(usize, &Value)> {
+    let mut changes = Vec::new();
+    let mut last = None;
+
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let value = snapshot.variables.get(&name);
+        if let Some(v) = value {
+            if last != Some(v) {
+                changes.push((i, v));
+            }
+        }
+        last = value;
+    }
+
+    changes
+}
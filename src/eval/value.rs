@@ -1,17 +1,53 @@
-use super::{ops, Scope};
+use super::{builtins::Builtin, compare::External, datetime::DateTime, iter::Iter, Scope};
 use crate::{
-    error::Error,
-    parser::ast::{And, Assign, Call, Expression, Function, If, Or, Primitive, Statement},
+    error::{Error, RuntimeErrorKind},
+    lexer::token::Location,
+    parser::ast::{
+        Assert, Assign, Call, Defer, Expression, Function, Identifier, If, OperatorKind, Primitive,
+        Statement,
+    },
+    symbol::Symbol,
+    sync::Shared,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    mem,
 };
-use std::fmt::{Display, Formatter, Result as FmtResult};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     Primitive(Primitive),
     Function(Function),
+    Builtin(Builtin),
+    Operator(OperatorKind),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+    DateTime(DateTime),
+    Bytes(Vec<u8>),
+    External(Shared<dyn External>),
+    Iterator(Iter),
 }
 
 impl Value {
+    pub fn eval_statement(stmt: &Statement, scope: &mut Scope) -> Result<Self, Error> {
+        scope.step().map_err(|e| e.at(&stmt.span()))?;
+
+        let value = match stmt {
+            Statement::Assign(a) => Value::eval_assign(a, scope),
+            Statement::If(i) => Value::eval_if_condition(i, scope),
+            Statement::Assert(a) => Value::eval_assert(a, scope),
+            Statement::Defer(d) => Value::eval_defer(d, scope),
+            Statement::Yield(y) => {
+                Err(Error::new("cannot yield outside of a generator").at(&y.loc))
+            }
+            Statement::Expression(e) => Value::eval_expr(e, scope),
+        }?;
+        scope.snapshot(stmt);
+
+        Ok(value)
+    }
+
     pub fn eval_assign(a: &Assign, scope: &mut Scope) -> Result<Self, Error> {
         let value = Value::eval_expr(&a.value, scope)?;
         scope.set(&a.name, &value);
@@ -19,174 +55,549 @@ impl Value {
         Ok(value)
     }
 
-    pub fn eval_if_condition(i: &If, scope: &mut Scope) -> Result<Self, Error> {
-        let condition = match Value::eval_expr(&i.condition, scope)? {
+    fn eval_condition(e: &Expression, scope: &mut Scope, loc: &Location) -> Result<bool, Error> {
+        Value::eval_expr(e, scope)?
+            .is_truthy()
+            .map_err(|e| e.at(loc))
+    }
+
+    /// Checks if the value counts as true when used as a condition.
+    pub fn is_truthy(&self) -> Result<bool, Error> {
+        match self {
             Value::Primitive(p) => match p {
-                Primitive::Boolean(v) => v,
-                Primitive::Null => false,
-                _ => true,
+                Primitive::Boolean(v) => Ok(*v),
+                Primitive::Null => Ok(false),
+                _ => Ok(true),
             },
-            Value::Function(_) => {
-                return Err(Error::new("cannot use type function as a condition"))
+            Value::List(_)
+            | Value::Map(_)
+            | Value::DateTime(_)
+            | Value::Bytes(_)
+            | Value::External(_)
+            | Value::Iterator(_) => Ok(true),
+            Value::Function(_) | Value::Builtin(_) | Value::Operator(_) => {
+                Err(Error::new("cannot use type function as a condition"))
             }
-        };
+        }
+    }
 
+    pub fn eval_if_condition(i: &If, scope: &mut Scope) -> Result<Self, Error> {
         let mut res = Value::Primitive(Primitive::Null);
 
-        if condition {
+        if Value::eval_condition(&i.condition, scope, &i.loc)? {
             for cons in &i.consequence {
-                res = match cons.as_ref() {
-                    Statement::Assign(v) => Value::eval_assign(v, scope)?,
-                    Statement::If(v) => Value::eval_if_condition(v, scope)?,
-                    Statement::Expression(v) => Value::eval_expr(v, scope)?,
-                };
+                res = Value::eval_statement(cons, scope)?;
             }
         } else if let Some(alternative) = &i.alternative {
             for alt in alternative {
-                res = match alt.as_ref() {
-                    Statement::Assign(v) => Value::eval_assign(v, scope)?,
-                    Statement::If(v) => Value::eval_if_condition(v, scope)?,
-                    Statement::Expression(v) => Value::eval_expr(v, scope)?,
-                };
+                res = Value::eval_statement(alt, scope)?;
             }
         }
 
         Ok(res)
     }
 
-    pub fn eval_expr(e: &Expression, scope: &mut Scope) -> Result<Self, Error> {
-        match e {
-            Expression::Primitive(v) => Ok(Self::Primitive(v.clone())),
-            Expression::Identifier(i) => match scope.get(i) {
-                Some(v) => Ok(v.clone()),
-                None => Err(Error::new(&format!("undefined variable {}", i.value))),
-            },
-            Expression::Operator(v) => ops::eval_operator(v.clone(), scope),
-            Expression::Function(v) => Ok(Self::Function(v.clone())),
-            Expression::Call(v) => Value::eval_call(v.clone(), scope),
-            Expression::And(v) => Value::eval_logic_and(v.clone(), scope),
-            Expression::Or(v) => Value::eval_logic_or(v.clone(), scope),
+    pub fn eval_assert(a: &Assert, scope: &mut Scope) -> Result<Self, Error> {
+        if Value::eval_condition(&a.condition, scope, &a.loc)? {
+            return Ok(Value::Primitive(Primitive::Null));
         }
-    }
 
-    fn eval_call(call: Call, scope: &mut Scope) -> Result<Self, Error> {
-        let Some(val) = scope.get(&call.name) else {
-            return Err(Error::new(&format!("undefined function variable {}", call.name.value)));
+        let message = match &a.message {
+            Some(e) => Some(Value::eval_expr(e, scope)?),
+            None => None,
         };
 
-        match val {
-            Value::Function(fun) => {
-                if call.args.len() != fun.params.len() {
-                    if call.args.len() == 1 && fun.params.is_empty() {
-                        match &call.args[0] {
-                            Expression::Primitive(Primitive::Null) => (),
-                            _ => {
-                                return Err(Error::new(&format!(
-                                    "function {} can only be called with ()",
-                                    call.name.value
-                                )))
+        Err(Value::assertion_failed(message, &a.loc))
+    }
+
+    /// Creates the error raised by a failed assertion at `loc`.
+    pub fn assertion_failed(message: Option<Value>, loc: &Location) -> Error {
+        Error::runtime(RuntimeErrorKind::AssertionFailed(
+            message.map(|v| v.value()),
+        ))
+        .at(loc)
+    }
+
+    pub fn eval_defer(d: &Defer, scope: &mut Scope) -> Result<Self, Error> {
+        scope.deferred.push(d.body.clone());
+
+        Ok(Value::Primitive(Primitive::Null))
+    }
+
+    /// Runs the deferred blocks registered in `scope` in reverse order, returning
+    /// the first error raised either by `result` or by one of the blocks.
+    pub fn eval_deferred(result: Result<Self, Error>, scope: &mut Scope) -> Result<Self, Error> {
+        let mut result = result;
+
+        while let Some(body) = scope.deferred.pop() {
+            for stmt in &body {
+                if let Err(e) = Value::eval_statement(stmt, scope) {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                    break;
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Evaluates an expression using an explicit work stack rather than recursion,
+    /// so deeply nested expressions can't overflow the stack. Function bodies are
+    /// still evaluated recursively, so calls are limited to the runtime's maximum
+    /// depth instead.
+    pub fn eval_expr(e: &Expression, scope: &mut Scope) -> Result<Self, Error> {
+        let mut work = vec![Work::Eval(e)];
+        let mut values = Vec::new();
+        // Scopes of function calls whose arguments are being evaluated.
+        let mut frames: Vec<Scope> = Vec::new();
+
+        while let Some(item) = work.pop() {
+            let scope = match frames.last_mut() {
+                Some(s) => s,
+                None => &mut *scope,
+            };
+
+            match item {
+                Work::Eval(e) => {
+                    scope.step().map_err(|err| err.at(&e.span()))?;
+
+                    match e {
+                        Expression::Primitive(v, ..) => values.push(Self::Primitive(v.clone())),
+                        Expression::Identifier(i) => match scope.get(i) {
+                            Some(v) => values.push(v),
+                            None => {
+                                return Err(Error::runtime(RuntimeErrorKind::UndefinedVariable(
+                                    i.value.to_string(),
+                                ))
+                                .did_you_mean(
+                                    &i.value.as_str(),
+                                    scope.names().iter().map(Symbol::as_str),
+                                )
+                                .at(&i.loc))
+                            }
+                        },
+                        Expression::Operator(op) => {
+                            if op.args.is_empty() {
+                                values.push(Self::Operator(op.kind.clone()));
+                            } else {
+                                work.push(Work::Operator(op.kind.clone(), op.args.len(), &op.loc));
+                                Work::push_args(&mut work, &op.args);
                             }
                         }
+                        Expression::Function(v) => values.push(Self::Function(v.clone())),
+                        Expression::Block(b) => {
+                            let mut child = scope.child();
+                            values.push(Self::eval_body(&b.body, &mut child)?);
+                        }
+                        Expression::Call(call) if call.null_safe => {
+                            work.push(Work::NullSafe(call));
+                            work.push(Work::Eval(&call.args[0]));
+                        }
+                        Expression::Call(call) => {
+                            if let Some(child) =
+                                Self::start_call(call, None, scope, &mut work, &mut values)?
+                            {
+                                frames.push(child);
+                            }
+                        }
+                        Expression::List(v, ..) => {
+                            work.push(Work::List(v.len()));
+                            Work::push_args(&mut work, v);
+                        }
+                        Expression::And(v) => {
+                            work.push(Work::And(v.0.len()));
+                            Work::push_args(&mut work, &v.0);
+                        }
+                        Expression::Or(v) => {
+                            work.push(Work::Or(v.0.len()));
+                            Work::push_args(&mut work, &v.0);
+                        }
+                    }
+                }
+                Work::Operator(kind, argc, loc) => {
+                    let v = if argc == 2 {
+                        let b = values.pop().expect("operator should have arguments");
+                        let a = values.pop().expect("operator should have arguments");
+                        scope.apply_binary(&kind, a, b)
                     } else {
-                        return Err(Error::new(&format!(
-                            "expected {} arguments to function {}",
-                            fun.params.len(),
-                            call.name.value
-                        )));
+                        scope.apply_operator(kind, values.split_off(values.len() - argc))
+                    };
+                    values.push(v.and_then(|v| scope.track(v)).map_err(|e| e.at(loc))?);
+                }
+                Work::Builtin(builtin, argc, loc) => {
+                    let args = values.split_off(values.len() - argc);
+                    let v = builtin
+                        .call(args, scope)
+                        .and_then(|v| scope.track(v))
+                        .map_err(|e| e.in_builtin(builtin.name, loc))?;
+                    values.push(v);
+                }
+                Work::List(len) => {
+                    let items = values.split_off(values.len() - len);
+                    values.push(scope.track(Self::List(items))?);
+                }
+                Work::And(argc) => {
+                    let args = values.split_off(values.len() - argc);
+                    values.push(Value::logic_and(args));
+                }
+                Work::Or(argc) => {
+                    let args = values.split_off(values.len() - argc);
+                    values.push(Value::logic_or(args));
+                }
+                Work::Bind(param) => {
+                    let v = values.pop().expect("argument should have been evaluated");
+                    scope.set(&param, &v);
+                }
+                Work::NullSafe(call) => {
+                    let receiver = values.pop().expect("receiver should have been evaluated");
+                    if matches!(receiver, Value::Primitive(Primitive::Null)) {
+                        values.push(receiver);
+                    } else if let Some(child) =
+                        Self::start_call(call, Some(receiver), scope, &mut work, &mut values)?
+                    {
+                        frames.push(child);
                     }
                 }
+                Work::Body(fun, call) => {
+                    let mut child = frames.pop().expect("call should have a scope");
+                    let v = if fun.generator {
+                        Value::Iterator(Iter::generator(
+                            &fun,
+                            child,
+                            Some((call.name.value, &call.loc)),
+                        ))
+                    } else {
+                        Self::eval_call(&fun, &mut child)
+                            .map_err(|e| e.at(&call.loc).in_function(call.name.value, &call.loc))?
+                    };
+                    values.push(v);
+                }
+            }
+        }
+
+        Ok(values.pop().expect("expression should have a value"))
+    }
 
-                let mut child = Scope {
-                    store: Default::default(),
-                    outer: Some(Box::new(scope.clone())),
-                };
+    /// Looks up the function a call is to and pushes the work that evaluates its
+    /// arguments and calls it. `receiver` is the first argument if it has already
+    /// been evaluated, as it is for null-safe calls. Gives the scope the arguments
+    /// are evaluated in if it calls a function.
+    fn start_call<'a>(
+        call: &'a Call,
+        receiver: Option<Value>,
+        scope: &Scope,
+        work: &mut Vec<Work<'a>>,
+        values: &mut Vec<Value>,
+    ) -> Result<Option<Scope>, Error> {
+        let Some(val) = scope.get(&call.name) else {
+            return Err(Error::runtime(RuntimeErrorKind::UndefinedFunction(
+                call.name.value.to_string(),
+            ))
+            .did_you_mean(
+                &call.name.value.as_str(),
+                scope.names().iter().map(Symbol::as_str),
+            )
+            .at(&call.loc));
+        };
+        let first = usize::from(receiver.is_some());
+        let args = &call.args[first..];
 
-                for (param, expr) in fun.params.iter().zip(call.args.iter()) {
-                    let v = &Value::eval_expr(expr, &mut child)?;
-                    child.set(param, v);
+        match val {
+            Value::Function(fun) => {
+                let unit = matches!(call.args[..], [Expression::Primitive(Primitive::Null, ..)]);
+                Value::check_arity(&fun, call.name.value, call.args.len(), unit)
+                    .map_err(|e| e.at(&call.loc))?;
+
+                // Arguments are evaluated in the function's scope, and bound to
+                // their parameters as soon as they are evaluated.
+                let mut child = scope.child();
+                if let (Some(v), Some(param)) = (receiver, fun.params.first()) {
+                    child.set(param, &v);
+                }
+                work.push(Work::Body(fun.clone(), call));
+                for (param, expr) in fun.params.iter().skip(first).zip(args).rev() {
+                    work.push(Work::Bind(param.clone()));
+                    work.push(Work::Eval(expr));
                 }
 
-                let mut result = Self::Primitive(Primitive::Null);
+                Ok(Some(child))
+            }
+            Value::Builtin(builtin) => {
+                values.extend(receiver);
+                work.push(Work::Builtin(builtin, call.args.len(), &call.loc));
+                Work::push_args(work, args);
 
-                for stmt in &fun.body {
-                    match stmt {
-                        Statement::Assign(a) => result = Self::eval_assign(a, &mut child)?,
-                        Statement::If(i) => result = Self::eval_if_condition(i, &mut child)?,
-                        Statement::Expression(e) => result = Self::eval_expr(e, &mut child)?,
-                    }
+                Ok(None)
+            }
+            Value::Operator(kind) => {
+                if call.args.is_empty() {
+                    values.push(Self::Operator(kind));
+                } else {
+                    values.extend(receiver);
+                    work.push(Work::Operator(kind, call.args.len(), &call.loc));
+                    Work::push_args(work, args);
                 }
 
-                Ok(result)
+                Ok(None)
             }
-            Value::Primitive(p) => {
-                Err(Error::new(&format!("cannot call type {} as a function", p)))
+            v => Err(Error::runtime(RuntimeErrorKind::NotCallable(v.to_string())).at(&call.loc)),
+        }
+    }
+
+    /// Checks that a call to the function `name` passes the right number of
+    /// arguments, where `unit` is whether the only argument is a literal `()`.
+    pub fn check_arity(fun: &Function, name: Symbol, argc: usize, unit: bool) -> Result<(), Error> {
+        if argc == fun.params.len() {
+            return Ok(());
+        }
+
+        if argc == 1 && fun.params.is_empty() {
+            if unit {
+                Ok(())
+            } else {
+                Err(Error::new(&format!(
+                    "function {} can only be called with ()",
+                    name
+                )))
             }
+        } else {
+            Err(Error::new(&format!(
+                "expected {} arguments to function {}",
+                fun.params.len(),
+                name
+            )))
         }
     }
 
-    fn eval_logic_and(and: And, scope: &mut Scope) -> Result<Self, Error> {
-        let mut values = Vec::new();
+    /// Evaluates the body of a function or block in its own scope, running the
+    /// blocks deferred in it once it finishes.
+    fn eval_body(body: &[Statement], child: &mut Scope) -> Result<Self, Error> {
+        let mut result = Ok(Self::Primitive(Primitive::Null));
 
-        for expr in &and.0 {
-            values.push(Value::eval_expr(expr, scope)?);
+        for stmt in body {
+            result = Self::eval_statement(stmt, child);
+            if result.is_err() {
+                break;
+            }
         }
 
+        Self::eval_deferred(result, child)
+    }
+
+    /// Evaluates the body of a function in the scope of a call to it, which
+    /// counts towards the call depth limit since it recurses on the Rust stack.
+    fn eval_call(fun: &Function, child: &mut Scope) -> Result<Self, Error> {
+        child.runtime().borrow_mut().enter_call()?;
+        let result = Self::eval_body(&fun.body, child);
+        child.runtime().borrow_mut().leave_call();
+
+        result
+    }
+
+    /// Calls the value as a function with already evaluated arguments, which is
+    /// how builtins such as `map` call the functions passed to them.
+    pub fn call(&self, args: Vec<Value>, scope: &mut Scope) -> Result<Self, Error> {
+        match self {
+            Value::Function(fun) => {
+                let unit = args.len() == 1
+                    && fun.params.is_empty()
+                    && args[0] == Value::Primitive(Primitive::Null);
+
+                if args.len() != fun.params.len() && !unit {
+                    return Err(Error::new(&format!(
+                        "expected {} arguments to function; got {}",
+                        fun.params.len(),
+                        args.len()
+                    )));
+                }
+
+                let mut child = scope.child();
+
+                for (param, v) in fun.params.iter().zip(args.iter()) {
+                    child.set(param, v);
+                }
+
+                if fun.generator {
+                    return Ok(Value::Iterator(Iter::generator(fun, child, None)));
+                }
+                Self::eval_call(fun, &mut child).map_err(|e| e.in_callback(&fun.span))
+            }
+            Value::Builtin(b) => b.call(args, scope),
+            Value::Operator(kind) => scope.apply_operator(kind.clone(), args),
+            v => Err(Error::runtime(RuntimeErrorKind::NotCallable(v.to_string()))),
+        }
+    }
+
+    /// Gets the name of the value's type, as shown in errors.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Primitive(p) => p.type_name(),
+            Value::Function(_) | Value::Builtin(_) | Value::Operator(_) => "function",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::DateTime(_) => "datetime",
+            Value::Bytes(_) => "bytes",
+            Value::External(e) => e.type_name(),
+            Value::Iterator(_) => "iterator",
+        }
+    }
+
+    /// Gets roughly how many bytes the value takes up on the heap.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Value::Primitive(Primitive::String(s)) => s.len(),
+            #[cfg(feature = "bigint")]
+            Value::Primitive(Primitive::BigInt(v)) => v.bits().div_ceil(8) as usize,
+            Value::List(items) => items
+                .iter()
+                .map(|v| mem::size_of::<Value>() + v.heap_size())
+                .sum(),
+            Value::Map(entries) => entries
+                .iter()
+                .map(|(k, v)| k.len() + mem::size_of::<Value>() + v.heap_size())
+                .sum(),
+            Value::Bytes(bytes) => bytes.len(),
+            _ => 0,
+        }
+    }
+
+    /// Checks that none of the values are `false` or `()`.
+    pub fn logic_and(values: Vec<Value>) -> Self {
         for val in values {
             match val {
                 Value::Primitive(p) => match p {
                     Primitive::Boolean(v) if !v => {
-                        return Ok(Value::Primitive(Primitive::Boolean(false)));
+                        return Value::Primitive(Primitive::Boolean(false));
                     }
-                    Primitive::Null => return Ok(Value::Primitive(Primitive::Boolean(false))),
+                    Primitive::Null => return Value::Primitive(Primitive::Boolean(false)),
                     _ => (),
                 },
-                Value::Function(_) => (),
+                Value::Function(_)
+                | Value::Builtin(_)
+                | Value::Operator(_)
+                | Value::List(_)
+                | Value::Map(_)
+                | Value::DateTime(_)
+                | Value::Bytes(_)
+                | Value::External(_)
+                | Value::Iterator(_) => (),
             }
         }
 
-        Ok(Value::Primitive(Primitive::Boolean(true)))
+        Value::Primitive(Primitive::Boolean(true))
     }
 
-    fn eval_logic_or(or: Or, scope: &mut Scope) -> Result<Self, Error> {
-        let mut values = Vec::new();
-
-        for expr in &or.0 {
-            values.push(Value::eval_expr(expr, scope)?);
-        }
-
+    /// Checks that at least one of the values isn't `false` or `()`.
+    pub fn logic_or(values: Vec<Value>) -> Self {
         for val in values {
             match val {
                 Value::Primitive(p) => match p {
                     Primitive::Boolean(v) if !v => (),
                     Primitive::Null => (),
-                    _ => return Ok(Value::Primitive(Primitive::Boolean(true))),
+                    _ => return Value::Primitive(Primitive::Boolean(true)),
                 },
-                Value::Function(_) => return Ok(Value::Primitive(Primitive::Boolean(true))),
+                Value::Function(_)
+                | Value::Builtin(_)
+                | Value::Operator(_)
+                | Value::List(_)
+                | Value::Map(_)
+                | Value::DateTime(_)
+                | Value::Bytes(_)
+                | Value::External(_)
+                | Value::Iterator(_) => return Value::Primitive(Primitive::Boolean(true)),
             }
         }
 
-        Ok(Value::Primitive(Primitive::Boolean(false)))
+        Value::Primitive(Primitive::Boolean(false))
     }
 
     pub fn value(&self) -> String {
         match self {
             Value::Primitive(p) => match p {
                 Primitive::Integer(v) => v.to_string(),
-                Primitive::Float(v) => v.to_string(),
+                #[cfg(feature = "bigint")]
+                Primitive::BigInt(v) => v.to_string(),
+                #[cfg(feature = "decimal")]
+                Primitive::Decimal(v) => v.to_string(),
+                Primitive::Float(v) => {
+                    if v.is_nan() {
+                        "NaN".to_string()
+                    } else if v.is_infinite() {
+                        if v.is_sign_negative() { "-inf" } else { "inf" }.to_string()
+                    } else {
+                        v.to_string()
+                    }
+                }
                 Primitive::String(v) => v.to_string(),
                 Primitive::Boolean(v) => v.to_string(),
                 Primitive::Null => "null".to_string(),
             },
-            Value::Function(_) => "function".to_string(),
+            Value::Function(_) | Value::Builtin(_) | Value::Operator(_) => "function".to_string(),
+            Value::List(items) => {
+                let items = items.iter().map(|v| v.inner_value()).collect::<Vec<_>>();
+
+                format!("[{}]", items.join(" "))
+            }
+            Value::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v.inner_value()))
+                    .collect::<Vec<_>>();
+
+                format!("{{{}}}", entries.join(" "))
+            }
+            Value::DateTime(t) => t.to_string(),
+            Value::Bytes(bytes) => {
+                let bytes = bytes
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<_>>();
+
+                format!("<{}>", bytes.join(" "))
+            }
+            Value::External(e) => e.value(),
+            Value::Iterator(_) => "iterator".to_string(),
+        }
+    }
+
+    /// Gets the value as it is shown inside a list or map, with strings quoted.
+    fn inner_value(&self) -> String {
+        match self {
+            Value::Primitive(Primitive::String(s)) => format!("{:?}", s),
+            v => v.value(),
         }
     }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        match self {
-            Value::Primitive(p) => p.fmt(f),
-            Value::Function(_) => write!(f, "function"),
-        }
+        f.write_str(self.type_name())
+    }
+}
+
+/// A pending step of [`Value::eval_expr`]. Steps other than `Eval` and `Bind` pop
+/// their arguments off the value stack and push their result.
+enum Work<'a> {
+    Eval(&'a Expression),
+    Operator(OperatorKind, usize, &'a Location),
+    Builtin(Builtin, usize, &'a Location),
+    List(usize),
+    And(usize),
+    Or(usize),
+    /// Pops an argument and binds it to a parameter in the innermost call scope.
+    Bind(Identifier),
+    /// Pops the first argument of a null-safe call, and makes the call unless
+    /// it's null.
+    NullSafe(&'a Call),
+    /// Evaluates a function body in the innermost call scope, then drops it.
+    Body(Function, &'a Call),
+}
+
+impl<'a> Work<'a> {
+    /// Pushes the expressions so that they are evaluated from left to right.
+    fn push_args(work: &mut Vec<Self>, args: &'a [Expression]) {
+        work.extend(args.iter().rev().map(Work::Eval));
     }
 }
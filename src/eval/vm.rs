@@ -0,0 +1,490 @@
+use super::{iter::Iter, value::Value, Scope};
+use crate::{
+    compiler::{self, CallOp, Chunk, Op},
+    error::{Error, RuntimeErrorKind},
+    lexer::token::Location,
+    parser::ast::{BodyId, Function, Primitive},
+    symbol::Symbol,
+    sync::Shared,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// How many compiled function bodies a [`Vm`] keeps.
+const MAX_FUNCTIONS: usize = 1024;
+
+/// A stack machine that runs compiled chunks. Function bodies are compiled the
+/// first time they are called, then reused for later calls.
+///
+/// Calls don't recurse on the Rust stack: every chunk being run has a frame in a
+/// [`Task`], so a task can be paused after any instruction and resumed later.
+#[derive(Debug, Default)]
+pub struct Vm {
+    /// Compiled function bodies. Once there are [`MAX_FUNCTIONS`] of them it's
+    /// emptied, so programs that keep creating functions, such as with `eval`,
+    /// don't keep all of their code alive.
+    functions: HashMap<BodyId, Shared<Chunk>>,
+}
+
+/// A chunk that has been started on a [`Vm`] but may not have finished yet.
+#[derive(Debug)]
+pub struct Task {
+    frames: Vec<Frame>,
+    /// The value given by the last `yield`, until it is taken by [`Vm::next`].
+    yielded: Option<Value>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    chunk: Shared<Chunk>,
+    pc: usize,
+    stack: Vec<Value>,
+    scope: Scope,
+    kind: FrameKind,
+}
+
+/// What a frame is for, which decides what happens to its value when it finishes.
+#[derive(Debug)]
+enum FrameKind {
+    /// The chunk the task was started with.
+    Top,
+    /// An argument to a function call, which is bound to a parameter in the scope
+    /// of the call. The call is the instruction the parent frame is paused on.
+    Argument { fun: Function, index: usize },
+    /// The body of a function.
+    Body { name: Symbol, loc: Location },
+    /// An argument to a builtin or operator, which is added to the ones before it.
+    Collect { callee: Value, args: Vec<Value> },
+    /// The message of a failed assertion.
+    Message { loc: Location },
+    /// The statements of a block expression.
+    Block,
+}
+
+impl Frame {
+    fn new(chunk: Shared<Chunk>, scope: Scope, kind: FrameKind) -> Self {
+        Self {
+            chunk,
+            pc: 0,
+            stack: Vec::new(),
+            scope,
+            kind,
+        }
+    }
+}
+
+impl Vm {
+    pub fn run(&mut self, chunk: Shared<Chunk>, scope: &Scope) -> Result<Value, Error> {
+        let mut task = self.start(chunk, scope);
+        loop {
+            if let Some(result) = self.resume(&mut task, usize::MAX) {
+                return result;
+            }
+        }
+    }
+
+    /// Sets up a task to run `chunk` in `scope` without running any of it. The
+    /// deferred blocks it registers are run when it finishes.
+    pub fn start(&self, chunk: Shared<Chunk>, scope: &Scope) -> Task {
+        Task {
+            frames: vec![Frame::new(chunk, scope.share(), FrameKind::Top)],
+            yielded: None,
+        }
+    }
+
+    /// Runs up to `steps` instructions of a task, giving its result if it
+    /// finished. It stops early if the task yields. A finished task must not be
+    /// resumed again.
+    pub fn resume(&mut self, task: &mut Task, steps: usize) -> Option<Result<Value, Error>> {
+        for _ in 0..steps {
+            let finished = match self.step(task) {
+                Ok(None) => None,
+                Ok(Some(value)) => self.finish(&mut task.frames, Ok(value)),
+                Err(e) => self.finish(&mut task.frames, Err(e)),
+            };
+
+            if finished.is_some() {
+                return finished;
+            }
+            if task.yielded.is_some() {
+                break;
+            }
+        }
+
+        None
+    }
+
+    /// Runs the task of a generator until it yields, giving the value it yielded,
+    /// or `None` once it has finished. The value the body finishes with is
+    /// dropped, as generators only give what they yield.
+    pub fn next(&mut self, task: &mut Task) -> Result<Option<Value>, Error> {
+        loop {
+            if let Some(result) = self.resume(task, usize::MAX) {
+                return result.map(|_| None);
+            }
+            if let Some(value) = task.yielded.take() {
+                return Ok(Some(value));
+            }
+        }
+    }
+
+    /// Runs the next instruction of the innermost frame, giving its value if the
+    /// frame has no instructions left.
+    fn step(&mut self, task: &mut Task) -> Result<Option<Value>, Error> {
+        let frames = &mut task.frames;
+        let frame = frames.last_mut().expect("task should not be finished");
+        let chunk = frame.chunk.clone();
+        let Some(op) = chunk.code.get(frame.pc) else {
+            return Ok(Some(pop(&mut frame.stack)));
+        };
+        frame.pc += 1;
+        frame
+            .scope
+            .step()
+            .map_err(|e| match chunk.loc_near(frame.pc - 1) {
+                Some(loc) => e.at(loc),
+                None => e,
+            })?;
+
+        let (stack, scope) = (&mut frame.stack, &mut frame.scope);
+        match op {
+            Op::Constant(p) => stack.push(Value::Primitive(p.clone())),
+            Op::Load(name) => match scope.get(name) {
+                Some(v) => stack.push(v),
+                None => {
+                    return Err(Error::runtime(RuntimeErrorKind::UndefinedVariable(
+                        name.value.to_string(),
+                    ))
+                    .did_you_mean(
+                        &name.value.as_str(),
+                        scope.names().iter().map(Symbol::as_str),
+                    )
+                    .at(&name.loc))
+                }
+            },
+            Op::Store(name) => scope.set(name, top(stack)),
+            Op::Pop => _ = stack.pop(),
+            Op::Function(f) => stack.push(Value::Function(f.clone())),
+            Op::Operator(kind, 0, _) => stack.push(Value::Operator(kind.clone())),
+            Op::Operator(kind, argc, loc) => {
+                let v = if *argc == 2 {
+                    let b = pop(stack);
+                    let a = pop(stack);
+                    scope.apply_binary(kind, a, b)
+                } else {
+                    scope.apply_operator(kind.clone(), pop_n(stack, *argc))
+                };
+                stack.push(v.and_then(|v| scope.track(v)).map_err(|e| e.at(loc))?);
+            }
+            Op::List(len) => {
+                let items = pop_n(stack, *len);
+                stack.push(scope.track(Value::List(items))?);
+            }
+            Op::And(argc) => {
+                let args = pop_n(stack, *argc);
+                stack.push(Value::logic_and(args));
+            }
+            Op::Or(argc) => {
+                let args = pop_n(stack, *argc);
+                stack.push(Value::logic_or(args));
+            }
+            Op::Call(call) => {
+                if let Some(next) = self.call(call, stack, scope)? {
+                    frames.push(next);
+                }
+            }
+            Op::Jump(to) => frame.pc = *to,
+            Op::JumpIfFalse(to, loc) => {
+                if !pop(stack).is_truthy().map_err(|e| e.at(loc))? {
+                    frame.pc = *to;
+                }
+            }
+            Op::Assert(a) => {
+                if !pop(stack).is_truthy().map_err(|e| e.at(&a.loc))? {
+                    let Some(message) = &a.message else {
+                        return Err(Value::assertion_failed(None, &a.loc));
+                    };
+
+                    let kind = FrameKind::Message { loc: a.loc.clone() };
+                    let next = Frame::new(message.clone(), scope.share(), kind);
+                    frames.push(next);
+                } else {
+                    stack.push(Value::Primitive(Primitive::Null));
+                }
+            }
+            Op::Defer(body) => {
+                scope.deferred.push(body.clone());
+                stack.push(Value::Primitive(Primitive::Null));
+            }
+            Op::Yield => {
+                task.yielded = Some(pop(stack));
+                stack.push(Value::Primitive(Primitive::Null));
+            }
+            Op::Scoped(body) => {
+                let next = Frame::new(body.clone(), scope.child(), FrameKind::Block);
+                frames.push(next);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Starts a call, giving the frame that evaluates its first argument, or the
+    /// body of the function if it has none. Calls that need no frame push their
+    /// result straight onto the stack.
+    fn call(
+        &mut self,
+        call: &CallOp,
+        stack: &mut Vec<Value>,
+        scope: &mut Scope,
+    ) -> Result<Option<Frame>, Error> {
+        // the first argument of a null-safe call is evaluated before the call
+        let receiver = if call.null_safe {
+            match pop(stack) {
+                Value::Primitive(Primitive::Null) => {
+                    stack.push(Value::Primitive(Primitive::Null));
+                    return Ok(None);
+                }
+                v => Some(v),
+            }
+        } else {
+            None
+        };
+
+        let Some(val) = scope.get(&call.name) else {
+            return Err(Error::runtime(RuntimeErrorKind::UndefinedFunction(
+                call.name.value.to_string(),
+            ))
+            .did_you_mean(
+                &call.name.value.as_str(),
+                scope.names().iter().map(Symbol::as_str),
+            )
+            .at(&call.loc));
+        };
+
+        match val {
+            Value::Function(fun) => {
+                Value::check_arity(&fun, call.name.value, call.argc(), call.unit)
+                    .map_err(|e| e.at(&call.loc))?;
+
+                let mut child = scope.child();
+                let index = usize::from(receiver.is_some());
+                if let (Some(v), Some(param)) = (receiver, fun.params.first()) {
+                    child.set(param, &v);
+                }
+                match call.arg(index) {
+                    Some(arg) => Ok(Some(Frame::new(
+                        arg.clone(),
+                        child,
+                        FrameKind::Argument { fun, index },
+                    ))),
+                    None if fun.generator => {
+                        stack.push(generator(&fun, child, call));
+                        Ok(None)
+                    }
+                    None => Ok(Some(self.body(&fun, child, call))),
+                }
+            }
+            callee @ (Value::Builtin(_) | Value::Operator(_)) => {
+                let args: Vec<_> = receiver.into_iter().collect();
+                match call.arg(args.len()) {
+                    Some(arg) => {
+                        let kind = FrameKind::Collect { callee, args };
+                        Ok(Some(Frame::new(arg.clone(), scope.share(), kind)))
+                    }
+                    None => {
+                        stack.push(apply(callee, args, scope, call)?);
+                        Ok(None)
+                    }
+                }
+            }
+            v => Err(Error::runtime(RuntimeErrorKind::NotCallable(v.to_string())).at(&call.loc)),
+        }
+    }
+
+    fn body(&mut self, fun: &Function, scope: Scope, call: &CallOp) -> Frame {
+        let kind = FrameKind::Body {
+            name: call.name.value,
+            loc: call.loc.clone(),
+        };
+
+        Frame::new(self.compile_body(fun), scope, kind)
+    }
+
+    /// Hands the result of the innermost frame to the frame below it, unwinding
+    /// through frames while it is an error. Gives the result of the task once the
+    /// top frame has finished.
+    fn finish(
+        &mut self,
+        frames: &mut Vec<Frame>,
+        mut result: Result<Value, Error>,
+    ) -> Option<Result<Value, Error>> {
+        loop {
+            let frame = frames.pop().expect("task should not be finished");
+            let mut scope = frame.scope;
+
+            result = match (frame.kind, result) {
+                (FrameKind::Top, result) => return Some(Value::eval_deferred(result, &mut scope)),
+                (FrameKind::Body { name, loc }, result) => {
+                    Value::eval_deferred(result, &mut scope).map_err(|e| e.in_function(name, &loc))
+                }
+                (FrameKind::Block, result) => Value::eval_deferred(result, &mut scope),
+                (FrameKind::Argument { fun, index }, Ok(v)) => {
+                    if let Some(param) = fun.params.get(index) {
+                        scope.set(param, &v);
+                    }
+
+                    let chunk = frames
+                        .last()
+                        .expect("call should have a caller")
+                        .chunk
+                        .clone();
+                    let call = current_call(frames, &chunk);
+                    let next = match call.arg(index + 1) {
+                        Some(arg) => Frame::new(
+                            arg.clone(),
+                            scope,
+                            FrameKind::Argument {
+                                fun,
+                                index: index + 1,
+                            },
+                        ),
+                        None if fun.generator => {
+                            let caller = frames.last_mut().expect("call should have a caller");
+                            caller.stack.push(generator(&fun, scope, call));
+
+                            return None;
+                        }
+                        None => self.body(&fun, scope, call),
+                    };
+                    frames.push(next);
+
+                    return None;
+                }
+                (FrameKind::Collect { callee, mut args }, Ok(v)) => {
+                    args.push(v);
+
+                    let chunk = frames
+                        .last()
+                        .expect("call should have a caller")
+                        .chunk
+                        .clone();
+                    let call = current_call(frames, &chunk);
+                    match call.arg(args.len()) {
+                        Some(arg) => {
+                            let next = arg.clone();
+                            frames.push(Frame::new(
+                                next,
+                                scope,
+                                FrameKind::Collect { callee, args },
+                            ));
+
+                            return None;
+                        }
+                        None => apply(callee, args, &mut scope, call),
+                    }
+                }
+                (FrameKind::Message { loc }, Ok(message)) => {
+                    Err(Value::assertion_failed(Some(message), &loc))
+                }
+                (_, Err(e)) => Err(e),
+            };
+
+            if let Ok(v) = result {
+                let caller = frames.last_mut().expect("frame should have a caller");
+                caller.stack.push(v);
+
+                return None;
+            }
+        }
+    }
+
+    pub(super) fn compile_body(&mut self, fun: &Function) -> Shared<Chunk> {
+        if let Some(chunk) = self.functions.get(&fun.body_id) {
+            return chunk.clone();
+        }
+
+        if self.functions.len() >= MAX_FUNCTIONS {
+            self.functions.clear();
+        }
+        let chunk = compiler::compile(&fun.body);
+        self.functions.insert(fun.body_id, chunk.clone());
+
+        chunk
+    }
+}
+
+/// Gives the call the innermost frame, whose chunk is `chunk`, is paused on.
+fn current_call<'a>(frames: &[Frame], chunk: &'a Chunk) -> &'a CallOp {
+    let frame = frames.last().expect("call should have a caller");
+    match &chunk.code[frame.pc - 1] {
+        Op::Call(call) => call,
+        op => unreachable!("frame is paused on {op:?} rather than a call"),
+    }
+}
+
+/// Creates the iterator given by calling a generator function, whose arguments
+/// have been bound in `scope`.
+fn generator(fun: &Function, scope: Scope, call: &CallOp) -> Value {
+    Value::Iterator(Iter::generator(
+        fun,
+        scope,
+        Some((call.name.value, &call.loc)),
+    ))
+}
+
+/// Calls a builtin or operator with arguments that have already been evaluated.
+fn apply(
+    callee: Value,
+    args: Vec<Value>,
+    scope: &mut Scope,
+    call: &CallOp,
+) -> Result<Value, Error> {
+    match callee {
+        Value::Builtin(b) => b
+            .call(args, scope)
+            .and_then(|v| scope.track(v))
+            .map_err(|e| e.in_builtin(b.name, &call.loc)),
+        Value::Operator(kind) if args.is_empty() => Ok(Value::Operator(kind)),
+        Value::Operator(kind) => scope
+            .apply_operator(kind, args)
+            .and_then(|v| scope.track(v))
+            .map_err(|e| e.at(&call.loc)),
+        _ => unreachable!("only builtins and operators are applied"),
+    }
+}
+
+/// A future that is pending the first time it is polled, so that the executor
+/// can run other tasks before polling it again.
+#[derive(Debug, Default)]
+pub(super) struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+fn top(stack: &[Value]) -> &Value {
+    stack.last().expect("stack should not be empty")
+}
+
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().expect("stack should not be empty")
+}
+
+fn pop_n(stack: &mut Vec<Value>, n: usize) -> Vec<Value> {
+    stack.split_off(stack.len() - n)
+}
@@ -0,0 +1,123 @@
+use crate::{
+    lexer::token::Location,
+    parser::ast::{Expression, Function, Identifier, OperatorKind, Primitive, Statement},
+    sync::Shared,
+};
+
+/// A desugared form of the AST for backends and analyses to work on. Statements
+/// are expressions that produce a value, blocks are explicit, and `if` statements
+/// become a single conditional form that always has an else branch.
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Constant(Primitive),
+    Load(Identifier),
+    /// Sets a variable, producing its new value.
+    Store(Identifier, Box<Expr>),
+    Operator(OperatorKind, Vec<Expr>, Location),
+    Function(Function),
+    Call(Box<Call>),
+    List(Vec<Expr>),
+    /// Evaluates all the arguments, then checks if all (or any) of them are
+    /// truthy, giving a boolean.
+    Logic(LogicKind, Vec<Expr>),
+    Cond(Box<Cond>),
+    Assert(Box<Assert>),
+    /// Runs the statements when the enclosing function or program finishes.
+    Defer(Vec<Statement>),
+    /// Pauses a generator, giving the value to whoever asked for the next one.
+    Yield(Box<Expr>),
+    /// Evaluates the expressions in order, producing the value of the last one,
+    /// or `()` if there are none.
+    Block(Vec<Expr>),
+    /// Evaluates the statements of a block expression in a new scope, producing
+    /// the value of the last one and running what they defer when they finish.
+    Scoped(Shared<[Statement]>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogicKind {
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub name: Identifier,
+    pub args: Vec<Expr>,
+    /// Whether the only argument is a literal `()`.
+    pub unit: bool,
+    /// Whether the call is skipped, giving null, if the first argument is null.
+    pub null_safe: bool,
+    pub loc: Location,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cond {
+    pub condition: Expr,
+    pub then: Expr,
+    pub otherwise: Expr,
+    pub loc: Location,
+}
+
+#[derive(Clone, Debug)]
+pub struct Assert {
+    pub condition: Expr,
+    pub message: Option<Expr>,
+    pub loc: Location,
+}
+
+/// Lowers a list of statements into a block.
+pub fn lower(statements: &[Statement]) -> Expr {
+    Expr::Block(statements.iter().map(lower_statement).collect())
+}
+
+fn lower_statement(stmt: &Statement) -> Expr {
+    match stmt {
+        Statement::Assign(a) => Expr::Store(a.name.clone(), Box::new(lower_expression(&a.value))),
+        Statement::If(i) => Expr::Cond(Box::new(Cond {
+            condition: lower_expression(&i.condition),
+            then: Expr::Block(i.consequence.iter().map(|s| lower_statement(s)).collect()),
+            otherwise: match &i.alternative {
+                Some(alternative) => {
+                    Expr::Block(alternative.iter().map(|s| lower_statement(s)).collect())
+                }
+                None => Expr::Constant(Primitive::Null),
+            },
+            loc: i.loc.clone(),
+        })),
+        Statement::Assert(a) => Expr::Assert(Box::new(Assert {
+            condition: lower_expression(&a.condition),
+            message: a.message.as_ref().map(lower_expression),
+            loc: a.loc.clone(),
+        })),
+        Statement::Defer(d) => Expr::Defer(d.body.clone()),
+        Statement::Yield(y) => Expr::Yield(Box::new(lower_expression(&y.value))),
+        Statement::Expression(e) => lower_expression(e),
+    }
+}
+
+pub fn lower_expression(expr: &Expression) -> Expr {
+    match expr {
+        Expression::Primitive(p, ..) => Expr::Constant(p.clone()),
+        Expression::Identifier(i) => Expr::Load(i.clone()),
+        Expression::Operator(op) => Expr::Operator(
+            op.kind.clone(),
+            op.args.iter().map(lower_expression).collect(),
+            op.loc.clone(),
+        ),
+        Expression::Function(f) => Expr::Function(f.clone()),
+        Expression::Block(b) => Expr::Scoped(b.body.clone()),
+        Expression::Call(c) => Expr::Call(Box::new(Call {
+            name: c.name.clone(),
+            args: c.args.iter().map(lower_expression).collect(),
+            unit: matches!(c.args[..], [Expression::Primitive(Primitive::Null, ..)]),
+            null_safe: c.null_safe,
+            loc: c.loc.clone(),
+        })),
+        Expression::List(items, ..) => Expr::List(items.iter().map(lower_expression).collect()),
+        Expression::And(a) => {
+            Expr::Logic(LogicKind::And, a.0.iter().map(lower_expression).collect())
+        }
+        Expression::Or(o) => Expr::Logic(LogicKind::Or, o.0.iter().map(lower_expression).collect()),
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug, Formatter},
+    io::{self, BufRead, ErrorKind},
+    str::Chars,
+};
+
+/// The characters that a lexer reads, from a string or a reader, with the ones
+/// it has looked ahead at kept until they're read.
+pub(super) struct Input<'a> {
+    source: Source<'a>,
+    ahead: VecDeque<char>,
+    error: Option<io::Error>,
+    /// How many bytes have been read, to stop at the limit.
+    read: usize,
+    limit: Option<usize>,
+    over_limit: bool,
+}
+
+enum Source<'a> {
+    Str { source: &'a str, chars: Chars<'a> },
+    Reader(Box<dyn BufRead + 'a>),
+}
+
+impl<'a> Input<'a> {
+    pub fn from_str(input: &'a str) -> Self {
+        Self {
+            source: Source::Str {
+                source: input,
+                chars: input.chars(),
+            },
+            ahead: VecDeque::new(),
+            error: None,
+            read: 0,
+            limit: None,
+            over_limit: false,
+        }
+    }
+
+    pub fn from_reader(reader: impl BufRead + 'a) -> Self {
+        Self {
+            source: Source::Reader(Box::new(reader)),
+            ahead: VecDeque::new(),
+            error: None,
+            read: 0,
+            limit: None,
+            over_limit: false,
+        }
+    }
+
+    /// Stops reading after `limit` bytes, as if the input ended there.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// Whether reading stopped at the limit rather than at the end of the input.
+    pub fn is_over_limit(&self) -> bool {
+        self.over_limit
+    }
+
+    pub fn peek(&mut self) -> Option<char> {
+        self.peek_nth(0)
+    }
+
+    /// Gets the character `n` characters after the next one without reading it.
+    pub fn peek_nth(&mut self, n: usize) -> Option<char> {
+        while self.ahead.len() <= n {
+            let c = self.read()?;
+            self.ahead.push_back(c);
+        }
+
+        self.ahead.get(n).copied()
+    }
+
+    pub fn next(&mut self) -> Option<char> {
+        match self.ahead.pop_front() {
+            Some(c) => Some(c),
+            None => self.read(),
+        }
+    }
+
+    /// Reads the start of the rest of a string all at once, including what has
+    /// been looked ahead at, up to the length in bytes that `find` gives for the
+    /// rest, which has to be at a character. This is much faster than reading one
+    /// character at a time, but readers can only be read that way, so for them it
+    /// reads nothing.
+    pub fn take_str(&mut self, find: impl FnOnce(&'a str) -> usize) -> Option<&'a str> {
+        let Source::Str { source, chars } = &mut self.source else {
+            return None;
+        };
+
+        let ahead: usize = self.ahead.iter().map(|c| c.len_utf8()).sum();
+        let start = source.len() - chars.as_str().len() - ahead;
+        let rest = &source[start..];
+        let mut len = find(rest);
+        if let Some(limit) = self.limit {
+            len = len.min(limit.saturating_sub(start));
+            while !rest.is_char_boundary(len) {
+                len -= 1;
+            }
+        }
+
+        *chars = rest[len..].chars();
+        self.ahead.clear();
+        self.read = start + len;
+        Some(&rest[..len])
+    }
+
+    /// Moves to the byte at `offset` in a string, failing for readers, which
+    /// can't go back, and for offsets that aren't at a character.
+    pub fn seek(&mut self, offset: usize) -> bool {
+        let Source::Str { source, chars } = &mut self.source else {
+            return false;
+        };
+        let Some(rest) = source.get(offset..) else {
+            return false;
+        };
+
+        *chars = rest.chars();
+        self.ahead.clear();
+        self.read = offset;
+        self.over_limit = false;
+        true
+    }
+
+    /// Takes the error that stopped the reader, if reading failed rather than
+    /// reaching the end of the input.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    fn read(&mut self) -> Option<char> {
+        let c = self.decode()?;
+        self.read += c.len_utf8();
+        if self.limit.is_some_and(|limit| self.read > limit) {
+            self.over_limit = true;
+            return None;
+        }
+
+        Some(c)
+    }
+
+    fn decode(&mut self) -> Option<char> {
+        if self.over_limit {
+            return None;
+        }
+        let reader = match &mut self.source {
+            Source::Str { chars, .. } => return chars.next(),
+            Source::Reader(reader) => reader,
+        };
+        if self.error.is_some() {
+            return None;
+        }
+
+        let mut bytes = [0; 4];
+        if let Err(e) = reader.read_exact(&mut bytes[..1]) {
+            if e.kind() != ErrorKind::UnexpectedEof {
+                self.error = Some(e);
+            }
+            return None;
+        }
+
+        // invalid UTF-8 is read as replacement characters, like from_utf8_lossy
+        let width = match bytes[0] {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Some(char::REPLACEMENT_CHARACTER),
+        };
+        if let Err(e) = reader.read_exact(&mut bytes[1..width]) {
+            if e.kind() != ErrorKind::UnexpectedEof {
+                self.error = Some(e);
+            }
+            return Some(char::REPLACEMENT_CHARACTER);
+        }
+
+        let c = std::str::from_utf8(&bytes[..width])
+            .ok()
+            .and_then(|s| s.chars().next());
+        Some(c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+}
+
+impl Debug for Input<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let source = match self.source {
+            Source::Str { .. } => "string",
+            Source::Reader(_) => "reader",
+        };
+
+        f.debug_struct("Input")
+            .field("source", &source)
+            .field("ahead", &self.ahead)
+            .finish()
+    }
+}
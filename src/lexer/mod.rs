@@ -1,298 +1,650 @@
-use self::token::{Location, Token, TokenValue};
-use std::{iter::Peekable, str::Chars};
+use self::{
+    input::Input,
+    token::{Location, Token, TokenValue},
+};
+use crate::error::Error;
+use memchr::{memchr, memchr2, memchr3};
+use std::io::{BufReader, Read};
 
+mod input;
 pub mod token;
 
+/// A saved position of a [`Lexer`] between two tokens, to go back to with
+/// [`Lexer::rewind`] or to carry on from with [`Lexer::resume`] or
+/// [`Lexer::after`].
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    loc: Location,
+    header: bool,
+    errors: usize,
+    tokens: usize,
+}
+
 #[derive(Debug)]
 pub struct Lexer<'a> {
-    input: Peekable<Chars<'a>>,
+    input: Input<'a>,
     loc: Location,
+    comments: bool,
+    trivia: bool,
+    /// Whether only newlines, comments and pragmas have been lexed so far, which
+    /// is where `#!` lines are pragmas.
+    header: bool,
+    done: bool,
+    errors: Vec<Error>,
+    /// How many tokens have been given, to stop at the limit.
+    tokens: usize,
+    max_input_len: Option<usize>,
+    max_string_len: Option<usize>,
+    max_tokens: Option<usize>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
+        Self::with_input(Input::from_str(input))
+    }
+
+    /// Creates a lexer that reads its input as it goes, such as from a file or
+    /// stdin, instead of needing all of it in memory first. The reader is
+    /// buffered, invalid UTF-8 is read as replacement characters, and an error
+    /// from the reader ends the input with a lex error.
+    pub fn from_reader(reader: impl Read + 'a) -> Self {
+        Self::with_input(Input::from_reader(BufReader::new(reader)))
+    }
+
+    /// Creates a lexer for `input` that starts from a checkpoint of a lexer for
+    /// the start of it, such as a REPL's buffer that has had more typed into it,
+    /// so only the new part is lexed. The tokens before the checkpoint have to
+    /// stay the same, so it should be taken where the input can't change what
+    /// they are, like after a newline. Errors from before the checkpoint aren't
+    /// given again.
+    pub fn resume(input: &'a str, checkpoint: &Checkpoint) -> Self {
+        let mut lexer = Self::new(input);
+        lexer.rewind(checkpoint);
+        lexer.errors.clear();
+
+        lexer
+    }
+
+    /// Creates a lexer for a new input that was added to the end of `input` after
+    /// a checkpoint taken at its end, such as the next entry typed into a REPL.
+    /// Its locations carry on from those before the checkpoint, but otherwise it's
+    /// lexed as if it were on its own, so it can start with pragmas.
+    pub fn after(input: &'a str, checkpoint: &Checkpoint) -> Self {
+        let mut lexer = Self::resume(input, checkpoint);
+        lexer.header = true;
+        lexer.tokens = 0;
+
+        lexer
+    }
+
+    fn with_input(input: Input<'a>) -> Self {
         Self {
-            input: input.chars().peekable(),
-            loc: Location::new(0, 0),
+            input,
+            loc: Location::default(),
+            comments: false,
+            trivia: false,
+            header: true,
+            done: false,
+            errors: Vec::new(),
+            tokens: 0,
+            max_input_len: None,
+            max_string_len: None,
+            max_tokens: None,
         }
     }
 
+    /// Sets whether comments are kept as `Comment` tokens instead of being
+    /// skipped, which the parser attaches to the statements around them.
+    pub fn keep_comments(&mut self, keep: bool) {
+        self.comments = keep;
+    }
+
+    /// Sets whether trivia, which is comments and whitespace, is kept as tokens,
+    /// for tools like syntax highlighters that need to rebuild the source from
+    /// them. The spans of the tokens then cover the whole input, apart from the
+    /// invalid characters in [`Lexer::errors`]. The parser skips whitespace
+    /// tokens and attaches comments as usual.
+    pub fn keep_trivia(&mut self, keep: bool) {
+        self.trivia = keep;
+    }
+
+    /// Sets how many bytes of input can be read. Lexing stops with an error at
+    /// the limit, without reading any more, so a huge input fails quickly
+    /// instead of using up memory. There's no limit by default.
+    pub fn set_max_input_len(&mut self, max: Option<usize>) {
+        self.max_input_len = max;
+        self.input.set_limit(max);
+    }
+
+    /// Sets how many bytes a string literal can be before lexing stops with an
+    /// error. There's no limit by default.
+    pub fn set_max_string_len(&mut self, max: Option<usize>) {
+        self.max_string_len = max;
+    }
+
+    /// Sets how many tokens can be lexed, not counting the EOF token, before
+    /// lexing stops with an error. There's no limit by default.
+    pub fn set_max_tokens(&mut self, max: Option<usize>) {
+        self.max_tokens = max;
+    }
+
+    /// Lexes the whole input, ending with an EOF token. Iterating over the lexer
+    /// gives the same tokens one at a time instead.
     pub fn lex(&mut self) -> Vec<Token> {
-        let mut res = Vec::new();
+        self.collect()
+    }
+
+    /// Saves where the lexer is, which is just after the last token it gave.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            loc: self.loc.clone(),
+            header: self.header,
+            errors: self.errors.len(),
+            tokens: self.tokens,
+        }
+    }
+
+    /// Goes back to a checkpoint, forgetting the errors found since, so that the
+    /// tokens after it are lexed again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lexer reads from a reader, which can't go back, or if the
+    /// checkpoint isn't in the input.
+    pub fn rewind(&mut self, checkpoint: &Checkpoint) {
+        assert!(
+            self.input.seek(checkpoint.loc.end as usize),
+            "cannot rewind a lexer to {}",
+            checkpoint.loc
+        );
+
+        self.loc = checkpoint.loc.clone();
+        self.header = checkpoint.header;
+        self.done = false;
+        self.errors.truncate(checkpoint.errors);
+        self.tokens = checkpoint.tokens;
+    }
+
+    /// Gets the errors for the parts of the input that aren't valid tokens, in
+    /// the order they were found. Lexing skips past them, so the tokens around
+    /// them are still given.
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn lex_token(&mut self) -> Option<Token> {
+        if self.done {
+            return None;
+        }
 
         loop {
             match self.input.peek() {
-                Some(&c) => match c {
+                Some(c) => match c {
+                    ' ' | '\t' if self.trivia => {
+                        let mut text = String::new();
+                        if let Some(run) =
+                            self.advance_run(|s| run_of(s, |b| b == b' ' || b == b'\t'))
+                        {
+                            text.push_str(run);
+                        }
+                        while let Some(c @ (' ' | '\t')) = self.input.peek() {
+                            text.push(c);
+                            self.advance();
+                        }
+                        return Some(Token::new(TokenValue::Whitespace(text), self.loc()));
+                    }
                     ' ' | '\t' => {
-                        self.next();
-                        self.loc.col_start = self.loc.col_stop;
-                    }
-                    '\r' => {
-                        if let Some(c) = self.input.next() {
-                            if c == '\n' {
-                                res.push(Token::new(TokenValue::Newline, self.loc()));
-                                self.next();
-                                self.loc.line_start += 1;
-                                self.loc.col_stop = 0;
-                            }
+                        if self
+                            .advance_run(|s| run_of(s, |b| b == b' ' || b == b'\t'))
+                            .is_none()
+                        {
+                            self.advance();
                         }
+                        self.skip();
                     }
-                    '\n' => {
-                        res.push(Token::new(TokenValue::Newline, self.loc()));
-                        self.next();
-                        self.loc.line_start += 1;
+                    // a byte order mark is skipped without taking up a column
+                    '\u{feff}' if self.loc.end == 0 => {
+                        self.advance();
                         self.loc.col_stop = 0;
+                        if self.trivia {
+                            return Some(Token::new(
+                                TokenValue::Whitespace(c.to_string()),
+                                self.loc(),
+                            ));
+                        }
+                        self.skip();
+                    }
+                    '\r' | '\n' => {
+                        self.line_break();
+                        return Some(self.newline());
                     }
                     ';' => {
-                        res.push(Token::new(TokenValue::Semicolon, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::Semicolon, self.loc()));
                     }
-                    '#' => loop {
-                        match self.input.peek() {
-                            Some(&c) => {
-                                if c == '\n' {
-                                    break;
-                                } else {
-                                    self.next();
-                                }
-                            }
-                            None => {
-                                res.push(Token::new(TokenValue::EOF, self.loc()));
+                    ',' => {
+                        self.advance();
+                        return Some(Token::new(TokenValue::Comma, self.loc()));
+                    }
+                    '#' => {
+                        let mut text = String::new();
+                        self.advance();
+                        if let Some(run) = self
+                            .advance_run(|s| memchr2(b'\r', b'\n', s.as_bytes()).unwrap_or(s.len()))
+                        {
+                            text.push_str(run);
+                        }
+                        while let Some(c) = self.input.peek() {
+                            if c == '\r' || c == '\n' {
                                 break;
                             }
+                            text.push(c);
+                            self.advance();
+                        }
+
+                        let loc = self.loc();
+                        // `#!` lines are pragmas until the first other token
+                        match text.strip_prefix('!') {
+                            Some(pragma) if self.header => {
+                                return Some(Token::new(
+                                    TokenValue::Pragma(pragma.trim().to_string()),
+                                    loc,
+                                ))
+                            }
+                            _ if self.comments || self.trivia => {
+                                return Some(Token::new(TokenValue::Comment(text), loc))
+                            }
+                            _ => {}
                         }
-                    },
+                    }
                     '(' => {
-                        res.push(Token::new(TokenValue::LeftParen, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::LeftParen, self.loc()));
                     }
                     ')' => {
-                        res.push(Token::new(TokenValue::RightParen, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::RightParen, self.loc()));
                     }
                     '[' => {
-                        res.push(Token::new(TokenValue::LeftBracket, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::LeftBracket, self.loc()));
                     }
                     ']' => {
-                        res.push(Token::new(TokenValue::RightBracket, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::RightBracket, self.loc()));
                     }
                     '{' => {
-                        res.push(Token::new(TokenValue::BlockStart, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::BlockStart, self.loc()));
                     }
                     '}' => {
-                        res.push(Token::new(TokenValue::BlockEnd, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::BlockEnd, self.loc()));
                     }
                     '=' => {
-                        self.next();
-                        match self.input.peek() {
-                            Some(&c) => {
-                                if c == '=' {
-                                    res.push(Token::new(TokenValue::Equal, self.loc()));
-                                    self.next();
-                                } else {
-                                    res.push(Token::new(TokenValue::Assign, self.loc()));
-                                }
-                            }
-                            None => {
-                                res.push(Token::new(TokenValue::Assign, self.loc()));
-                            }
+                        self.advance();
+                        if let Some('=') = self.input.peek() {
+                            self.advance();
+                            return Some(Token::new(TokenValue::Equal, self.loc()));
+                        } else {
+                            return Some(Token::new(TokenValue::Assign, self.loc()));
                         }
                     }
                     '<' => {
-                        self.next();
-                        match self.input.peek() {
-                            Some(&c) => {
-                                if c == '=' {
-                                    res.push(Token::new(TokenValue::LessEqual, self.loc()));
-                                    self.next();
-                                } else {
-                                    res.push(Token::new(TokenValue::Less, self.loc()));
-                                }
-                            }
-                            None => {
-                                res.push(Token::new(TokenValue::Less, self.loc()));
-                            }
+                        self.advance();
+                        if let Some('=') = self.input.peek() {
+                            self.advance();
+                            return Some(Token::new(TokenValue::LessEqual, self.loc()));
+                        } else {
+                            return Some(Token::new(TokenValue::Less, self.loc()));
                         }
                     }
                     '>' => {
-                        self.next();
-                        match self.input.peek() {
-                            Some(&c) => {
-                                if c == '=' {
-                                    res.push(Token::new(TokenValue::GreaterEqual, self.loc()));
-                                    self.next();
-                                } else {
-                                    res.push(Token::new(TokenValue::Greater, self.loc()));
-                                }
-                            }
-                            None => {
-                                res.push(Token::new(TokenValue::Greater, self.loc()));
-                            }
+                        self.advance();
+                        if let Some('=') = self.input.peek() {
+                            self.advance();
+                            return Some(Token::new(TokenValue::GreaterEqual, self.loc()));
+                        } else {
+                            return Some(Token::new(TokenValue::Greater, self.loc()));
                         }
                     }
                     '+' => {
-                        res.push(Token::new(TokenValue::Plus, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::Plus, self.loc()));
                     }
                     '-' => {
-                        res.push(Token::new(TokenValue::Minus, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::Minus, self.loc()));
                     }
                     '*' => {
-                        res.push(Token::new(TokenValue::Asterisk, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::Asterisk, self.loc()));
                     }
                     '/' => {
-                        res.push(Token::new(TokenValue::Slash, self.loc()));
-                        self.next();
+                        self.advance();
+                        if let Some('/') = self.input.peek() {
+                            self.advance();
+                            return Some(Token::new(TokenValue::DoubleSlash, self.loc()));
+                        } else {
+                            return Some(Token::new(TokenValue::Slash, self.loc()));
+                        }
                     }
                     '&' => {
-                        self.next();
-                        match self.input.peek() {
-                            Some(&c) => {
-                                if c == '&' {
-                                    res.push(Token::new(TokenValue::And, self.loc()));
-                                    self.next();
-                                } else {
-                                    res.push(Token::new(
-                                        TokenValue::Illegal("unexpected: &".to_string()),
-                                        self.loc(),
-                                    ));
-                                }
-                            }
-                            None => {
-                                res.push(Token::new(
-                                    TokenValue::Illegal("unexpected: &".to_string()),
-                                    self.loc(),
-                                ));
-                            }
+                        self.advance();
+                        if let Some('&') = self.input.peek() {
+                            self.advance();
+                            return Some(Token::new(TokenValue::And, self.loc()));
                         }
+                        self.error("unexpected character &; did you mean &&?");
                     }
                     '|' => {
-                        self.next();
-                        match self.input.peek() {
-                            Some(&c) => {
-                                if c == '|' {
-                                    res.push(Token::new(TokenValue::Or, self.loc()));
-                                    self.next();
-                                } else {
-                                    res.push(Token::new(
-                                        TokenValue::Illegal("unexpected: |".to_string()),
-                                        self.loc(),
-                                    ));
-                                }
-                            }
-                            None => {
-                                res.push(Token::new(
-                                    TokenValue::Illegal("unexpected: |".to_string()),
-                                    self.loc(),
-                                ));
-                            }
+                        self.advance();
+                        if let Some('|') = self.input.peek() {
+                            self.advance();
+                            return Some(Token::new(TokenValue::Or, self.loc()));
                         }
+                        self.error("unexpected character |; did you mean ||?");
+                    }
+                    '.' => {
+                        self.advance();
+                        return Some(Token::new(TokenValue::Dot, self.loc()));
+                    }
+                    '?' if self.input.peek_nth(1) == Some('.') => {
+                        self.advance();
+                        self.advance();
+                        return Some(Token::new(TokenValue::QuestionDot, self.loc()));
                     }
                     '!' => {
-                        res.push(Token::new(TokenValue::Bang, self.loc()));
-                        self.next();
+                        self.advance();
+                        return Some(Token::new(TokenValue::Bang, self.loc()));
                     }
-                    '0'..='9' => res.push(self.lex_int_or_float()),
-                    '"' => res.push(self.lex_string()),
-                    'a'..='z' | 'A'..='Z' | '_' => res.push(self.lex_ident()),
+                    '$' => {
+                        self.advance();
+                        if let Some('(') = self.input.peek() {
+                            self.advance();
+                            return Some(Token::new(TokenValue::InfixStart, self.loc()));
+                        }
+                        self.error("unexpected character $; infix expressions start with $(");
+                    }
+                    '0'..='9' => return Some(self.lex_int_or_float()),
+                    '"' if self.input.peek_nth(1) == Some('"')
+                        && self.input.peek_nth(2) == Some('"') =>
+                    {
+                        return Some(self.lex_text_block())
+                    }
+                    '"' => return Some(self.lex_string()),
+                    'a'..='z' | 'A'..='Z' | '_' => return Some(self.lex_ident()),
                     _ => {
-                        res.push(Token::new(
-                            TokenValue::Illegal(format!("unexpected: {c}")),
-                            self.loc(),
-                        ));
-                        self.next();
+                        self.advance();
+                        self.error(format!("unexpected character {c}"));
                     }
                 },
+                None if let Some(e) = self.input.take_error() => {
+                    self.error(format!("error reading input: {e}"));
+                }
+                None if self.input.is_over_limit() => {
+                    let max = self.max_input_len.unwrap_or_default();
+                    return Some(
+                        self.stop(format!("input is longer than the limit of {max} bytes")),
+                    );
+                }
                 None => {
-                    res.push(Token::new(TokenValue::EOF, self.loc()));
-                    break;
+                    self.done = true;
+                    return Some(Token::new(TokenValue::EOF, self.loc()));
                 }
             }
         }
+    }
+
+    /// Reads a line break, which is `\n`, `\r\n` or a lone `\r`, so that files
+    /// written on any platform are lexed the same.
+    fn line_break(&mut self) {
+        let c = self.input.peek();
+        self.advance();
+        if c == Some('\r') && self.input.peek() == Some('\n') {
+            self.advance();
+        }
+    }
+
+    fn newline(&mut self) -> Token {
+        let token = Token::new(TokenValue::Newline, self.loc());
+        self.loc.line_start += 1;
+        self.loc.col_start = 0;
+        self.loc.col_stop = 0;
 
-        res
+        token
     }
 
     fn lex_int_or_float(&mut self) -> Token {
         let mut value = String::new();
         let mut float = false;
 
-        while let Some(&c) = self.input.peek() {
+        while let Some(c) = self.input.peek() {
             match c {
-                '0'..='9' => {
-                    value.push(c);
-                    self.next();
-                }
-                '_' => continue,
-                '.' => {
+                '0'..='9' => match self.advance_run(|s| run_of(s, |b| b.is_ascii_digit())) {
+                    Some(run) => value.push_str(run),
+                    None => {
+                        value.push(c);
+                        self.advance();
+                    }
+                },
+                // underscores can separate digits, like in `1_000`
+                '_' if matches!(self.input.peek_nth(1), Some('0'..='9')) => self.advance(),
+                '.' if matches!(self.input.peek_nth(1), Some('0'..='9')) => {
+                    // the number ends at a second point, which is skipped
                     if float {
-                        self.next();
-                        return Token::new(
-                            TokenValue::Illegal(format!("unexpected: {c}")),
-                            self.loc(),
-                        );
+                        let token = Token::new(TokenValue::Float(value), self.loc());
+                        self.advance();
+                        self.error("unexpected character . in number");
+                        return token;
                     }
                     float = true;
                     value.push('.');
-                    self.next();
+                    self.advance();
                 }
                 _ => break,
             }
         }
 
-        if float {
-            Token::new(TokenValue::Float(value), self.loc())
-        } else {
-            Token::new(TokenValue::Integer(value), self.loc())
+        // a suffix forces the type of the number. An invalid one is an error, but
+        // the number is still given so that the statement it's in carries on.
+        let mut suffix = String::new();
+        while let Some(c @ ('a'..='z' | 'A'..='Z' | '0'..='9' | '_')) =
+            self.input.peek_nth(suffix.len())
+        {
+            suffix.push(c);
         }
+        for _ in 0..suffix.len() {
+            self.advance();
+        }
+        let loc = self.loc();
+
+        let name = suffix
+            .strip_prefix('_')
+            .filter(|name| !name.is_empty())
+            .unwrap_or(&suffix);
+        let (value, error) = match name {
+            "" if float => (TokenValue::Float(value), None),
+            "" => (TokenValue::Integer(value), None),
+            // a number like `1.0i` is only an integer if its fraction is zero
+            "i" | "int" | "i64" => match value.split_once('.') {
+                Some((whole, fraction)) if fraction.bytes().all(|b| b == b'0') => {
+                    (TokenValue::Integer(whole.to_string()), None)
+                }
+                Some((whole, _)) => (
+                    TokenValue::Integer(whole.to_string()),
+                    Some(format!("integer literal {} is not a whole number", value)),
+                ),
+                None => (TokenValue::Integer(value), None),
+            },
+            "f" | "float" | "f64" => (TokenValue::Float(value), None),
+            #[cfg(feature = "decimal")]
+            "d" | "dec" => (TokenValue::Decimal(value), None),
+            #[cfg(not(feature = "decimal"))]
+            "d" | "dec" => (
+                TokenValue::Float(value),
+                Some("decimal literals need the decimal feature".to_string()),
+            ),
+            _ => (
+                if float {
+                    TokenValue::Float(value)
+                } else {
+                    TokenValue::Integer(value)
+                },
+                Some(format!("invalid suffix {} on number", suffix)),
+            ),
+        };
+        if let Some(message) = error {
+            self.errors.push(Error::Lex {
+                message,
+                loc: loc.clone(),
+            });
+        }
+
+        Token::new(value, loc)
     }
 
     fn lex_string(&mut self) -> Token {
+        let (line, col) = (self.loc.line_start, self.loc.col_start);
         let mut string = String::new();
-        let mut escaped = false;
-        self.next();
+        self.advance();
+
+        let closed = loop {
+            if let Some(token) = self.string_over_limit(&string, line, col) {
+                return token;
+            }
+
+            // everything up to a quote, backslash or line break is read at once
+            if let Some(run) = self.advance_run(|s| {
+                let bytes = s.as_bytes();
+                let end = memchr3(b'"', b'\\', b'\n', bytes).unwrap_or(bytes.len());
+                memchr(b'\r', &bytes[..end]).unwrap_or(end)
+            }) {
+                string.push_str(run);
+            }
 
-        loop {
             match self.input.peek() {
-                Some(&c) => match c {
-                    '\\' => escaped = !escaped,
-                    '"' => {
-                        if escaped {
-                            escaped = false;
-                            continue;
+                Some(c) => match c {
+                    // `\"` and `\\` are a quote and a backslash, and other
+                    // backslashes are kept as they are
+                    '\\' => {
+                        self.advance();
+                        match self.input.peek() {
+                            Some(c @ ('"' | '\\')) => {
+                                string.push(c);
+                                self.advance();
+                            }
+                            _ => string.push('\\'),
                         }
-                        self.next();
-                        break Token::new(TokenValue::String(string), self.loc());
+                    }
+                    '"' => {
+                        self.advance();
+                        break true;
+                    }
+                    '\r' | '\n' => {
+                        self.line_break();
+                        string.push('\n');
+                        self.loc.line_start += 1;
+                        self.loc.col_stop = 0;
                     }
                     _ => {
                         string.push(c);
-                        self.next();
+                        self.advance();
                     }
                 },
-                None => {
-                    break Token::new(
-                        TokenValue::Illegal("unterminated quote string".to_string()),
-                        self.loc(),
-                    );
+                None => break false,
+            }
+        };
+
+        // the token starts on the line of its opening quote, and is kept when it
+        // isn't closed so the parser can carry on after it
+        let mut loc = self.loc();
+        loc.line_start = line;
+        loc.col_start = col;
+        if !closed {
+            self.errors.push(Error::Lex {
+                message: "unterminated quote string".to_string(),
+                loc: loc.clone(),
+            });
+        }
+
+        Token::new(TokenValue::String(string), loc)
+    }
+
+    /// Lexes a `"""` string, which can span lines and has no escapes. The text
+    /// is [dedented](dedent) so it can be indented along with the code around it.
+    fn lex_text_block(&mut self) -> Token {
+        let (line, col) = (self.loc.line_start, self.loc.col_start);
+        for _ in 0..3 {
+            self.advance();
+        }
+
+        let mut text = String::new();
+        let closed = loop {
+            if let Some(token) = self.string_over_limit(&text, line, col) {
+                return token;
+            }
+            if let Some(run) =
+                self.advance_run(|s| memchr3(b'"', b'\r', b'\n', s.as_bytes()).unwrap_or(s.len()))
+            {
+                text.push_str(run);
+            }
+
+            match self.input.peek() {
+                Some('"')
+                    if self.input.peek_nth(1) == Some('"')
+                        && self.input.peek_nth(2) == Some('"') =>
+                {
+                    for _ in 0..3 {
+                        self.advance();
+                    }
+                    break true;
+                }
+                Some('\r' | '\n') => {
+                    self.line_break();
+                    text.push('\n');
+                    self.loc.line_start += 1;
+                    self.loc.col_stop = 0;
                 }
+                Some(c) => {
+                    text.push(c);
+                    self.advance();
+                }
+                None => break false,
             }
+        };
+
+        // the token starts on the line of its opening quotes
+        let mut loc = self.loc();
+        loc.line_start = line;
+        loc.col_start = col;
+        if !closed {
+            self.errors.push(Error::Lex {
+                message: "unterminated triple quote string".to_string(),
+                loc: loc.clone(),
+            });
         }
+
+        Token::new(TokenValue::Text(dedent(&text)), loc)
     }
 
     fn lex_ident(&mut self) -> Token {
         let mut ident = String::new();
+        if let Some(run) =
+            self.advance_run(|s| run_of(s, |b| b.is_ascii_alphanumeric() || b == b'_'))
+        {
+            ident.push_str(run);
+        }
 
-        while let Some(&c) = self.input.peek() {
+        while let Some(c) = self.input.peek() {
             match c {
-                'a'..='z' | 'A'..='Z' | '_' => {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {
                     ident.push(c);
-                    self.next();
+                    self.advance();
                 }
                 _ => break,
             }
@@ -302,6 +654,9 @@ impl<'a> Lexer<'a> {
             "if" => TokenValue::If,
             "elif" => TokenValue::Elif,
             "else" => TokenValue::Else,
+            "assert" => TokenValue::Assert,
+            "defer" => TokenValue::Defer,
+            "yield" => TokenValue::Yield,
             "true" => TokenValue::True,
             "false" => TokenValue::False,
             _ => TokenValue::Ident(ident),
@@ -310,20 +665,142 @@ impl<'a> Lexer<'a> {
         Token::new(value, self.loc())
     }
 
-    fn next(&mut self) {
-        _ = self.input.next();
-        self.loc.col_stop += 1;
+    /// Ends the tokens early because a limit was reached, with an error for what
+    /// has just been read.
+    fn stop(&mut self, message: String) -> Token {
+        self.error(message);
+        self.done = true;
+        Token::new(TokenValue::EOF, self.loc())
+    }
+
+    /// Ends the tokens early if a string that starts at `line` and `col` is past
+    /// the limit.
+    fn string_over_limit(&mut self, string: &str, line: i32, col: i32) -> Option<Token> {
+        let max = self.max_string_len.filter(|&max| string.len() > max)?;
+        let mut loc = self.loc();
+        loc.line_start = line;
+        loc.col_start = col;
+        self.errors.push(Error::Lex {
+            message: format!("string is longer than the limit of {max} bytes"),
+            loc: loc.clone(),
+        });
+        self.done = true;
+
+        Some(Token::new(TokenValue::EOF, loc))
+    }
+
+    /// Records an error for what has just been read, which is skipped over.
+    fn error(&mut self, message: impl Into<String>) {
+        let loc = self.loc();
+        self.errors.push(Error::Lex {
+            message: message.into(),
+            loc,
+        });
+    }
+
+    /// Reads the characters up to the length in bytes that `find` gives for the
+    /// rest of the input all at once, which is much faster for long runs of them
+    /// than [`Lexer::advance`]. Nothing is read unless the input is a string, so
+    /// the characters still have to be read one at a time after this.
+    fn advance_run(&mut self, find: impl FnOnce(&'a str) -> usize) -> Option<&'a str> {
+        let run = self.input.take_str(find)?;
+        self.loc.end += run.len() as u32;
+        self.loc.col_stop += run.chars().count() as i32;
+
+        Some(run)
+    }
+
+    fn advance(&mut self) {
+        if let Some(c) = self.input.next() {
+            self.loc.end += c.len_utf8() as u32;
+            self.loc.col_stop += 1;
+        }
+    }
+
+    /// Moves the start of the next token past what has been read, such as
+    /// whitespace.
+    fn skip(&mut self) {
+        self.loc.start = self.loc.end;
+        self.loc.col_start = self.loc.col_stop;
     }
 
+    /// Gets the location of the token that has just been read, and starts the
+    /// next one after it.
     fn loc(&mut self) -> Location {
-        let loc = self.loc.clone();
         self.loc.line_stop = self.loc.line_start;
-        self.loc.col_start = self.loc.col_stop;
+        let loc = self.loc.clone();
+        self.skip();
 
         loc
     }
 }
 
+/// Gets the length of the bytes at the start of `s` that `f` is true for, which
+/// are all ASCII, so it's always at a character.
+fn run_of(s: &str, f: impl Fn(u8) -> bool) -> usize {
+    s.bytes()
+        .position(|b| !(b.is_ascii() && f(b)))
+        .unwrap_or(s.len())
+}
+
+/// Strips the indentation that every line of a `"""` string starts with, after
+/// dropping the line break right after the opening quotes and the last line if
+/// it's only the indentation of the closing ones. Lines that are only whitespace
+/// don't count towards the indentation and are left empty.
+fn dedent(text: &str) -> String {
+    let text = text.strip_prefix('\n').unwrap_or(text);
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    if lines.len() > 1 && lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| &l[..l.len() - l.trim_start().len()])
+        .reduce(|common, indent| {
+            let len = common
+                .char_indices()
+                .zip(indent.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(indent.len()), |((i, _), _)| i);
+            &common[..len]
+        })
+        .unwrap_or("");
+
+    lines
+        .iter()
+        .map(|l| match l.strip_prefix(indent) {
+            Some(rest) if !l.trim().is_empty() => rest,
+            _ => "",
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Gives the tokens one at a time as they're lexed, ending with an EOF token, so
+/// that the whole input doesn't have to be lexed before using them.
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.lex_token()?;
+        if token.value != TokenValue::EOF {
+            self.tokens += 1;
+            if let Some(max) = self.max_tokens.filter(|&max| self.tokens > max) {
+                return Some(self.stop(format!("input has more than {max} tokens")));
+            }
+        }
+        if !token.value.is_trivia()
+            && !matches!(token.value, TokenValue::Newline | TokenValue::Pragma(_))
+        {
+            self.header = false;
+        }
+
+        Some(token)
+    }
+}
+
 // #[cfg(test)]
 // mod test {
 //     use super::{Lexer, Token};
@@ -455,3 +932,325 @@ impl<'a> Lexer<'a> {
 //         );
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use super::{token::TokenValue, Lexer};
+
+    /// Lexes `source`, giving the values of the tokens and the messages of the
+    /// errors.
+    fn lex(source: &str) -> (Vec<TokenValue>, Vec<String>) {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.lex().into_iter().map(|t| t.value).collect();
+        let errors = lexer.take_errors().iter().map(|e| e.to_string()).collect();
+
+        (tokens, errors)
+    }
+
+    fn values(source: &str) -> Vec<TokenValue> {
+        let (tokens, errors) = lex(source);
+        assert!(errors.is_empty(), "{:?} has errors: {:?}", source, errors);
+
+        tokens
+    }
+
+    fn text(value: &str) -> TokenValue {
+        TokenValue::Text(value.to_string())
+    }
+
+    /// Gets where each token starts, as its byte offset, line and column.
+    fn starts(source: &str) -> Vec<(u32, i32, i32)> {
+        Lexer::new(source)
+            .lex()
+            .iter()
+            .map(|t| (t.loc.start, t.loc.line_start, t.loc.col_start))
+            .collect()
+    }
+
+    fn ident(name: &str) -> TokenValue {
+        TokenValue::Ident(name.to_string())
+    }
+
+    fn int(value: &str) -> TokenValue {
+        TokenValue::Integer(value.to_string())
+    }
+
+    fn float(value: &str) -> TokenValue {
+        TokenValue::Float(value.to_string())
+    }
+
+    #[test]
+    fn test_method_dots() {
+        let (tokens, errors) = lex("a.b?.c ?");
+        assert_eq!(
+            tokens,
+            [
+                ident("a"),
+                TokenValue::Dot,
+                ident("b"),
+                TokenValue::QuestionDot,
+                ident("c"),
+                TokenValue::EOF
+            ]
+        );
+        // a question mark on its own isn't anything
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_text_block() {
+        assert_eq!(
+            values(r#""""foo bar""""#),
+            [text("foo bar"), TokenValue::EOF]
+        );
+    }
+
+    #[test]
+    fn test_empty_text_block() {
+        assert_eq!(values(r#""""""""#), [text(""), TokenValue::EOF]);
+    }
+
+    #[test]
+    fn test_text_block_with_quotes() {
+        // only three quotes in a row end the string
+        assert_eq!(
+            values(r#""""say "hi" and ""bye"" """"#),
+            [text(r#"say "hi" and ""bye"" "#), TokenValue::EOF]
+        );
+    }
+
+    #[test]
+    fn test_text_block_has_no_escapes() {
+        assert_eq!(values(r#""""a\nb\""""#), [text(r"a\nb\"), TokenValue::EOF]);
+    }
+
+    #[test]
+    fn test_text_block_indentation() {
+        let source = "= q \"\"\"\n    SELECT name\n      FROM users\n\n    \"\"\"";
+        assert_eq!(
+            values(source),
+            [
+                TokenValue::Assign,
+                TokenValue::Ident("q".to_string()),
+                text("SELECT name\n  FROM users\n"),
+                TokenValue::EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_block_keeps_lines_after_the_closing_indentation() {
+        // the last line is only dropped if it's just the indentation of the quotes
+        assert_eq!(
+            values("\"\"\"\n  a\n  b\"\"\""),
+            [text("a\nb"), TokenValue::EOF]
+        );
+    }
+
+    #[test]
+    fn test_text_block_span() {
+        let tokens = Lexer::new("x \"\"\"\na\n\"\"\" y").lex();
+        let loc = &tokens[1].loc;
+        assert_eq!((loc.line_start, loc.col_start), (0, 2));
+        assert_eq!((loc.line_stop, loc.col_stop), (2, 3));
+        assert_eq!(tokens[2].value, TokenValue::Ident("y".to_string()));
+        assert_eq!(tokens[2].loc.line_start, 2);
+    }
+
+    #[test]
+    fn test_after_checkpoint() {
+        let mut input = "= x 1\n".to_string();
+        let end = {
+            let mut lexer = Lexer::new(&input);
+            lexer.lex();
+            lexer.checkpoint()
+        };
+
+        // the next entry can start with a pragma, and its lines follow on
+        input.push_str("#!strict\n= y 2\n");
+        let tokens = Lexer::after(&input, &end).lex();
+        assert_eq!(tokens[0].value, TokenValue::Pragma("strict".to_string()));
+        assert_eq!(tokens[0].loc.line_start, 1);
+        assert_eq!(tokens[2].value, TokenValue::Assign);
+        assert_eq!((tokens[2].loc.start, tokens[2].loc.line_start), (15, 2));
+    }
+
+    #[test]
+    fn test_unterminated_text_block() {
+        let (tokens, errors) = lex("\"\"\"\n  foo\n  \"\"");
+        assert_eq!(tokens, [text("foo\n\"\""), TokenValue::EOF]);
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("unterminated triple quote string"),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn test_crlf() {
+        let source = "a\r\nb";
+        assert_eq!(
+            values(source),
+            [ident("a"), TokenValue::Newline, ident("b"), TokenValue::EOF]
+        );
+        assert_eq!(starts(source)[2], (3, 1, 0));
+    }
+
+    #[test]
+    fn test_lone_cr() {
+        let source = "a\rb\r\rc";
+        assert_eq!(
+            values(source),
+            [
+                ident("a"),
+                TokenValue::Newline,
+                ident("b"),
+                TokenValue::Newline,
+                TokenValue::Newline,
+                ident("c"),
+                TokenValue::EOF
+            ]
+        );
+        assert_eq!(starts(source)[2], (2, 1, 0));
+        assert_eq!(starts(source)[5], (5, 3, 0));
+    }
+
+    #[test]
+    fn test_bom_only() {
+        assert_eq!(values("\u{feff}"), [TokenValue::EOF]);
+    }
+
+    #[test]
+    fn test_bom_then_crlf() {
+        let source = "\u{feff}a\r\nb";
+        assert_eq!(
+            values(source),
+            [ident("a"), TokenValue::Newline, ident("b"), TokenValue::EOF]
+        );
+        // the BOM takes up three bytes but no columns
+        assert_eq!(starts(source)[0], (3, 0, 0));
+        assert_eq!(starts(source)[2], (6, 1, 0));
+    }
+
+    #[test]
+    fn test_bom_only_at_start() {
+        let (tokens, errors) = lex("a \u{feff}");
+        assert_eq!(tokens, [ident("a"), TokenValue::EOF]);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_crlf_in_text_block() {
+        let source = "\"\"\"\r\n  a\r\n  b\r\n  \"\"\"\r\nc";
+        assert_eq!(
+            values(source),
+            [
+                text("a\nb"),
+                TokenValue::Newline,
+                ident("c"),
+                TokenValue::EOF
+            ]
+        );
+        assert_eq!(starts(source)[2].1, 4);
+    }
+
+    #[test]
+    fn test_crlf_error_line() {
+        let source = "a\r\n  $";
+        let mut lexer = Lexer::new(source);
+        lexer.lex();
+        let errors = lexer.take_errors();
+        assert_eq!(errors.len(), 1);
+
+        let loc = errors[0].loc().unwrap();
+        assert_eq!((loc.start, loc.line_start, loc.col_start), (5, 1, 2));
+        let rendered = errors[0].render("test", source);
+        assert!(rendered.contains("2 |   $\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn test_number_suffixes() {
+        assert_eq!(
+            values("1f 2.0i 3_i64 1_000_f64 4.50float"),
+            [
+                float("1"),
+                int("2"),
+                int("3"),
+                float("1000"),
+                float("4.50"),
+                TokenValue::EOF
+            ]
+        );
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_decimal_suffix() {
+        assert_eq!(
+            values("1.5d 2_dec"),
+            [
+                TokenValue::Decimal("1.5".to_string()),
+                TokenValue::Decimal("2".to_string()),
+                TokenValue::EOF
+            ]
+        );
+    }
+
+    #[cfg(not(feature = "decimal"))]
+    #[test]
+    fn test_decimal_suffix_without_feature() {
+        let (tokens, errors) = lex("1.5d 2");
+        assert_eq!(tokens, [float("1.5"), int("2"), TokenValue::EOF]);
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("decimal literals need the decimal feature"),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn test_fraction_with_integer_suffix() {
+        let (tokens, errors) = lex("2.5i 7");
+        assert_eq!(tokens, [int("2"), int("7"), TokenValue::EOF]);
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("integer literal 2.5 is not a whole number"),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn test_invalid_suffix() {
+        let mut lexer = Lexer::new("+ 2x 3_ 4");
+        let tokens = lexer.lex();
+        let values = tokens.iter().map(|t| t.value.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                TokenValue::Plus,
+                int("2"),
+                int("3"),
+                int("4"),
+                TokenValue::EOF
+            ]
+        );
+
+        // the errors are at the whole literal, suffix included
+        let errors = lexer.take_errors();
+        let errors = errors
+            .iter()
+            .map(|e| {
+                let loc = e.loc().unwrap();
+                (e.to_string(), loc.start, loc.end)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].0.starts_with("invalid suffix x on number"));
+        assert_eq!((errors[0].1, errors[0].2), (2, 4));
+        assert!(errors[1].0.starts_with("invalid suffix _ on number"));
+        assert_eq!((errors[1].1, errors[1].2), (5, 7));
+    }
+}
@@ -1,4 +1,7 @@
-use std::fmt::{Display, Formatter, Result};
+use std::{
+    fmt::{Display, Formatter, Result},
+    ops::Range,
+};
 
 #[derive(Clone, Debug)]
 pub struct Token {
@@ -28,6 +31,8 @@ impl Display for Token {
 pub enum TokenValue {
     EOF,
     Semicolon,
+    /// Separates parameters and arguments, where it's optional.
+    Comma,
     Newline,
     LeftParen,
     RightParen,
@@ -35,10 +40,18 @@ pub enum TokenValue {
     RightBracket,
     BlockStart,
     BlockEnd,
+    Dot,
+    /// `?.`, which calls a method unless the value before it is null.
+    QuestionDot,
+    /// `$(`, which starts an infix expression.
+    InfixStart,
 
     If,
     Elif,
     Else,
+    Assert,
+    Defer,
+    Yield,
 
     Assign,
     Equal,
@@ -50,17 +63,41 @@ pub enum TokenValue {
     Minus,
     Asterisk,
     Slash,
+    DoubleSlash,
     Bang,
     And,
     Or,
 
+    /// A number without a point, or one with an `i` suffix, whose fraction has
+    /// to be zero and is left out. Suffixes aren't kept in the value.
     Integer(String),
+    /// A number with a point, or one with an `f` suffix.
     Float(String),
+    /// A number with a `d` suffix.
+    Decimal(String),
     String(String),
+    /// A string in triple quotes, which is kept apart from other strings so that
+    /// it can be formatted the way it was written.
+    Text(String),
     True,
     False,
     Ident(String),
-    Illegal(String),
+    /// The text of a comment after the `#`, only emitted when the lexer is
+    /// keeping comments or trivia.
+    Comment(String),
+    /// A run of spaces and tabs, only emitted when the lexer is keeping trivia.
+    Whitespace(String),
+    /// The text of a `#!` line at the top of the source, before any other
+    /// tokens, which sets up how the script is parsed and evaluated.
+    Pragma(String),
+}
+
+impl TokenValue {
+    /// Whether the token is trivia, which doesn't change what the source means:
+    /// comments and whitespace. The parser leaves trivia out of the program.
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, TokenValue::Comment(_) | TokenValue::Whitespace(_))
+    }
 }
 
 impl Display for TokenValue {
@@ -68,6 +105,7 @@ impl Display for TokenValue {
         match self {
             TokenValue::EOF => write!(f, "eof"),
             TokenValue::Semicolon => write!(f, "semicolon"),
+            TokenValue::Comma => write!(f, "comma"),
             TokenValue::Newline => write!(f, "newline"),
             TokenValue::LeftParen => write!(f, "left paren"),
             TokenValue::RightParen => write!(f, "right paren"),
@@ -76,6 +114,9 @@ impl Display for TokenValue {
             TokenValue::If => write!(f, "if"),
             TokenValue::Elif => write!(f, "elif"),
             TokenValue::Else => write!(f, "else"),
+            TokenValue::Assert => write!(f, "assert"),
+            TokenValue::Defer => write!(f, "defer"),
+            TokenValue::Yield => write!(f, "yield"),
             TokenValue::Assign => write!(f, "assign"),
             TokenValue::Equal => write!(f, "equal"),
             TokenValue::Greater => write!(f, "greater than"),
@@ -86,56 +127,152 @@ impl Display for TokenValue {
             TokenValue::Minus => write!(f, "minus"),
             TokenValue::Asterisk => write!(f, "asterisk"),
             TokenValue::Slash => write!(f, "slash"),
+            TokenValue::DoubleSlash => write!(f, "double slash"),
             TokenValue::Bang => write!(f, "bang"),
             TokenValue::And => write!(f, "and"),
             TokenValue::Or => write!(f, "or"),
             TokenValue::BlockStart => write!(f, "block start"),
             TokenValue::BlockEnd => write!(f, "block end"),
+            TokenValue::Dot => write!(f, "dot"),
+            TokenValue::QuestionDot => write!(f, "question dot"),
+            TokenValue::InfixStart => write!(f, "infix start"),
             TokenValue::Integer(v) => write!(f, "integer: {}", v),
             TokenValue::Float(v) => write!(f, "float: {}", v),
-            TokenValue::String(v) => write!(f, "string: {}", v),
+            TokenValue::Decimal(v) => write!(f, "decimal: {}", v),
+            TokenValue::String(v) | TokenValue::Text(v) => write!(f, "string: {}", v),
             TokenValue::True => write!(f, "boolean: true"),
             TokenValue::False => write!(f, "boolean: false"),
             TokenValue::Ident(v) => write!(f, "ident: {}", v),
-            TokenValue::Illegal(v) => write!(f, "illegal: {}", v),
+            TokenValue::Comment(v) => write!(f, "comment: {}", v),
+            TokenValue::Whitespace(v) => write!(f, "whitespace: {:?}", v),
+            TokenValue::Pragma(v) => write!(f, "pragma: {}", v),
         }
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Location {
+/// Where a token is in the source. Tokens and syntax nodes use the same type,
+/// so anything that has a location can be combined into a larger span.
+pub type Location = Span;
+
+/// The part of the source that a token or syntax node was parsed from, from the
+/// start of its first token to the end of its last token. `start` and `end` are
+/// byte offsets into the source, for slicing it or working with editors, while
+/// the lines and columns (counted in characters, from 0) are kept for showing
+/// where it is without having the source at hand. Offsets are 32-bit to keep
+/// spans, and the errors that hold them, small.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
     pub line_start: i32,
-    pub line_stop: i32,
     pub col_start: i32,
+    pub line_stop: i32,
     pub col_stop: i32,
 }
 
-impl Location {
-    pub const fn new(line_start: i32, col_start: i32) -> Self {
+impl Span {
+    /// Creates a span from the start of the token at `start` to the end of the
+    /// token at `stop`.
+    pub fn new(start: &Location, stop: &Location) -> Self {
         Self {
-            line_start,
-            line_stop: 0,
-            col_start,
-            col_stop: 0,
+            start: start.start,
+            end: stop.end,
+            line_start: start.line_start,
+            col_start: start.col_start,
+            line_stop: stop.line_stop,
+            col_stop: stop.col_stop,
         }
     }
 
-    pub fn stop(&self, line_stop: i32, col_stop: i32) -> Self {
-        Self {
-            line_start: self.line_start,
-            line_stop,
-            col_start: self.col_start,
-            col_stop,
-        }
+    /// Creates a span from the start of this span to the end of `other`.
+    pub fn to(&self, other: &Span) -> Self {
+        Self::new(self, other)
+    }
+
+    /// Gets the bytes of the source that the span covers, for slicing it.
+    pub fn range(&self) -> Range<usize> {
+        self.start as usize..self.end as usize
+    }
+}
+
+impl From<&Location> for Span {
+    fn from(loc: &Location) -> Self {
+        loc.clone()
     }
 }
 
-impl Display for Location {
+impl Display for Span {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(
             f,
-            "{}:{}, {}:{}",
-            self.line_start, self.line_stop, self.col_start, self.col_stop
+            "{}:{}-{}:{}",
+            self.line_start + 1,
+            self.col_start + 1,
+            self.line_stop + 1,
+            self.col_stop
         )
     }
 }
+
+/// The byte offsets where each line of a source starts, for finding the line and
+/// column of an offset in a [`Span`]. Lines end at `\n`, `\r\n` or a lone `\r`
+/// like in the lexer, and a byte order mark at the start isn't counted as a
+/// column.
+#[derive(Clone, Debug)]
+pub struct LineIndex<'a> {
+    source: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let bom = if source.starts_with('\u{feff}') { 3 } else { 0 };
+        let starts = std::iter::once(bom)
+            .chain(
+                source
+                    .match_indices(['\r', '\n'])
+                    .filter(|&(i, c)| c == "\n" || source.as_bytes().get(i + 1) != Some(&b'\n'))
+                    .map(|(i, _)| i + 1),
+            )
+            .collect();
+
+        Self { source, starts }
+    }
+
+    /// Gets the line that the byte at `offset` is on, counting from 0.
+    pub fn line(&self, offset: usize) -> usize {
+        self.starts
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1)
+    }
+
+    /// Gets the line and the column in characters of the byte at `offset`, both
+    /// counting from 0. Offsets past the end are on the last line.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line(offset);
+        let offset = offset.min(self.source.len());
+        let col = self
+            .source
+            .get(self.starts[line]..offset)
+            .map_or(0, |text| text.chars().count());
+
+        (line, col)
+    }
+
+    /// Gets the text of a line without its line break, if there is such a line.
+    /// A line break at the very end doesn't start another line.
+    pub fn line_text(&self, line: usize) -> Option<&'a str> {
+        let start = *self.starts.get(line)?;
+        if line > 0 && start == self.source.len() {
+            return None;
+        }
+
+        let end = self
+            .starts
+            .get(line + 1)
+            .map_or(self.source.len(), |&next| next);
+
+        Some(self.source[start..end].trim_end_matches(['\r', '\n']))
+    }
+}
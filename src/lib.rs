@@ -1,5 +1,21 @@
+pub mod compiler;
 pub mod error;
 pub mod eval;
+pub mod ir;
 pub mod lexer;
+pub mod optimizer;
 pub mod parser;
 pub mod repl;
+pub mod resolver;
+pub mod symbol;
+pub mod sync;
+
+use error::Error;
+use lexer::Lexer;
+use parser::{ast::Expression, Parser};
+
+/// Parses a single expression from source, such as a formula or a configuration
+/// value, for when there's no need for a whole program.
+pub fn parse_expr(source: &str) -> Result<Expression, Error> {
+    Parser::from_lexer(Lexer::new(source)).parse_expression()
+}
@@ -1,11 +1,24 @@
-use clap::{Parser as ClapParser, Subcommand};
+use clap::{Args as ClapArgs, Parser as ClapParser, Subcommand, ValueEnum};
 use clip::{
-    eval::{eval, Scope},
+    error::Error,
+    eval::{
+        capabilities::{Capabilities, Capability},
+        eval, eval_vm, Scope,
+    },
     lexer::Lexer,
-    parser::{ast::Statement, Parser},
-    repl,
+    optimizer,
+    parser::{ast::Statement, format, sexpr, Parser},
+    repl, resolver,
+};
+use std::{
+    fs, io, thread,
+    time::{Duration, Instant},
 };
-use std::fs;
+
+/// The stack size of the thread that scripts run on, which leaves room for the
+/// default call depth limit in the tree-walker even in debug builds, where each
+/// call takes a lot more of the stack.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
 
 #[derive(ClapParser)]
 #[command(author, version, about, long_about = None)]
@@ -14,6 +27,73 @@ struct Args {
     command: Commands,
 }
 
+#[derive(ClapArgs)]
+struct EvalOptions {
+    /// Seed the random number generator for reproducible runs
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Compile to bytecode and run on the VM instead of the tree-walker
+    #[arg(long)]
+    vm: bool,
+    /// Don't pre-evaluate operators whose arguments are all literals
+    #[arg(long)]
+    no_fold: bool,
+    /// Don't check for undefined names and wrong arities before evaluating
+    #[arg(long)]
+    no_check: bool,
+    /// Reject code that the parser would otherwise guess the meaning of
+    #[arg(long)]
+    strict: bool,
+    /// Stop evaluation with an error after this many steps
+    #[arg(long)]
+    fuel: Option<u64>,
+    /// Stop evaluation with an error after this many milliseconds
+    #[arg(long)]
+    timeout: Option<u64>,
+    /// Stop evaluation with an error once values have taken up this many bytes
+    #[arg(long)]
+    memory_limit: Option<usize>,
+    /// Disallow builtins with side effects, such as reading files or running programs
+    #[arg(long)]
+    sandbox: bool,
+    /// Allow a capability in the sandbox: fs_read, fs_write, network, env or exec
+    #[arg(long, value_name = "CAPABILITY", requires = "sandbox")]
+    allow: Vec<Capability>,
+}
+
+impl EvalOptions {
+    /// Creates the scope to evaluate in, with the seed and capabilities applied.
+    fn scope(&self) -> Scope {
+        let mut scope = Scope::default();
+        if let Some(seed) = self.seed {
+            scope.set_seed(seed);
+        }
+
+        if self.sandbox {
+            let mut capabilities = Capabilities::none();
+            for capability in &self.allow {
+                capabilities.allow(*capability);
+            }
+            scope.set_capabilities(capabilities);
+        }
+
+        scope
+    }
+}
+
+/// How `--parse` prints the syntax tree.
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum ParseFormat {
+    /// Each statement with Rust's debug formatting
+    #[default]
+    Debug,
+    /// The whole program as S-expressions, one statement per line
+    Sexpr,
+    /// The whole program as JSON
+    #[cfg(feature = "serde")]
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Run a clip script file
@@ -24,9 +104,19 @@ enum Commands {
         /// Print the parsed abstract syntax tree
         #[arg(short, long)]
         parse: bool,
+        /// How to print the syntax tree with --parse
+        #[arg(long, value_enum, default_value_t, requires = "parse")]
+        parse_format: ParseFormat,
         /// Print the parsed tokens
         #[arg(short, long)]
         token: bool,
+        #[command(flatten)]
+        options: EvalOptions,
+        /// The input file, or - to read it from stdin
+        file: String,
+    },
+    /// Print a clip script file formatted, keeping its comments
+    Fmt {
         /// The input file
         file: String,
     },
@@ -38,64 +128,208 @@ enum Commands {
         /// Print the parsed tokens
         #[arg(short, long)]
         token: bool,
+        /// Record the variables after each statement to step back through with :back, :forward and :trace
+        #[arg(long, conflicts_with = "vm")]
+        snapshots: bool,
+        #[command(flatten)]
+        options: EvalOptions,
     },
 }
 
 fn main() {
     let args = Args::parse();
 
+    let cli = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| command(args))
+        .expect("should be able to start the interpreter thread");
+    if cli.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn command(args: Args) {
     match args.command {
         Commands::Run {
             display,
             parse,
+            parse_format,
             token,
+            options,
             file,
-        } => run(file, display, token, parse),
-        Commands::Repl { parse, token } => repl::repl(token, parse),
+        } => run(file, display, token, parse.then_some(parse_format), options),
+        Commands::Fmt { file } => fmt(file),
+        Commands::Repl {
+            parse,
+            token,
+            snapshots,
+            options,
+        } => repl::repl(
+            token,
+            parse,
+            options.scope(),
+            repl::Options {
+                vm: options.vm,
+                fold: !options.no_fold,
+                check: !options.no_check,
+                strict: options.strict,
+                fuel: options.fuel,
+                timeout: options.timeout.map(Duration::from_millis),
+                memory_limit: options.memory_limit,
+                snapshots,
+            },
+        ),
     }
 }
 
-fn run(path: String, display: bool, show_token: bool, show_parse: bool) {
-    if show_token && show_parse {
+fn run(
+    path: String,
+    display: bool,
+    show_token: bool,
+    show_parse: Option<ParseFormat>,
+    options: EvalOptions,
+) {
+    if show_token && show_parse.is_some() {
         eprintln!("error: cannot specify both --token and --parse flags");
         return;
     }
 
-    match fs::read_to_string(path) {
-        Ok(input) => {
-            if display {
-                for line in input.lines() {
-                    println!("# {}", line);
-                }
+    // the script is read as it's lexed from stdin, so there's no source to show
+    // errors in and only their messages are printed
+    let source = if path == "-" {
+        if display {
+            eprintln!("error: cannot display a script read from stdin");
+            return;
+        }
+        None
+    } else {
+        match fs::read_to_string(&path) {
+            Ok(input) => Some(input),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
             }
+        }
+    };
+    let report = |e: &Error| match &source {
+        Some(input) => e.render(&path, input),
+        None => e.to_string(),
+    };
 
-            let tokens = Lexer::new(&input).lex();
-            if show_token {
-                for token in &tokens {
-                    println!("{}", token);
-                }
-                return;
+    if let (true, Some(input)) = (display, &source) {
+        for line in input.lines() {
+            println!("# {}", line);
+        }
+    }
+
+    let mut lexer = match &source {
+        Some(input) => Lexer::new(input),
+        None => Lexer::from_reader(io::stdin().lock()),
+    };
+    if show_token {
+        for token in lexer.by_ref() {
+            println!("{}", token);
+        }
+        for e in lexer.take_errors() {
+            eprintln!("{}", report(&e));
+        }
+        return;
+    }
+
+    let mut parser = Parser::from_lexer(lexer);
+    if options.strict {
+        parser.set_strict(true);
+    }
+
+    let program = parser.parse_all();
+    for w in parser.take_warnings() {
+        eprintln!("{}", report(&w));
+    }
+
+    match program {
+        Ok(mut p) => {
+            if !options.no_fold {
+                optimizer::fold_constants(&mut p);
             }
 
-            match Parser::new(tokens).parse() {
-                Ok(p) => {
-                    if show_parse {
-                        for stmt in &p.statements {
-                            match stmt {
-                                Statement::Assign(a) => println!("{:#?}", a),
-                                Statement::If(i) => println!("{:#?}", i),
-                                Statement::Expression(e) => println!("{:#?}", e),
-                            }
+            match show_parse {
+                Some(ParseFormat::Debug) => {
+                    for stmt in &p.statements {
+                        match stmt {
+                            Statement::Assign(a) => println!("{:#?}", a),
+                            Statement::If(i) => println!("{:#?}", i),
+                            Statement::Assert(a) => println!("{:#?}", a),
+                            Statement::Defer(d) => println!("{:#?}", d),
+                            Statement::Yield(y) => println!("{:#?}", y),
+                            Statement::Expression(e) => println!("{:#?}", e),
                         }
-                        return;
                     }
+                    return;
+                }
+                Some(ParseFormat::Sexpr) => {
+                    print!("{}", sexpr::program(&p));
+                    return;
+                }
+                #[cfg(feature = "serde")]
+                Some(ParseFormat::Json) => {
+                    match serde_json::to_string_pretty(&p) {
+                        Ok(json) => println!("{}", json),
+                        Err(e) => eprintln!("error: {}", e),
+                    }
+                    return;
+                }
+                None => {}
+            }
+
+            let mut scope = options.scope();
+            if !options.no_check {
+                let errors = resolver::check(&p, &scope);
+                if !errors.is_empty() {
+                    for e in errors {
+                        eprintln!("{}", report(&e));
+                    }
+                    return;
+                }
+            }
+
+            scope.set_fuel(options.fuel);
+            scope.set_memory_limit(options.memory_limit);
+            if let Some(timeout) = options.timeout {
+                scope.set_deadline(Some(Instant::now() + Duration::from_millis(timeout)));
+            }
+
+            let result = if options.vm {
+                eval_vm(p, &mut scope)
+            } else {
+                eval(p, &mut scope)
+            };
+
+            match result {
+                Ok(v) => println!("{} : {}", v, v.value()),
+                Err(e) => eprintln!("{}", report(&e)),
+            }
+        }
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", report(&e));
+            }
+        }
+    }
+}
+
+fn fmt(path: String) {
+    match fs::read_to_string(&path) {
+        Ok(input) => {
+            let mut lexer = Lexer::new(&input);
+            lexer.keep_comments(true);
 
-                    match eval(p, &mut Scope::default()) {
-                        Ok(v) => println!("{} : {}", v, v.value()),
-                        Err(e) => eprintln!("{}", e),
+            match Parser::from_lexer(lexer).parse_all() {
+                Ok(p) => print!("{}", format::program(&p)),
+                Err(errors) => {
+                    for e in errors {
+                        eprintln!("{}", e.render(&path, &input));
                     }
                 }
-                Err(e) => eprintln!("{}", e),
             }
         }
         Err(e) => eprintln!("{}", e),
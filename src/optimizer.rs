@@ -0,0 +1,41 @@
+use crate::{
+    eval::{ops, value::Value},
+    parser::{
+        ast::{Expression, Program},
+        fold::{walk_expression, Fold},
+    },
+};
+
+/// Replaces operators whose arguments are all literals with their result, such
+/// as `+ 1 2 3` with `6`, including inside function bodies. Operators that would
+/// fail are left alone so that the error is raised when they're evaluated.
+pub fn fold_constants(program: &mut Program) {
+    ConstantFolder.fold_program(program);
+}
+
+/// The pass behind [`fold_constants`], for running it along with other passes.
+#[derive(Debug, Default)]
+pub struct ConstantFolder;
+
+impl Fold for ConstantFolder {
+    fn fold_expression(&mut self, expr: &mut Expression) {
+        // fold the arguments first so that nested operators can fold into this one
+        walk_expression(self, expr);
+
+        let Expression::Operator(o) = expr else {
+            return;
+        };
+
+        let mut args = Vec::with_capacity(o.args.len());
+        for arg in &o.args {
+            match arg {
+                Expression::Primitive(p, ..) => args.push(Value::Primitive(p.clone())),
+                _ => return,
+            }
+        }
+
+        if let Ok(Value::Primitive(p)) = ops::apply_operator(o.kind.clone(), args) {
+            *expr = Expression::Primitive(p, o.span.clone(), o.id);
+        }
+    }
+}
@@ -1,152 +1,276 @@
-use super::{Parse, Parser};
-use crate::{error::Error, lexer::token::TokenValue};
-use std::fmt::{Display, Formatter, Result as FmtResult};
+use super::{comments::Comments, dialect::Construct, pragma::Pragmas, Parse, Parser, Precedence};
+use crate::{
+    error::Error,
+    lexer::token::{Location, Span, TokenValue},
+    symbol::Symbol,
+    sync::Shared,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    sync::atomic::{AtomicU64, Ordering as AtomicOrdering},
+};
+
+/// Identifies a node in the syntax tree, so that passes over the tree can refer
+/// to nodes without comparing them. IDs are unique within the tree from a single
+/// [`Parser`], and are given out in the order the nodes finish parsing, so parsing
+/// the same source always gives the same IDs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NodeId(pub u32);
+
+/// Identifies the body of a function across every parser, unlike [`NodeId`], so
+/// that the VM can cache the code compiled for it. Each body that is parsed or
+/// rewritten gets a new one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BodyId(u64);
+
+impl BodyId {
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, AtomicOrdering::Relaxed))
+    }
+}
+
+/// How an expression was written, for the ones that are written differently to
+/// how they're parsed, so that they can be formatted the same way.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Syntax {
+    /// The expression is an infix expression in `$( ... )`.
+    pub infix: bool,
+    /// The call was written as a method call, like `xs.len`.
+    pub method: bool,
+    /// The string was written in triple quotes.
+    pub text: bool,
+}
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Program {
     pub statements: Vec<Statement>,
+    /// The comments attached to each statement, by the statement's ID.
+    pub comments: BTreeMap<NodeId, Comments>,
+    /// How the expressions that have other ways of being written were written,
+    /// by the expression's ID.
+    pub syntax: BTreeMap<NodeId, Syntax>,
+    /// Comments that couldn't be attached since there are no statements.
+    pub dangling: Vec<String>,
+    pub pragmas: Pragmas,
 }
 
 impl Parse for Program {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
-        let mut statements = Vec::new();
-
-        loop {
-            match p.current_token().value {
-                TokenValue::EOF => break,
-                TokenValue::Semicolon | TokenValue::Newline => {
-                    _ = p.next_token();
-                }
-                _ => {
-                    statements.push(Statement::parse(p)?);
-                    if p.current_token().value == TokenValue::EOF {
-                        break;
-                    }
-                    _ = p.next_token();
-                }
-            }
-        }
+        let mut errors = Vec::new();
+        let statements = p
+            .statements()
+            .filter_map(|stmt| stmt.map_err(|e| errors.push(e)).ok())
+            .collect();
+        p.errors = errors;
 
-        Ok(Self { statements })
+        Ok(Self {
+            statements,
+            comments: BTreeMap::new(),
+            syntax: p.take_syntax(),
+            dangling: Vec::new(),
+            pragmas: p.pragmas().clone(),
+        })
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Statement {
     Assign(Assign),
     If(If),
+    Assert(Assert),
+    Defer(Defer),
+    Yield(Yield),
     Expression(Expression),
 }
 
+impl Statement {
+    /// Parses a statement, or records the error and skips past it if it's invalid
+    /// so that the statements after it can still be parsed.
+    fn parse_or_recover(p: &mut Parser) -> Option<Self> {
+        let start = p.position();
+        match Self::parse(p) {
+            Ok(stmt) => Some(stmt),
+            Err(e) => {
+                p.recover(e, start);
+                None
+            }
+        }
+    }
+
+    pub fn id(&self) -> NodeId {
+        match self {
+            Self::Assign(a) => a.id,
+            Self::If(i) => i.id,
+            Self::Assert(a) => a.id,
+            Self::Defer(d) => d.id,
+            Self::Yield(y) => y.id,
+            Self::Expression(e) => e.id(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Assign(a) => a.span.clone(),
+            Self::If(i) => i.span.clone(),
+            Self::Assert(a) => a.span.clone(),
+            Self::Defer(d) => d.span.clone(),
+            Self::Yield(y) => y.span.clone(),
+            Self::Expression(e) => e.span(),
+        }
+    }
+}
+
 impl Parse for Statement {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
-        match p.current_token().value {
+        p.nested(|p| match p.current_token().value {
             TokenValue::Assign => Ok(Self::Assign(Assign::parse(p)?)),
             TokenValue::If => Ok(Self::If(If::parse(p)?)),
+            TokenValue::Assert => Ok(Self::Assert(Assert::parse(p)?)),
+            TokenValue::Defer => Ok(Self::Defer(Defer::parse(p)?)),
+            TokenValue::Yield => Ok(Self::Yield(Yield::parse(p)?)),
             _ => Ok(Self::Expression(Expression::parse(p)?)),
-        }
+        })
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Assign {
     pub name: Identifier,
     pub value: Expression,
+    pub span: Span,
+    pub id: NodeId,
 }
 
 impl Parse for Assign {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
-        _ = p.next_token();
+        let start = p.current_token().loc;
+        p.next_token()?;
         let name = Identifier::parse(p)?;
-        _ = p.next_token();
+        p.next_token()?;
         let value = Expression::parse(p)?;
+        let span = Span::from(&start).to(&value.span());
 
-        if let TokenValue::EOF | TokenValue::Semicolon | TokenValue::Newline =
-            p.current_token().value
+        if let TokenValue::EOF
+        | TokenValue::Semicolon
+        | TokenValue::Newline
+        | TokenValue::BlockEnd = p.current_token().value
         {
-            Ok(Self { name, value })
+            Ok(Self {
+                name,
+                value,
+                span,
+                id: p.node_id(),
+            })
         } else {
             match &p.peek_token().value {
-                TokenValue::EOF | TokenValue::Semicolon | TokenValue::Newline => {
-                    Ok(Self { name, value })
-                }
-                t => Err(Error::new(&format!("unexpected token {t}"))),
+                TokenValue::EOF
+                | TokenValue::Semicolon
+                | TokenValue::Newline
+                | TokenValue::BlockEnd => Ok(Self {
+                    name,
+                    value,
+                    span,
+                    id: p.node_id(),
+                }),
+                _ => Err(Error::unexpected(p.peek_token())),
             }
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct If {
     pub condition: Expression,
     pub consequence: Vec<Box<Statement>>,
     pub alternative: Option<Vec<Box<Statement>>>,
+    pub loc: Location,
+    pub span: Span,
+    pub id: NodeId,
 }
 
 impl Parse for If {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
-        _ = p.next_token();
+        p.expect_allowed(Construct::IfElse)?;
+        let loc = p.current_token().loc;
+        p.next_token()?;
         let condition = Expression::parse(p)?;
 
-        if p.next_token().value != TokenValue::BlockStart {
-            return Err(Error::new(&format!(
-                "expected block start; got {}",
-                p.current_token().value
-            )));
+        if p.next_token()?.value != TokenValue::BlockStart {
+            return Err(Error::expected("block start", &p.current_token()));
         }
 
+        let open = p.current_token().loc;
         let mut consequence = Vec::new();
 
         loop {
             match p.peek_token().value {
-                TokenValue::EOF => return Err(Error::new("unexpected end of file")),
+                TokenValue::EOF => {
+                    p.unclosed(&open);
+                    return Ok(Self {
+                        condition,
+                        consequence,
+                        alternative: None,
+                        span: Span::new(&loc, &p.current_token().loc),
+                        loc,
+                        id: p.node_id(),
+                    });
+                }
                 TokenValue::Semicolon | TokenValue::Newline => _ = p.next_token(),
                 TokenValue::BlockEnd => {
-                    _ = p.next_token();
+                    p.next_token()?;
                     break;
                 }
                 _ => {
-                    _ = p.next_token();
-                    let stmt = Statement::parse(p)?;
-                    consequence.push(Box::new(stmt));
+                    p.next_token()?;
+                    consequence.extend(Statement::parse_or_recover(p).map(Box::new));
                 }
             }
         }
 
         let mut alternative = None;
+        let mut span = Span::new(&loc, &p.current_token().loc);
 
         while p.peek_token().value == TokenValue::Semicolon
             || p.peek_token().value == TokenValue::Newline
         {
-            _ = p.next_token();
+            p.next_token()?;
         }
 
         match p.peek_token().value {
             TokenValue::BlockEnd => _ = p.next_token(),
             TokenValue::Else => {
-                _ = p.next_token();
-                if p.next_token().value != TokenValue::BlockStart {
-                    return Err(Error::new(&format!(
-                        "expected block start; got {}",
-                        p.current_token().value
-                    )));
+                p.next_token()?;
+                if p.next_token()?.value != TokenValue::BlockStart {
+                    return Err(Error::expected("block start", &p.current_token()));
                 }
 
+                let open = p.current_token().loc;
                 let mut statements = Vec::new();
 
                 loop {
                     match p.peek_token().value {
-                        TokenValue::EOF => return Err(Error::new("unexpected end of file")),
+                        TokenValue::EOF => {
+                            p.unclosed(&open);
+                            span = Span::new(&loc, &p.current_token().loc);
+                            break;
+                        }
                         TokenValue::Semicolon | TokenValue::Newline => _ = p.next_token(),
                         TokenValue::BlockEnd => {
-                            _ = p.next_token();
-                            _ = p.next_token();
+                            span = Span::new(&loc, &p.next_token()?.loc);
+                            p.next_token()?;
                             break;
                         }
                         _ => {
-                            _ = p.next_token();
-                            let stmt = Statement::parse(p)?;
-                            statements.push(Box::new(stmt));
+                            p.next_token()?;
+                            statements.extend(Statement::parse_or_recover(p).map(Box::new));
                         }
                     }
                 }
@@ -154,10 +278,12 @@ impl Parse for If {
                 alternative = Some(statements);
             }
             _ => {
-                return Err(Error::new(&format!(
-                    "expected block end or else statement; got {}",
-                    p.peek_token().value
-                )))
+                let token = p.peek_token();
+                let error = Error::expected("block end or else statement", token);
+                return Err(match &token.value {
+                    TokenValue::Ident(name) => error.did_you_mean(name, ["else"]),
+                    _ => error,
+                });
             }
         }
 
@@ -165,93 +291,536 @@ impl Parse for If {
             condition,
             consequence,
             alternative,
+            loc,
+            span,
+            id: p.node_id(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Assert {
+    pub condition: Expression,
+    pub message: Option<Expression>,
+    pub loc: Location,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+impl Parse for Assert {
+    fn parse(p: &mut Parser) -> Result<Self, Error> {
+        p.expect_allowed(Construct::Assert)?;
+        let loc = p.current_token().loc;
+        p.next_token()?;
+        let condition = Expression::parse_non_call(p)?;
+        let mut message = None;
+
+        let is_end = |t: &TokenValue| {
+            matches!(
+                t,
+                TokenValue::EOF
+                    | TokenValue::Semicolon
+                    | TokenValue::Newline
+                    | TokenValue::BlockEnd
+            )
+        };
+
+        if !is_end(&p.current_token().value) && !is_end(&p.peek_token().value) {
+            p.next_token()?;
+            message = Some(Expression::parse(p)?);
+        }
+
+        let end = message.as_ref().unwrap_or(&condition).span();
+        let span = Span::from(&loc).to(&end);
+
+        Ok(Self {
+            condition,
+            message,
+            loc,
+            span,
+            id: p.node_id(),
         })
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Defer {
+    pub body: Vec<Statement>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+impl Parse for Defer {
+    fn parse(p: &mut Parser) -> Result<Self, Error> {
+        p.expect_allowed(Construct::Defer)?;
+        let start = p.current_token().loc;
+        if p.next_token()?.value != TokenValue::BlockStart {
+            return Err(Error::expected("block start", &p.current_token()));
+        }
+
+        // deferred blocks run after the function has finished, so can't yield
+        let open = p.current_token().loc;
+        let (body, _) = p.yielding(None, |p| Self::parse_body(p, &start, &open));
+        let (body, span) = body?;
+
+        Ok(Self {
+            body,
+            span,
+            id: p.node_id(),
+        })
+    }
+}
+
+impl Defer {
+    fn parse_body(
+        p: &mut Parser,
+        start: &Location,
+        open: &Location,
+    ) -> Result<(Vec<Statement>, Span), Error> {
+        let mut body = Vec::new();
+
+        loop {
+            match p.peek_token().value {
+                TokenValue::EOF => {
+                    p.unclosed(open);
+                    return Ok((body, Span::new(start, &p.current_token().loc)));
+                }
+                TokenValue::Semicolon | TokenValue::Newline => _ = p.next_token(),
+                TokenValue::BlockEnd => {
+                    let span = Span::new(start, &p.next_token()?.loc);
+                    p.next_token()?;
+                    return Ok((body, span));
+                }
+                _ => {
+                    p.next_token()?;
+                    body.extend(Statement::parse_or_recover(p));
+                }
+            }
+        }
+    }
+}
+
+/// Gives a value from a generator function, pausing it until the next value is
+/// asked for.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Yield {
+    pub value: Expression,
+    pub loc: Location,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+impl Parse for Yield {
+    fn parse(p: &mut Parser) -> Result<Self, Error> {
+        p.expect_allowed(Construct::Generators)?;
+        let token = p.current_token();
+        if !p.yielded() {
+            return Err(Error::Parse {
+                expected: None,
+                found: token.value,
+                loc: token.loc,
+                help: Some("yield can only be used in a function".into()),
+            });
+        }
+
+        p.next_token()?;
+        let value = Expression::parse(p)?;
+        let span = Span::from(&token.loc).to(&value.span());
+
+        Ok(Self {
+            value,
+            loc: token.loc,
+            span,
+            id: p.node_id(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Expression {
-    Primitive(Primitive),
+    Primitive(Primitive, Span, NodeId),
     Identifier(Identifier),
     Operator(Operator),
     Function(Function),
+    Block(Block),
     Call(Call),
+    List(Vec<Expression>, Span, NodeId),
     And(And),
     Or(Or),
 }
 
 impl Expression {
+    pub fn id(&self) -> NodeId {
+        match self {
+            Self::Primitive(_, _, id) | Self::List(_, _, id) => *id,
+            Self::Identifier(i) => i.id,
+            Self::Operator(o) => o.id,
+            Self::Function(f) => f.id,
+            Self::Block(b) => b.id,
+            Self::Call(c) => c.id,
+            Self::And(a) => a.2,
+            Self::Or(o) => o.2,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Primitive(_, span, _) | Self::List(_, span, _) => span.clone(),
+            Self::Identifier(i) => i.span(),
+            Self::Operator(o) => o.span.clone(),
+            Self::Function(f) => f.span.clone(),
+            Self::Block(b) => b.span.clone(),
+            Self::Call(c) => c.span.clone(),
+            Self::And(a) => a.1.clone(),
+            Self::Or(o) => o.1.clone(),
+        }
+    }
+
     fn parse_non_call(p: &mut Parser) -> Result<Self, Error> {
+        p.nested(|p| {
+            match p.current_token().value {
+                TokenValue::LeftParen => {
+                    let start = p.current_token().loc;
+                    if p.next_token()?.value == TokenValue::RightParen {
+                        let span = Span::new(&start, &p.current_token().loc);
+                        return Ok(Self::Primitive(Primitive::Null, span, p.node_id()));
+                    }
+
+                    let expr = Expression::parse(p)?;
+                    let t = &p.peek_token().value;
+
+                    if t == &TokenValue::RightParen {
+                        p.next_token()?;
+                        Self::parse_method(p, expr, false)
+                    } else {
+                        Err(Error::expected("right paren", p.peek_token()))
+                    }
+                }
+                TokenValue::LeftBracket => {
+                    let start = p.current_token().loc;
+                    let items = Self::parse_list(p)?;
+                    let span = Span::new(&start, &p.current_token().loc);
+                    let expr = Self::List(items, span, p.node_id());
+                    Self::parse_method(p, expr, false)
+                }
+                TokenValue::InfixStart => {
+                    let expr = Self::parse_infix_group(p)?;
+                    Self::parse_method(p, expr, false)
+                }
+                TokenValue::And => Ok(Self::And(And::parse(p)?)),
+                TokenValue::Or => Ok(Self::Or(Or::parse(p)?)),
+                TokenValue::BlockStart => {
+                    let expr = Self::parse_braces(p)?;
+                    // leave the cursor on the closing brace like other arguments
+                    p.back_token();
+                    Ok(expr)
+                }
+                TokenValue::Integer(_)
+                | TokenValue::Float(_)
+                | TokenValue::Decimal(_)
+                | TokenValue::String(_)
+                | TokenValue::Text(_)
+                | TokenValue::True
+                | TokenValue::False => {
+                    let expr = Self::parse_primitive(p)?;
+                    Self::parse_method(p, expr, false)
+                }
+                TokenValue::Ident(_) => {
+                    let expr = Self::Identifier(Identifier::parse(p)?);
+                    Self::parse_method(p, expr, false)
+                }
+                TokenValue::Equal
+                | TokenValue::Greater
+                | TokenValue::GreaterEqual
+                | TokenValue::Less
+                | TokenValue::LessEqual
+                | TokenValue::Plus
+                | TokenValue::Minus
+                | TokenValue::Asterisk
+                | TokenValue::Slash
+                | TokenValue::DoubleSlash
+                | TokenValue::Bang => Ok(Self::Operator(Operator::parse(p)?)),
+                _ => Err(Error::unexpected(&p.current_token())),
+            }
+        })
+    }
+
+    fn parse_list(p: &mut Parser) -> Result<Vec<Self>, Error> {
+        let mut items = Vec::new();
+
+        loop {
+            match p.peek_token().value {
+                TokenValue::EOF => return Err(Error::unexpected(p.peek_token())),
+                TokenValue::Newline => _ = p.next_token()?,
+                TokenValue::RightBracket => {
+                    p.next_token()?;
+                    break;
+                }
+                TokenValue::Comma => skip_comma(p, items.is_empty())?,
+                _ => {
+                    p.next_token()?;
+                    items.push(Self::parse_non_call(p)?);
+                }
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Parses `{ ... }` as a function if it starts with a list of parameters, even
+    /// an empty one, or as a block otherwise. A list with anything other than names
+    /// in it is the first statement of a block rather than parameters.
+    ///
+    /// Braces without parameters used to be a function without any, and outside
+    /// of strict mode they still are, with a warning, unless the dialect only
+    /// allows blocks.
+    fn parse_braces(p: &mut Parser) -> Result<Self, Error> {
+        if p.peek_token().value == TokenValue::LeftBracket {
+            let checkpoint = p.checkpoint();
+            p.next_token()?;
+            let params = Function::parse_params(p);
+            p.rewind(checkpoint);
+
+            if params.is_ok() {
+                return Ok(Self::Function(Function::parse(p)?));
+            }
+        }
+
+        if !p.is_strict() && p.dialect().allows(Construct::Functions) {
+            p.warn(Error::Deprecated {
+                message: "braces without parameters will be a block instead of a function; \
+                          use `{ [] ... }` for a function, or strict mode for a block"
+                    .to_string(),
+                loc: p.current_token().loc,
+            });
+            let start = p.current_token().loc;
+            p.next_token()?;
+            return Ok(Self::Function(Function::parse_body(p, &start, Vec::new())?));
+        }
+
+        Ok(Self::Block(Block::parse(p)?))
+    }
+
+    fn parse_primitive(p: &mut Parser) -> Result<Self, Error> {
+        let token = p.current_token();
+        let expr = Self::Primitive(Primitive::parse(p)?, Span::from(&token.loc), p.node_id());
+        if let TokenValue::Text(_) = token.value {
+            p.syntax(expr.id()).text = true;
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses an infix expression in `$( ... )`, such as `$(1 + 2 * 3)`, leaving the
+    /// cursor on the closing paren.
+    fn parse_infix_group(p: &mut Parser) -> Result<Self, Error> {
+        p.expect_allowed(Construct::Infix)?;
+        p.next_token()?;
+        let expr = Self::parse_infix(p, Precedence::Lowest)?;
+        Self::expect_infix_end(p)?;
+        p.syntax(expr.id()).infix = true;
+
+        Ok(expr)
+    }
+
+    /// Moves to the right paren that ends an infix group or parenthesized operand.
+    /// Anything else after a whole operand means an operator is missing, as in
+    /// `$(1 2)`.
+    fn expect_infix_end(p: &mut Parser) -> Result<(), Error> {
+        match p.peek_token().value {
+            TokenValue::RightParen => {
+                p.next_token()?;
+                Ok(())
+            }
+            TokenValue::EOF => Err(Error::expected("right paren", p.peek_token())),
+            _ => Err(Error::expected("operator", p.peek_token())),
+        }
+    }
+
+    /// Parses operators that bind more tightly than `precedence`, starting with the
+    /// operand at the cursor.
+    fn parse_infix(p: &mut Parser, precedence: Precedence) -> Result<Self, Error> {
+        p.nested(|p| {
+            let mut left = Self::parse_infix_operand(p)?;
+
+            loop {
+                let next = Precedence::of(&p.peek_token().value);
+                if next <= precedence {
+                    break;
+                }
+
+                let token = p.next_token()?.clone();
+                p.next_token()?;
+                let right = Self::parse_infix(p, next)?;
+
+                let span = left.span().to(&right.span());
+                left = match token.value {
+                    TokenValue::And => match left {
+                        Self::And(mut a) => {
+                            a.0.push(right);
+                            Self::And(And(a.0, span, a.2))
+                        }
+                        left => Self::And(And(vec![left, right], span, p.node_id())),
+                    },
+                    TokenValue::Or => match left {
+                        Self::Or(mut o) => {
+                            o.0.push(right);
+                            Self::Or(Or(o.0, span, o.2))
+                        }
+                        left => Self::Or(Or(vec![left, right], span, p.node_id())),
+                    },
+                    value => Self::Operator(Operator {
+                        kind: OperatorKind::from_token(&value)
+                            .expect("token should be an operator"),
+                        args: vec![left, right],
+                        loc: token.loc,
+                        span,
+                        id: p.node_id(),
+                    }),
+                };
+            }
+
+            Ok(left)
+        })
+    }
+
+    fn parse_infix_operand(p: &mut Parser) -> Result<Self, Error> {
         match p.current_token().value {
             TokenValue::LeftParen => {
-                if p.next_token().value == TokenValue::RightParen {
-                    return Ok(Self::Primitive(Primitive::Null));
+                let start = p.current_token().loc;
+                if p.next_token()?.value == TokenValue::RightParen {
+                    let span = Span::new(&start, &p.current_token().loc);
+                    return Ok(Self::Primitive(Primitive::Null, span, p.node_id()));
                 }
 
-                let expr = Expression::parse(p)?;
-                let t = &p.peek_token().value;
+                let expr = Self::parse_infix(p, Precedence::Lowest)?;
+                Self::expect_infix_end(p)?;
+                Self::parse_method(p, expr, false)
+            }
+            TokenValue::Minus | TokenValue::Bang => {
+                let token = p.current_token();
+                p.next_token()?;
+                let operand = Self::parse_infix(p, Precedence::Prefix)?;
+                let span = Span::from(&token.loc).to(&operand.span());
 
-                if t == &TokenValue::RightParen {
-                    _ = p.next_token();
-                    Ok(expr)
-                } else {
-                    Err(Error::new(&format!("expected right paren; got {t}")))
-                }
+                Ok(Self::Operator(Operator {
+                    kind: OperatorKind::from_token(&token.value)
+                        .expect("token should be an operator"),
+                    args: vec![operand],
+                    loc: token.loc,
+                    span,
+                    id: p.node_id(),
+                }))
             }
-            TokenValue::And => Ok(Self::And(And::parse(p)?)),
-            TokenValue::Or => Ok(Self::Or(Or::parse(p)?)),
-            TokenValue::BlockStart => Ok(Self::Function(Function::parse(p)?)),
-            TokenValue::Integer(_)
+            TokenValue::LeftBracket
+            | TokenValue::InfixStart
+            | TokenValue::Integer(_)
             | TokenValue::Float(_)
+            | TokenValue::Decimal(_)
             | TokenValue::String(_)
+            | TokenValue::Text(_)
             | TokenValue::True
-            | TokenValue::False => Ok(Self::Primitive(Primitive::parse(p)?)),
-            TokenValue::Ident(_) => Ok(Self::Identifier(Identifier::parse(p)?)),
-            TokenValue::Equal
-            | TokenValue::Greater
-            | TokenValue::GreaterEqual
-            | TokenValue::Less
-            | TokenValue::LessEqual
-            | TokenValue::Plus
-            | TokenValue::Minus
-            | TokenValue::Asterisk
-            | TokenValue::Slash
-            | TokenValue::Bang => Ok(Self::Operator(Operator::parse(p)?)),
-            t => Err(Error::new(&format!("unexpected token {t}"))),
+            | TokenValue::False
+            | TokenValue::Ident(_) => Self::parse_non_call(p),
+            _ => Err(Error::unexpected(&p.current_token())),
+        }
+    }
+
+    /// Parses any method calls following `receiver`, desugaring `value.method args`
+    /// into `method value args`. Method calls in argument position don't take any
+    /// arguments of their own, so `f xs.len` is the same as `f (len xs)`. A call
+    /// written with `?.` gives null without being made if the receiver is null.
+    fn parse_method(p: &mut Parser, receiver: Self, with_args: bool) -> Result<Self, Error> {
+        let mut receiver = receiver;
+
+        while is_method_dot(&p.peek_token().value) {
+            let null_safe = p.next_token()?.value == TokenValue::QuestionDot;
+            p.expect_allowed(Construct::Methods)?;
+            let loc = p.next_token()?.loc.clone();
+            let name = Identifier::parse(p)?;
+            let mut args = vec![receiver];
+
+            if with_args && !is_method_dot(&p.peek_token().value) {
+                args.extend(Call::parse_args(p)?);
+            }
+
+            let end = match args.last() {
+                Some(arg) if args.len() > 1 => arg.span(),
+                _ => name.span(),
+            };
+            let span = args[0].span().to(&end);
+            let id = p.node_id();
+            p.syntax(id).method = true;
+            receiver = Self::Call(Call {
+                name,
+                args,
+                null_safe,
+                loc,
+                span,
+                id,
+            });
         }
+
+        Ok(receiver)
     }
 }
 
 impl Parse for Expression {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
-        match p.current_token().value {
+        p.nested(|p| match p.current_token().value {
             TokenValue::LeftParen => {
-                if p.next_token().value == TokenValue::RightParen {
-                    return Ok(Self::Primitive(Primitive::Null));
+                let start = p.current_token().loc;
+                if p.next_token()?.value == TokenValue::RightParen {
+                    let span = Span::new(&start, &p.current_token().loc);
+                    return Ok(Self::Primitive(Primitive::Null, span, p.node_id()));
                 }
 
                 let expr = Expression::parse(p)?;
                 let t = &p.peek_token().value;
 
                 if t == &TokenValue::RightParen {
-                    _ = p.next_token();
-                    Ok(expr)
+                    p.next_token()?;
+                    Self::parse_method(p, expr, true)
                 } else {
-                    Err(Error::new(&format!("expected right paren; got {t}")))
+                    Err(Error::expected("right paren", p.peek_token()))
                 }
             }
+            TokenValue::LeftBracket => {
+                let start = p.current_token().loc;
+                let items = Self::parse_list(p)?;
+                let span = Span::new(&start, &p.current_token().loc);
+                let expr = Self::List(items, span, p.node_id());
+                Self::parse_method(p, expr, true)
+            }
+            TokenValue::InfixStart => {
+                let expr = Self::parse_infix_group(p)?;
+                Self::parse_method(p, expr, true)
+            }
             TokenValue::And => Ok(Self::And(And::parse(p)?)),
             TokenValue::Or => Ok(Self::Or(Or::parse(p)?)),
-            TokenValue::BlockStart => Ok(Self::Function(Function::parse(p)?)),
+            TokenValue::BlockStart => Self::parse_braces(p),
             TokenValue::Integer(_)
             | TokenValue::Float(_)
+            | TokenValue::Decimal(_)
             | TokenValue::String(_)
+            | TokenValue::Text(_)
             | TokenValue::True
-            | TokenValue::False => Ok(Self::Primitive(Primitive::parse(p)?)),
+            | TokenValue::False => {
+                let expr = Self::parse_primitive(p)?;
+                Self::parse_method(p, expr, true)
+            }
             TokenValue::Ident(_) => match p.peek_token().value {
                 TokenValue::EOF | TokenValue::Semicolon | TokenValue::Newline => {
                     Ok(Self::Identifier(Identifier::parse(p)?))
                 }
+                TokenValue::Dot | TokenValue::QuestionDot => {
+                    let expr = Self::Identifier(Identifier::parse(p)?);
+                    Self::parse_method(p, expr, true)
+                }
                 _ => Ok(Self::Call(Call::parse(p)?)),
             },
             TokenValue::Equal
@@ -263,17 +832,28 @@ impl Parse for Expression {
             | TokenValue::Minus
             | TokenValue::Asterisk
             | TokenValue::Slash
+            | TokenValue::DoubleSlash
             | TokenValue::Bang => Ok(Self::Operator(Operator::parse(p)?)),
-            t => Err(Error::new(&format!("unexpected token {t}"))),
-        }
+            _ => Err(Error::unexpected(&p.current_token())),
+        })
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Primitive {
     Integer(i64),
+    /// An integer that doesn't fit in an `i64`. Integers that do are always
+    /// `Integer`, so the two never compare equal.
+    #[cfg(feature = "bigint")]
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_display"))]
+    BigInt(num_bigint::BigInt),
+    /// An exact decimal number, written with a `d` suffix like `1.10d`.
+    #[cfg(feature = "decimal")]
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_display"))]
+    Decimal(rust_decimal::Decimal),
     Float(f64),
-    String(String),
+    String(Shared<str>),
     Boolean(bool),
     Null,
 }
@@ -281,9 +861,32 @@ pub enum Primitive {
 impl Parse for Primitive {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
         Ok(match p.current_token().value {
-            TokenValue::Integer(v) => Self::Integer(v.parse()?),
-            TokenValue::Float(v) => Self::Float(v.parse()?),
-            TokenValue::String(v) => Self::String(v),
+            TokenValue::Integer(v) => match v.parse() {
+                Ok(v) => Self::Integer(v),
+                #[cfg(feature = "bigint")]
+                Err(_) => Self::BigInt(v.parse().map_err(|_| {
+                    Error::new(&format!("invalid integer literal {}", v)).at(&p.current_token().loc)
+                })?),
+                #[cfg(not(feature = "bigint"))]
+                Err(e) => {
+                    return Err(Error::new(&format!("invalid integer literal {}: {}", v, e))
+                        .at(&p.current_token().loc))
+                }
+            },
+            TokenValue::Float(v) => Self::Float(v.parse().map_err(|e| {
+                Error::new(&format!("invalid float literal {}: {}", v, e))
+                    .at(&p.current_token().loc)
+            })?),
+            #[cfg(feature = "decimal")]
+            TokenValue::Decimal(v) => Self::Decimal(v.parse().map_err(|_| {
+                Error::new(&format!("invalid decimal literal {}", v)).at(&p.current_token().loc)
+            })?),
+            #[cfg(not(feature = "decimal"))]
+            TokenValue::Decimal(_) => {
+                return Err(Error::new("decimal literals need the decimal feature")
+                    .at(&p.current_token().loc))
+            }
+            TokenValue::String(v) | TokenValue::Text(v) => Self::String(v.into()),
             TokenValue::True => Self::Boolean(true),
             TokenValue::False => Self::Boolean(false),
             _ => unreachable!(),
@@ -291,53 +894,84 @@ impl Parse for Primitive {
     }
 }
 
-impl Display for Primitive {
-    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+impl Primitive {
+    pub fn type_name(&self) -> &'static str {
         match *self {
-            Primitive::Integer(_) => write!(f, "integer"),
-            Primitive::Float(_) => write!(f, "float"),
-            Primitive::String(_) => write!(f, "string"),
-            Primitive::Boolean(_) => write!(f, "boolean"),
-            Primitive::Null => write!(f, "null"),
+            Primitive::Integer(_) => "integer",
+            #[cfg(feature = "bigint")]
+            Primitive::BigInt(_) => "integer",
+            #[cfg(feature = "decimal")]
+            Primitive::Decimal(_) => "decimal",
+            Primitive::Float(_) => "float",
+            Primitive::String(_) => "string",
+            Primitive::Boolean(_) => "boolean",
+            Primitive::Null => "null",
         }
     }
 }
 
+/// Serializes numbers that JSON numbers can't hold exactly as strings.
+#[cfg(all(feature = "serde", any(feature = "bigint", feature = "decimal")))]
+fn serialize_display<T: Display, S: serde::Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+impl Display for Primitive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.type_name())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Identifier {
-    pub value: String,
+    pub value: Symbol,
+    pub loc: Location,
+    pub id: NodeId,
+}
+
+impl Identifier {
+    pub fn span(&self) -> Span {
+        Span::from(&self.loc)
+    }
 }
 
 impl Parse for Identifier {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
-        match p.current_token().value {
-            TokenValue::Ident(value) => Ok(Self { value }),
-            t => Err(Error::new(&format!("unexpected token {t}"))),
+        let token = p.current_token();
+        match &token.value {
+            TokenValue::Ident(value) if p.dialect().is_reserved(value) => Err(Error::Dialect {
+                message: format!("{} is not allowed in this dialect", value),
+                loc: token.loc,
+            }),
+            TokenValue::Ident(value) => Ok(Self {
+                value: Symbol::intern(value),
+                loc: token.loc.clone(),
+                id: p.node_id(),
+            }),
+            _ => Err(Error::unexpected(&token)),
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Operator {
     pub kind: OperatorKind,
     pub args: Vec<Expression>,
+    pub loc: Location,
+    pub span: Span,
+    pub id: NodeId,
 }
 
 impl Parse for Operator {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
-        let kind = match p.current_token().value {
-            TokenValue::Equal => OperatorKind::Equal,
-            TokenValue::Greater => OperatorKind::Greater,
-            TokenValue::GreaterEqual => OperatorKind::GreaterEqual,
-            TokenValue::Less => OperatorKind::Less,
-            TokenValue::LessEqual => OperatorKind::LessEqual,
-            TokenValue::Plus => OperatorKind::Add,
-            TokenValue::Minus => OperatorKind::Subtract,
-            TokenValue::Asterisk => OperatorKind::Multiply,
-            TokenValue::Slash => OperatorKind::Divide,
-            TokenValue::Bang => OperatorKind::Inverse,
-            _ => unreachable!(),
-        };
+        let loc = p.current_token().loc;
+        let kind = OperatorKind::from_token(&p.current_token().value)
+            .expect("token should be an operator");
 
         let mut args = Vec::new();
 
@@ -347,22 +981,66 @@ impl Parse for Operator {
                 | TokenValue::Semicolon
                 | TokenValue::Newline
                 | TokenValue::RightParen
-                | TokenValue::BlockStart => break,
+                | TokenValue::RightBracket
+                | TokenValue::BlockStart
+                | TokenValue::BlockEnd => break,
+                TokenValue::Comma => skip_comma(p, args.is_empty())?,
+                _ if p.is_strict() && kind == OperatorKind::Inverse && args.len() == 1 => {
+                    return Err(Error::expected(
+                        "exactly one argument for inverse operator",
+                        p.peek_token(),
+                    ));
+                }
                 _ => {
-                    _ = p.next_token();
+                    // once `$(` or a number is read the argument can only be an infix
+                    // group or that number, so there's no other meaning to guess if
+                    // it's wrong
+                    let certain = matches!(
+                        p.next_token()?.value,
+                        TokenValue::InfixStart
+                            | TokenValue::Integer(_)
+                            | TokenValue::Float(_)
+                            | TokenValue::Decimal(_)
+                    );
                     match Expression::parse_non_call(p) {
                         Ok(expr) => args.push(expr),
+                        Err(e @ (Error::TooDeep { .. } | Error::Dialect { .. })) => return Err(e),
+                        Err(e) if certain || p.is_strict() => return Err(e),
                         Err(_) => break,
                     }
                 }
             }
         }
 
-        Ok(Self { kind, args })
+        let min = match kind {
+            OperatorKind::Subtract | OperatorKind::Inverse => 1,
+            _ => 2,
+        };
+        if p.is_strict() && args.len() < min {
+            let expected = match min {
+                1 => format!("an argument for {} operator", kind),
+                _ => format!("at least 2 arguments for {} operator", kind),
+            };
+            return Err(Error::expected(&expected, p.peek_token()));
+        }
+
+        let span = match args.last() {
+            Some(arg) => Span::from(&loc).to(&arg.span()),
+            None => Span::from(&loc),
+        };
+
+        Ok(Self {
+            kind,
+            args,
+            loc,
+            span,
+            id: p.node_id(),
+        })
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum OperatorKind {
     Equal,
     Greater,
@@ -373,9 +1051,30 @@ pub enum OperatorKind {
     Subtract,
     Multiply,
     Divide,
+    FloorDivide,
     Inverse,
 }
 
+impl OperatorKind {
+    /// Gets the operator a token stands for, if it is one.
+    pub fn from_token(value: &TokenValue) -> Option<Self> {
+        Some(match value {
+            TokenValue::Equal => Self::Equal,
+            TokenValue::Greater => Self::Greater,
+            TokenValue::GreaterEqual => Self::GreaterEqual,
+            TokenValue::Less => Self::Less,
+            TokenValue::LessEqual => Self::LessEqual,
+            TokenValue::Plus => Self::Add,
+            TokenValue::Minus => Self::Subtract,
+            TokenValue::Asterisk => Self::Multiply,
+            TokenValue::Slash => Self::Divide,
+            TokenValue::DoubleSlash => Self::FloorDivide,
+            TokenValue::Bang => Self::Inverse,
+            _ => return None,
+        })
+    }
+}
+
 impl Display for OperatorKind {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match *self {
@@ -388,75 +1087,172 @@ impl Display for OperatorKind {
             OperatorKind::Subtract => write!(f, "subtract"),
             OperatorKind::Multiply => write!(f, "multiply"),
             OperatorKind::Divide => write!(f, "divide"),
+            OperatorKind::FloorDivide => write!(f, "floor divide"),
             OperatorKind::Inverse => write!(f, "inverse"),
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Function {
     pub params: Vec<Identifier>,
-    pub body: Vec<Statement>,
+    /// Shared so that copies of a function value don't copy its body.
+    pub body: Shared<[Statement]>,
+    /// Identifies the body, which has to be given a new ID when it's replaced.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub body_id: BodyId,
+    pub doc: Option<String>,
+    /// Whether the body has a `yield` in it, which makes calling the function
+    /// give an iterator over the values it yields instead of running it.
+    pub generator: bool,
+    pub span: Span,
+    pub id: NodeId,
 }
 
-impl Parse for Function {
-    fn parse(p: &mut Parser) -> Result<Self, Error> {
+impl Function {
+    /// Parses the body of a function with the parameters `params` whose opening
+    /// brace is at `start`, with the cursor on the token after the parameters, or
+    /// after the opening brace if there are none.
+    fn parse_body(
+        p: &mut Parser,
+        start: &Location,
+        params: Vec<Identifier>,
+    ) -> Result<Self, Error> {
+        let (body, yields) = p.yielding(Some(false), |p| Block::parse_body(p, start));
+        let (mut body, span) = body?;
+
+        let mut doc = None;
+        if body.len() > 1 {
+            if let Statement::Expression(Expression::Primitive(Primitive::String(s), ..)) = &body[0]
+            {
+                doc = Some(s.to_string());
+                body.remove(0);
+            }
+        }
+
+        Ok(Self {
+            params,
+            body: body.into(),
+            body_id: BodyId::next(),
+            doc,
+            generator: yields == Some(true),
+            span,
+            id: p.node_id(),
+        })
+    }
+
+    /// Parses the parameters in `[ ... ]`, leaving the cursor on the token after
+    /// the closing bracket.
+    fn parse_params(p: &mut Parser) -> Result<Vec<Identifier>, Error> {
         let mut params = Vec::new();
 
-        if p.next_token().value == TokenValue::LeftBracket {
-            match p.next_token().value {
-                TokenValue::EOF => return Err(Error::new("unexpected end of file")),
-                TokenValue::RightBracket => _ = p.next_token(),
+        loop {
+            match p.peek_token().value {
+                TokenValue::RightBracket => {
+                    p.next_token()?;
+                    p.next_token()?;
+                    break;
+                }
+                TokenValue::Comma => skip_comma(p, params.is_empty())?,
                 _ => {
+                    p.next_token()?;
                     params.push(Identifier::parse(p)?);
-                    loop {
-                        match p.next_token().value {
-                            TokenValue::EOF => return Err(Error::new("unexpected end of file")),
-                            TokenValue::RightBracket => {
-                                _ = p.next_token();
-                                break;
-                            }
-                            _ => params.push(Identifier::parse(p)?),
-                        }
-                    }
                 }
             }
         }
 
+        Ok(params)
+    }
+}
+
+impl Parse for Function {
+    fn parse(p: &mut Parser) -> Result<Self, Error> {
+        p.expect_allowed(Construct::Functions)?;
+        let start = p.current_token().loc;
+        if p.next_token()?.value != TokenValue::LeftBracket {
+            return Err(Error::expected("left bracket", &p.current_token()));
+        }
+
+        let params = Self::parse_params(p)?;
+        Self::parse_body(p, &start, params)
+    }
+}
+
+/// Statements in braces without a parameter list, which are run in their own
+/// scope as soon as they're reached, giving the value of the last one.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Block {
+    /// Shared like a function body, so the VM can compile it once.
+    pub body: Shared<[Statement]>,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+impl Block {
+    /// Parses statements up to the closing brace of a block or function starting
+    /// at `start`, with the cursor on the first token after the opening brace or
+    /// parameters. The cursor is left on the token after the closing brace, or on
+    /// the end of the file if the block isn't closed.
+    fn parse_body(p: &mut Parser, start: &Location) -> Result<(Vec<Statement>, Span), Error> {
         let mut body = Vec::new();
 
         loop {
             match p.current_token().value {
-                TokenValue::EOF => return Err(Error::new("unexpected end of file")),
-                TokenValue::Semicolon | TokenValue::Newline => _ = p.next_token(),
-                TokenValue::BlockEnd => {
-                    _ = p.next_token();
-                    break;
+                TokenValue::EOF => {
+                    p.unclosed(start);
+                    return Ok((body, Span::new(start, &p.current_token().loc)));
                 }
+                TokenValue::Semicolon | TokenValue::Newline => _ = p.next_token(),
+                TokenValue::BlockEnd => break,
                 _ => {
-                    body.push(Statement::parse(p)?);
+                    body.extend(Statement::parse_or_recover(p));
                     if p.current_token().value == TokenValue::BlockEnd {
-                        _ = p.next_token();
                         break;
                     }
-                    _ = p.next_token();
+                    p.next_token()?;
                 }
             }
         }
 
-        Ok(Self { params, body })
+        let span = Span::new(start, &p.current_token().loc);
+        p.next_token()?;
+
+        Ok((body, span))
+    }
+}
+
+impl Parse for Block {
+    fn parse(p: &mut Parser) -> Result<Self, Error> {
+        p.expect_allowed(Construct::Blocks)?;
+        let start = p.current_token().loc;
+        p.next_token()?;
+        let (body, span) = Self::parse_body(p, &start)?;
+
+        Ok(Self {
+            body: body.into(),
+            span,
+            id: p.node_id(),
+        })
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Call {
     pub name: Identifier,
     pub args: Vec<Expression>,
+    /// The call was written with `?.`, so it isn't made if its first argument is
+    /// null, giving null instead.
+    pub null_safe: bool,
+    pub loc: Location,
+    pub span: Span,
+    pub id: NodeId,
 }
 
-impl Parse for Call {
-    fn parse(p: &mut Parser) -> Result<Self, Error> {
-        let name = Identifier::parse(p)?;
+impl Call {
+    fn parse_args(p: &mut Parser) -> Result<Vec<Expression>, Error> {
         let mut args = Vec::new();
 
         loop {
@@ -464,26 +1260,62 @@ impl Parse for Call {
                 TokenValue::EOF
                 | TokenValue::Semicolon
                 | TokenValue::Newline
-                | TokenValue::RightParen => break,
+                | TokenValue::RightParen
+                | TokenValue::RightBracket
+                | TokenValue::BlockEnd => break,
+                TokenValue::Comma => skip_comma(p, args.is_empty())?,
                 _ => {
-                    _ = p.next_token();
+                    // once `$(` or a number is read the argument can only be an infix
+                    // group or that number, so there's no other meaning to guess if
+                    // it's wrong
+                    let certain = matches!(
+                        p.next_token()?.value,
+                        TokenValue::InfixStart
+                            | TokenValue::Integer(_)
+                            | TokenValue::Float(_)
+                            | TokenValue::Decimal(_)
+                    );
                     match Expression::parse_non_call(p) {
                         Ok(expr) => args.push(expr),
+                        Err(e @ (Error::TooDeep { .. } | Error::Dialect { .. })) => return Err(e),
+                        Err(e) if certain || p.is_strict() => return Err(e),
                         Err(_) => break,
                     }
                 }
             }
         }
 
-        Ok(Self { name, args })
+        Ok(args)
+    }
+}
+
+impl Parse for Call {
+    fn parse(p: &mut Parser) -> Result<Self, Error> {
+        let loc = p.current_token().loc;
+        let name = Identifier::parse(p)?;
+        let args = Call::parse_args(p)?;
+
+        let end = args.last().map_or_else(|| name.span(), Expression::span);
+        let span = Span::from(&loc).to(&end);
+
+        Ok(Self {
+            name,
+            args,
+            null_safe: false,
+            loc,
+            span,
+            id: p.node_id(),
+        })
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub struct And(pub Vec<Expression>);
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct And(pub Vec<Expression>, pub Span, pub NodeId);
 
 impl Parse for And {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
+        let start = Span::from(&p.current_token().loc);
         let mut args = Vec::new();
 
         loop {
@@ -492,23 +1324,31 @@ impl Parse for And {
                 | TokenValue::Semicolon
                 | TokenValue::Newline
                 | TokenValue::RightParen
-                | TokenValue::BlockStart => break,
+                | TokenValue::RightBracket
+                | TokenValue::BlockStart
+                | TokenValue::BlockEnd => break,
                 _ => {
-                    _ = p.next_token();
+                    p.next_token()?;
                     args.push(Expression::parse(p)?);
                 }
             }
         }
 
-        Ok(Self(args))
+        let span = args
+            .last()
+            .map_or(start.clone(), |arg| start.to(&arg.span()));
+
+        Ok(Self(args, span, p.node_id()))
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub struct Or(pub Vec<Expression>);
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Or(pub Vec<Expression>, pub Span, pub NodeId);
 
 impl Parse for Or {
     fn parse(p: &mut Parser) -> Result<Self, Error> {
+        let start = Span::from(&p.current_token().loc);
         let mut args = Vec::new();
 
         loop {
@@ -517,14 +1357,92 @@ impl Parse for Or {
                 | TokenValue::Semicolon
                 | TokenValue::Newline
                 | TokenValue::RightParen
-                | TokenValue::BlockStart => break,
+                | TokenValue::RightBracket
+                | TokenValue::BlockStart
+                | TokenValue::BlockEnd => break,
                 _ => {
-                    _ = p.next_token();
+                    p.next_token()?;
                     args.push(Expression::parse(p)?);
                 }
             }
         }
 
-        Ok(Self(args))
+        let span = args
+            .last()
+            .map_or(start.clone(), |arg| start.to(&arg.span()));
+
+        Ok(Self(args, span, p.node_id()))
+    }
+}
+
+/// Moves onto the comma after the cursor, which can optionally separate the
+/// parameters of a function, the items of a list and the arguments of a call, but
+/// only once after each of them.
+fn skip_comma(p: &mut Parser, first: bool) -> Result<(), Error> {
+    if first || p.current_token().value == TokenValue::Comma {
+        return Err(Error::unexpected(p.peek_token()));
+    }
+    p.next_token()?;
+
+    Ok(())
+}
+
+/// Whether the token starts a method call, as either `.` or `?.`.
+fn is_method_dot(value: &TokenValue) -> bool {
+    matches!(value, TokenValue::Dot | TokenValue::QuestionDot)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        lexer::Lexer,
+        parser::{sexpr, Parser},
+    };
+
+    /// Parses `source`, giving the message of its first error along with the
+    /// byte offsets of where it is.
+    #[cfg(not(feature = "bigint"))]
+    fn error(source: &str) -> (String, u32, u32) {
+        let e = Parser::from_lexer(Lexer::new(source))
+            .parse()
+            .expect_err("source should fail to parse");
+        let loc = e.loc().expect("error should have a location");
+
+        (e.to_string(), loc.start, loc.end)
+    }
+
+    #[cfg(not(feature = "bigint"))]
+    #[test]
+    fn test_integer_too_large() {
+        for source in [
+            "= x 9223372036854775808",
+            "+ 1 9223372036854775808",
+            "println 1 9223372036854775808",
+        ] {
+            let (message, start, end) = error(source);
+            assert!(
+                message.starts_with("invalid integer literal 9223372036854775808"),
+                "{}",
+                message
+            );
+            assert_eq!(&source[start as usize..end as usize], "9223372036854775808");
+        }
+    }
+
+    #[test]
+    fn test_integer_fits() {
+        let program = Parser::from_lexer(Lexer::new("= x 9223372036854775807")).parse();
+        assert!(program.is_ok(), "{:?}", program.err());
+    }
+
+    #[test]
+    fn test_null_safe_methods() {
+        let program = Parser::from_lexer(Lexer::new("m?.get \"a\" 1\nf xs?.len m.keys"))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            sexpr::program(&program),
+            "(call? get m \"a\" 1)\n(call f (call? len xs) (call keys m))\n"
+        );
     }
 }
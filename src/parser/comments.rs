@@ -0,0 +1,253 @@
+use super::ast::{Expression, NodeId, Program, Statement};
+use crate::lexer::token::{Span, Token, TokenValue};
+use std::collections::BTreeMap;
+
+/// The comments around a statement, which are only kept when the tokens come
+/// from a lexer that keeps comments.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Comments {
+    /// Comments on their own lines just before the statement.
+    pub leading: Vec<String>,
+    /// A comment at the end of the statement's last line.
+    pub trailing: Option<String>,
+    /// Comments on their own lines after the statement when nothing else comes
+    /// after it in the same block, such as at the end of a function.
+    pub after: Vec<String>,
+    /// A comment at the end of the line that opens the block of a function,
+    /// block expression, if or defer statement, such as after the parameters of
+    /// a function. It's attached to the function or statement, not the first
+    /// statement in the block.
+    pub opening: Option<String>,
+}
+
+type Position = (i32, i32);
+
+struct Comment {
+    pos: Position,
+    text: String,
+}
+
+/// Statements that are parsed together, such as the body of a function, and the
+/// part of the source they're in.
+struct Block<'a> {
+    start: Position,
+    stop: Position,
+    statements: Vec<&'a Statement>,
+    /// The node that owns the block and the line it opens on, for a comment at
+    /// the end of that line.
+    opener: Option<(NodeId, i32)>,
+}
+
+/// Attaches each comment token to the nearest statement in the innermost block
+/// it's in. Comments on the same line after a statement trail it, a comment on
+/// the line a block opens on goes with whatever the block belongs to, other
+/// comments lead the statement after them, and comments at the end of a block
+/// follow its last statement, or the statement the block belongs to if it's
+/// empty.
+pub fn attach(program: &mut Program, tokens: Vec<Token>) {
+    let comments = tokens
+        .into_iter()
+        .filter_map(|t| match t.value {
+            TokenValue::Comment(text) => Some(Comment {
+                pos: (t.loc.line_start, t.loc.col_start),
+                text,
+            }),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    if comments.is_empty() {
+        return;
+    }
+
+    let block = Block {
+        start: (0, 0),
+        stop: (i32::MAX, i32::MAX),
+        statements: program.statements.iter().collect(),
+        opener: None,
+    };
+    let mut found = Found::default();
+    found.block(&block, comments, None);
+
+    program.comments = found.comments;
+    program.dangling = found.dangling;
+}
+
+#[derive(Default)]
+struct Found {
+    comments: BTreeMap<NodeId, Comments>,
+    dangling: Vec<String>,
+}
+
+impl Found {
+    fn block(&mut self, block: &Block, comments: Vec<Comment>, owner: Option<NodeId>) {
+        let mut inner: Vec<(&Statement, Vec<Comment>)> = Vec::new();
+
+        for comment in comments {
+            if let Some(stmt) = block
+                .statements
+                .iter()
+                .find(|s| contains(&s.span(), comment.pos))
+            {
+                match inner.iter_mut().find(|(s, _)| s.id() == stmt.id()) {
+                    Some((_, list)) => list.push(comment),
+                    None => inner.push((stmt, vec![comment])),
+                }
+                continue;
+            }
+
+            let before = block.statements.iter().rev().find(|s| {
+                let span = s.span();
+                (span.line_stop, span.col_stop) <= comment.pos
+            });
+            let next = block.statements.iter().find(|s| {
+                let span = s.span();
+                (span.line_start, span.col_start) > comment.pos
+            });
+
+            match (before, next) {
+                (None, _) if block.opener.is_some_and(|(_, line)| line == comment.pos.0) => {
+                    let (id, _) = block.opener.expect("block should have an opener");
+                    let entry = self.entry(id);
+                    if entry.opening.is_none() {
+                        entry.opening = Some(comment.text);
+                    } else {
+                        entry.after.push(comment.text);
+                    }
+                }
+                (Some(stmt), _) if stmt.span().line_stop == comment.pos.0 => {
+                    let entry = self.entry(stmt.id());
+                    if entry.trailing.is_none() {
+                        entry.trailing = Some(comment.text);
+                    } else {
+                        entry.after.push(comment.text);
+                    }
+                }
+                (_, Some(stmt)) => self.entry(stmt.id()).leading.push(comment.text),
+                (Some(stmt), None) => self.entry(stmt.id()).after.push(comment.text),
+                (None, None) => match owner {
+                    Some(id) => self.entry(id).after.push(comment.text),
+                    None => self.dangling.push(comment.text),
+                },
+            }
+        }
+
+        for (stmt, comments) in inner {
+            self.statement(stmt, comments);
+        }
+    }
+
+    /// Attaches comments that are inside a statement to the blocks within it, or
+    /// before the statement if they're only inside an expression spanning lines.
+    fn statement(&mut self, stmt: &Statement, comments: Vec<Comment>) {
+        let blocks = blocks(stmt);
+        let mut grouped: Vec<Vec<Comment>> = blocks.iter().map(|_| Vec::new()).collect();
+
+        for comment in comments {
+            match blocks
+                .iter()
+                .position(|b| b.start <= comment.pos && comment.pos < b.stop)
+            {
+                Some(i) => grouped[i].push(comment),
+                None => self.entry(stmt.id()).leading.push(comment.text),
+            }
+        }
+
+        for (block, comments) in blocks.iter().zip(grouped) {
+            if !comments.is_empty() {
+                self.block(block, comments, Some(stmt.id()));
+            }
+        }
+    }
+
+    fn entry(&mut self, id: NodeId) -> &mut Comments {
+        self.comments.entry(id).or_default()
+    }
+}
+
+fn contains(span: &Span, pos: Position) -> bool {
+    (span.line_start, span.col_start) <= pos && pos < (span.line_stop, span.col_stop)
+}
+
+/// Gets the blocks directly within a statement, with the bodies of functions in
+/// its expressions first since they can be inside the blocks of an if statement.
+fn blocks(stmt: &Statement) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+
+    match stmt {
+        Statement::Assign(a) => functions(&a.value, &mut blocks),
+        Statement::If(i) => {
+            functions(&i.condition, &mut blocks);
+
+            let start = (i.span.line_start, i.span.col_start);
+            let stop = (i.span.line_stop, i.span.col_stop);
+            // comments on the lines between the blocks go with the else block, so
+            // a comment above the first statement in it stays there
+            let middle = match (
+                i.consequence.last(),
+                i.alternative.as_ref().and_then(|a| a.first()),
+            ) {
+                (_, None) => stop,
+                (Some(last), Some(_)) => (last.span().line_stop + 1, 0),
+                (None, Some(first)) => (first.span().line_start, first.span().col_start),
+            };
+
+            blocks.push(Block {
+                start,
+                stop: middle,
+                statements: i.consequence.iter().map(|s| s.as_ref()).collect(),
+                opener: Some((i.id, i.span.line_start)),
+            });
+            if let Some(alternative) = &i.alternative {
+                blocks.push(Block {
+                    start: middle,
+                    stop,
+                    statements: alternative.iter().map(|s| s.as_ref()).collect(),
+                    opener: None,
+                });
+            }
+        }
+        Statement::Assert(a) => {
+            functions(&a.condition, &mut blocks);
+            if let Some(message) = &a.message {
+                functions(message, &mut blocks);
+            }
+        }
+        Statement::Defer(d) => blocks.push(Block {
+            start: (d.span.line_start, d.span.col_start),
+            stop: (d.span.line_stop, d.span.col_stop),
+            statements: d.body.iter().collect(),
+            opener: Some((d.id, d.span.line_start)),
+        }),
+        Statement::Yield(y) => functions(&y.value, &mut blocks),
+        Statement::Expression(e) => functions(e, &mut blocks),
+    }
+
+    blocks
+}
+
+/// Collects the bodies of the functions and blocks in an expression, without
+/// going into them.
+fn functions<'a>(expr: &'a Expression, blocks: &mut Vec<Block<'a>>) {
+    match expr {
+        Expression::Function(f) => blocks.push(Block {
+            start: (f.span.line_start, f.span.col_start),
+            stop: (f.span.line_stop, f.span.col_stop),
+            statements: f.body.iter().collect(),
+            opener: Some((f.id, f.span.line_start)),
+        }),
+        Expression::Block(b) => blocks.push(Block {
+            start: (b.span.line_start, b.span.col_start),
+            stop: (b.span.line_stop, b.span.col_stop),
+            statements: b.body.iter().collect(),
+            opener: Some((b.id, b.span.line_start)),
+        }),
+        Expression::Operator(o) => o.args.iter().for_each(|a| functions(a, blocks)),
+        Expression::Call(c) => c.args.iter().for_each(|a| functions(a, blocks)),
+        Expression::List(items, ..) => items.iter().for_each(|i| functions(i, blocks)),
+        Expression::And(a) => a.0.iter().for_each(|e| functions(e, blocks)),
+        Expression::Or(o) => o.0.iter().for_each(|e| functions(e, blocks)),
+        Expression::Primitive(..) | Expression::Identifier(_) => {}
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// The constructs that the parser accepts and the names that programs can't use,
+/// for embedding a restricted version of the language. Everything is allowed by
+/// default; use [`Dialect::minimal`] to start from only assignments, calls,
+/// operators and literals.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dialect {
+    /// `if` statements, with their `else` blocks.
+    pub if_else: bool,
+    /// `assert` statements.
+    pub assert: bool,
+    /// `defer` statements.
+    pub defer: bool,
+    /// `yield` statements, which make the functions they're in generators.
+    pub generators: bool,
+    /// Functions like `{ [a b] + a b }`.
+    pub functions: bool,
+    /// Blocks like `{ = x 1; + x 1 }`.
+    pub blocks: bool,
+    /// Infix expressions like `$(1 + 2)`.
+    pub infix: bool,
+    /// Method calls like `x.to_string`.
+    pub methods: bool,
+    /// Names that can't be used anywhere in a program, such as builtins that an
+    /// embedder doesn't want scripts to call, like `exec`.
+    pub reserved: Vec<String>,
+}
+
+impl Dialect {
+    pub fn all() -> Self {
+        Self {
+            if_else: true,
+            assert: true,
+            defer: true,
+            generators: true,
+            functions: true,
+            blocks: true,
+            infix: true,
+            methods: true,
+            reserved: Vec::new(),
+        }
+    }
+
+    pub fn minimal() -> Self {
+        Self {
+            if_else: false,
+            assert: false,
+            defer: false,
+            generators: false,
+            functions: false,
+            blocks: false,
+            infix: false,
+            methods: false,
+            reserved: Vec::new(),
+        }
+    }
+
+    pub fn allows(&self, construct: Construct) -> bool {
+        match construct {
+            Construct::IfElse => self.if_else,
+            Construct::Assert => self.assert,
+            Construct::Defer => self.defer,
+            Construct::Generators => self.generators,
+            Construct::Functions => self.functions,
+            Construct::Blocks => self.blocks,
+            Construct::Infix => self.infix,
+            Construct::Methods => self.methods,
+        }
+    }
+
+    pub fn set(&mut self, construct: Construct, allowed: bool) {
+        match construct {
+            Construct::IfElse => self.if_else = allowed,
+            Construct::Assert => self.assert = allowed,
+            Construct::Defer => self.defer = allowed,
+            Construct::Generators => self.generators = allowed,
+            Construct::Functions => self.functions = allowed,
+            Construct::Blocks => self.blocks = allowed,
+            Construct::Infix => self.infix = allowed,
+            Construct::Methods => self.methods = allowed,
+        }
+    }
+
+    /// Keeps programs from using `name`, whether as a variable, a parameter or a
+    /// function to call.
+    pub fn reserve(&mut self, name: &str) {
+        if !self.is_reserved(name) {
+            self.reserved.push(name.to_string());
+        }
+    }
+
+    pub fn is_reserved(&self, name: &str) -> bool {
+        self.reserved.iter().any(|r| r == name)
+    }
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// A single construct, named the same as the [`Dialect`] fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Construct {
+    IfElse,
+    Assert,
+    Defer,
+    Generators,
+    Functions,
+    Blocks,
+    Infix,
+    Methods,
+}
+
+impl Display for Construct {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Construct::IfElse => write!(f, "if statements"),
+            Construct::Assert => write!(f, "assert statements"),
+            Construct::Defer => write!(f, "defer statements"),
+            Construct::Generators => write!(f, "yield statements"),
+            Construct::Functions => write!(f, "functions"),
+            Construct::Blocks => write!(f, "blocks"),
+            Construct::Infix => write!(f, "infix expressions"),
+            Construct::Methods => write!(f, "method calls"),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use super::ast::{BodyId, Expression, Program, Statement};
+use crate::sync::Shared;
+
+/// Rewrites a syntax tree in place, such as to replace expressions with simpler
+/// ones. Each method is called for every node of its kind, and by default goes
+/// into the node's children with the matching `walk_` function, so a pass only
+/// overrides the methods for the nodes it changes. An override that still wants
+/// the children rewritten calls the `walk_` function itself, before or after
+/// changing the node.
+///
+/// Passes are run one after the other by folding the program with each.
+pub trait Fold {
+    fn fold_program(&mut self, program: &mut Program) {
+        walk_program(self, program);
+    }
+
+    fn fold_statement(&mut self, stmt: &mut Statement) {
+        walk_statement(self, stmt);
+    }
+
+    fn fold_expression(&mut self, expr: &mut Expression) {
+        walk_expression(self, expr);
+    }
+}
+
+pub fn walk_program<F: Fold + ?Sized>(f: &mut F, program: &mut Program) {
+    for stmt in &mut program.statements {
+        f.fold_statement(stmt);
+    }
+}
+
+pub fn walk_statement<F: Fold + ?Sized>(f: &mut F, stmt: &mut Statement) {
+    match stmt {
+        Statement::Assign(a) => f.fold_expression(&mut a.value),
+        Statement::If(i) => {
+            f.fold_expression(&mut i.condition);
+            for stmt in &mut i.consequence {
+                f.fold_statement(stmt);
+            }
+            for stmt in i.alternative.iter_mut().flatten() {
+                f.fold_statement(stmt);
+            }
+        }
+        Statement::Assert(a) => {
+            f.fold_expression(&mut a.condition);
+            if let Some(message) = &mut a.message {
+                f.fold_expression(message);
+            }
+        }
+        Statement::Defer(d) => {
+            for stmt in &mut d.body {
+                f.fold_statement(stmt);
+            }
+        }
+        Statement::Yield(y) => f.fold_expression(&mut y.value),
+        Statement::Expression(e) => f.fold_expression(e),
+    }
+}
+
+/// Rewrites the children of an expression. Function and block bodies are shared,
+/// so they are copied to be rewritten and the node is given the new body.
+pub fn walk_expression<F: Fold + ?Sized>(f: &mut F, expr: &mut Expression) {
+    match expr {
+        Expression::Primitive(..) | Expression::Identifier(_) => {}
+        Expression::Operator(o) => {
+            for arg in &mut o.args {
+                f.fold_expression(arg);
+            }
+        }
+        Expression::Function(func) => {
+            let mut body = func.body.to_vec();
+            for stmt in &mut body {
+                f.fold_statement(stmt);
+            }
+            func.body = Shared::from(body);
+            func.body_id = BodyId::next();
+        }
+        Expression::Block(b) => {
+            let mut body = b.body.to_vec();
+            for stmt in &mut body {
+                f.fold_statement(stmt);
+            }
+            b.body = Shared::from(body);
+        }
+        Expression::Call(c) => {
+            for arg in &mut c.args {
+                f.fold_expression(arg);
+            }
+        }
+        Expression::List(items, ..) => {
+            for item in items {
+                f.fold_expression(item);
+            }
+        }
+        Expression::And(a) => {
+            for arg in &mut a.0 {
+                f.fold_expression(arg);
+            }
+        }
+        Expression::Or(o) => {
+            for arg in &mut o.0 {
+                f.fold_expression(arg);
+            }
+        }
+    }
+}
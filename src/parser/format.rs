@@ -0,0 +1,522 @@
+use super::{
+    ast::{
+        Call, Expression, Function, NodeId, OperatorKind, Primitive, Program, Statement, Syntax,
+    },
+    comments::Comments,
+    Precedence,
+};
+use std::collections::BTreeMap;
+
+/// Renders a program back into source, one statement per line with blocks
+/// indented by four spaces. The comments attached to the program are put back
+/// around their statements, so formatting a program parsed with comments kept
+/// doesn't lose any of them, and infix expressions, method calls and strings in
+/// triple quotes are written the way they were. Pragmas go first, one per line.
+pub fn program(program: &Program) -> String {
+    let mut f = Formatter {
+        comments: &program.comments,
+        syntax: &program.syntax,
+        out: String::new(),
+        indent: 0,
+    };
+    for line in program.pragmas.lines() {
+        f.out.push_str(&line);
+        f.out.push('\n');
+    }
+    for comment in &program.dangling {
+        f.comment(comment);
+    }
+    f.statements(program.statements.iter());
+
+    f.out
+}
+
+struct Formatter<'a> {
+    comments: &'a BTreeMap<NodeId, Comments>,
+    syntax: &'a BTreeMap<NodeId, Syntax>,
+    out: String,
+    indent: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn statements<'s>(&mut self, statements: impl Iterator<Item = &'s Statement>) {
+        let mut last_line = None;
+
+        for stmt in statements {
+            let comments = self.comments.get(&stmt.id());
+
+            // keep a blank line where there was at least one between statements
+            if let Some(last) = last_line {
+                let lines = comments.map_or(0, |c| c.leading.len() as i32);
+                if stmt.span().line_start - last - lines > 1 {
+                    self.out.push('\n');
+                }
+            }
+            last_line = Some(stmt.span().line_stop + comments.map_or(0, |c| c.after.len() as i32));
+            if let Some(c) = comments {
+                for comment in &c.leading {
+                    self.comment(comment);
+                }
+            }
+
+            self.pad();
+            self.statement(stmt);
+            if let Some(comment) = comments.and_then(|c| c.trailing.as_ref()) {
+                self.out.push_str(" #");
+                self.out.push_str(comment);
+            }
+            self.out.push('\n');
+
+            if let Some(c) = comments {
+                for comment in &c.after {
+                    self.comment(comment);
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Assign(a) => {
+                self.out.push_str("= ");
+                self.out.push_str(&a.name.value.to_string());
+                self.out.push(' ');
+                self.top(&a.value);
+            }
+            Statement::If(i) => {
+                self.out.push_str("if ");
+                self.condition(&i.condition);
+                self.block(Some(i.id), i.consequence.iter().map(|s| s.as_ref()));
+                if let Some(alternative) = &i.alternative {
+                    self.out.push_str(" else");
+                    self.block(None, alternative.iter().map(|s| s.as_ref()));
+                }
+            }
+            Statement::Assert(a) => {
+                self.out.push_str("assert ");
+                self.arg(&a.condition);
+                if let Some(message) = &a.message {
+                    self.out.push(' ');
+                    self.top(message);
+                }
+            }
+            Statement::Defer(d) => {
+                self.out.push_str("defer");
+                self.block(Some(d.id), d.body.iter());
+            }
+            Statement::Yield(y) => {
+                self.out.push_str("yield ");
+                self.top(&y.value);
+            }
+            Statement::Expression(e) => self.top(e),
+        }
+    }
+
+    /// Writes ` { ... }` with the statements on their own lines, after the comment
+    /// on the opening line of the statement with the ID `owner`.
+    fn block<'s>(
+        &mut self,
+        owner: Option<NodeId>,
+        statements: impl Iterator<Item = &'s Statement>,
+    ) {
+        self.out.push_str(" {");
+        if let Some(comment) = owner.and_then(|id| self.opening(id)) {
+            self.out.push_str(" #");
+            self.out.push_str(comment);
+        }
+        self.out.push('\n');
+        self.indent += 1;
+        self.statements(statements);
+        self.indent -= 1;
+        self.pad();
+        self.out.push('}');
+    }
+
+    /// Writes an expression where it's parsed on its own, like the value of an
+    /// assignment, which lets operators and calls take their arguments.
+    fn top(&mut self, expr: &Expression) {
+        match expr {
+            // a function as the last argument leaves the parser on its closing
+            // brace, which would end the block the statement is in
+            Expression::Operator(o) if matches!(o.args.last(), Some(Expression::Function(_))) => {
+                self.arg(expr)
+            }
+            Expression::Call(c) if matches!(c.args.last(), Some(Expression::Function(_))) => {
+                self.arg(expr)
+            }
+            _ => self.unwrapped(expr),
+        }
+    }
+
+    /// Writes operators, calls, `&&` and `||` without parentheses.
+    fn unwrapped(&mut self, expr: &Expression) {
+        let syntax = self.syntax(expr);
+        match expr {
+            _ if syntax.infix => self.infix_group(expr),
+            Expression::Call(c) if syntax.method => self.method(c, false),
+            Expression::Operator(o) => {
+                self.out.push_str(operator(&o.kind));
+                for arg in &o.args {
+                    self.out.push(' ');
+                    self.arg(arg);
+                }
+            }
+            Expression::Call(c) if !c.args.is_empty() => {
+                self.out.push_str(&c.name.value.to_string());
+                for arg in &c.args {
+                    self.out.push(' ');
+                    self.arg(arg);
+                }
+            }
+            Expression::And(a) => self.logical("&&", &a.0),
+            Expression::Or(o) => self.logical("||", &o.0),
+            _ => self.arg(expr),
+        }
+    }
+
+    /// Writes an expression where it's an argument, wrapping anything that would
+    /// take the arguments after it in parentheses.
+    fn arg(&mut self, expr: &Expression) {
+        let syntax = self.syntax(expr);
+        match expr {
+            _ if syntax.infix => self.infix_group(expr),
+            Expression::Primitive(Primitive::String(s), ..) if syntax.text => self.text(s),
+            Expression::Primitive(p, ..) => self.out.push_str(&primitive(p)),
+            Expression::Identifier(i) => self.out.push_str(&i.value.to_string()),
+            Expression::Function(f) => self.function(f),
+            Expression::Block(b) => self.braces("", None, &b.body, b.id),
+            Expression::List(items, ..) => {
+                self.out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push(' ');
+                    }
+                    self.arg(item);
+                }
+                self.out.push(']');
+            }
+            Expression::Call(c) if c.args.is_empty() => {
+                self.out.push('(');
+                self.out.push_str(&c.name.value.to_string());
+                self.out.push(')');
+            }
+            // a method call in an argument doesn't take arguments of its own
+            Expression::Call(c) if syntax.method && c.args.len() == 1 => self.method(c, false),
+            Expression::Operator(_)
+            | Expression::Call(_)
+            | Expression::And(_)
+            | Expression::Or(_) => {
+                self.out.push('(');
+                self.unwrapped(expr);
+                self.out.push(')');
+            }
+        }
+    }
+
+    /// Writes the condition of an if statement, where a name or call would take
+    /// the block after it as an argument.
+    fn condition(&mut self, expr: &Expression) {
+        let syntax = self.syntax(expr);
+        match expr {
+            _ if syntax.infix => self.infix_group(expr),
+            Expression::Call(_) if syntax.method => self.parenthesized(expr),
+            Expression::Identifier(i) => {
+                self.out.push_str("$(");
+                self.out.push_str(&i.value.to_string());
+                self.out.push(')');
+            }
+            Expression::Call(_) => self.arg(expr),
+            _ => self.top(expr),
+        }
+    }
+
+    /// Writes `&&` or `||` with its arguments, which are each parsed as a whole
+    /// expression, so names are wrapped to keep them from becoming calls.
+    fn logical(&mut self, symbol: &str, args: &[Expression]) {
+        self.out.push_str(symbol);
+        for arg in args {
+            self.out.push(' ');
+            match arg {
+                Expression::Identifier(id) if !self.syntax(arg).infix => {
+                    self.out.push_str("$(");
+                    self.out.push_str(&id.value.to_string());
+                    self.out.push(')');
+                }
+                Expression::Call(_) if self.syntax(arg).method && !self.syntax(arg).infix => {
+                    self.parenthesized(arg)
+                }
+                _ => self.arg(arg),
+            }
+        }
+    }
+
+    fn function(&mut self, f: &Function) {
+        let params: Vec<_> = f.params.iter().map(|p| p.value.to_string()).collect();
+        let head = format!("[{}]", params.join(" "));
+        self.braces(&head, f.doc.as_deref(), &f.body, f.id);
+    }
+
+    /// Writes `{ ... }` for a function, which has its parameters in `head`, or a
+    /// block, where it's empty. `id` is the ID of the function or block.
+    fn braces(&mut self, head: &str, doc: Option<&str>, body: &[Statement], id: NodeId) {
+        let opening = self.opening(id);
+        if let Some(line) = self.inline(head, doc, body).filter(|_| opening.is_none()) {
+            self.out.push_str(&line);
+            return;
+        }
+
+        self.out.push('{');
+        if !head.is_empty() {
+            self.out.push(' ');
+            self.out.push_str(head);
+        }
+        if let Some(comment) = opening {
+            self.out.push_str(" #");
+            self.out.push_str(comment);
+        }
+        self.out.push('\n');
+
+        self.indent += 1;
+        if let Some(doc) = doc {
+            self.pad();
+            self.out.push_str(&format!("\"{}\"\n", doc));
+        }
+        self.statements(body.iter());
+        self.indent -= 1;
+
+        self.pad();
+        self.out.push('}');
+    }
+
+    /// Gets a function or block on one line like `{ [a b] + a b }` if its body is
+    /// a single statement that fits on one, without a doc or comments.
+    fn inline(&self, head: &str, doc: Option<&str>, body: &[Statement]) -> Option<String> {
+        let [stmt] = body else {
+            return None;
+        };
+        if doc.is_some() || self.comments.contains_key(&stmt.id()) {
+            return None;
+        }
+
+        let mut inner = Formatter {
+            comments: self.comments,
+            syntax: self.syntax,
+            out: String::new(),
+            indent: 0,
+        };
+        inner.statement(stmt);
+        if inner.out.contains('\n') {
+            return None;
+        }
+
+        Some(if head.is_empty() {
+            format!("{{ {} }}", inner.out)
+        } else {
+            format!("{{ {} {} }}", head, inner.out)
+        })
+    }
+
+    /// Writes `(` and `)` around an operator or call, with its arguments inside.
+    fn parenthesized(&mut self, expr: &Expression) {
+        self.out.push('(');
+        self.unwrapped(expr);
+        self.out.push(')');
+    }
+
+    /// Writes a call as a method call on its first argument, like `xs.map f`.
+    /// In an infix expression, the receiver is written as an infix operand.
+    fn method(&mut self, c: &Call, infix: bool) {
+        let (receiver, args) = c
+            .args
+            .split_first()
+            .expect("method call should have a receiver");
+        match receiver {
+            // a point after a number would be read as part of it
+            Expression::Primitive(p, ..) if !matches!(p, Primitive::String(_)) => {
+                self.out.push('(');
+                self.arg(receiver);
+                self.out.push(')');
+            }
+            _ if infix => self.infix_operand(receiver, Precedence::Prefix, false),
+            _ => self.arg(receiver),
+        }
+        self.out.push_str(if c.null_safe { "?." } else { "." });
+        self.out.push_str(&c.name.value.to_string());
+        for arg in args {
+            self.out.push(' ');
+            self.arg(arg);
+        }
+    }
+
+    /// Writes an expression as an infix expression in `$( ... )`.
+    fn infix_group(&mut self, expr: &Expression) {
+        self.out.push_str("$(");
+        self.infix(expr);
+        self.out.push(')');
+    }
+
+    /// Writes an operand of an infix operator with the precedence `parent`, in
+    /// parentheses if it binds more loosely, or as tightly when it's on the right
+    /// since operators group from the left.
+    fn infix_operand(&mut self, expr: &Expression, parent: Precedence, right: bool) {
+        if self.syntax(expr).infix {
+            return self.infix_group(expr);
+        }
+
+        let precedence = infix_precedence(expr);
+        if precedence < parent || (right && precedence == parent) {
+            self.out.push('(');
+            self.infix(expr);
+            self.out.push(')');
+        } else {
+            self.infix(expr);
+        }
+    }
+
+    fn infix(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Operator(o) if o.args.len() == 1 => {
+                self.out.push_str(operator(&o.kind));
+                self.infix_operand(&o.args[0], Precedence::Prefix, false);
+            }
+            Expression::Operator(o) if o.args.len() == 2 => {
+                let precedence = infix_precedence(expr);
+                self.infix_operand(&o.args[0], precedence, false);
+                self.out.push(' ');
+                self.out.push_str(operator(&o.kind));
+                self.out.push(' ');
+                self.infix_operand(&o.args[1], precedence, true);
+            }
+            Expression::And(a) => self.infix_chain("&&", Precedence::And, &a.0),
+            Expression::Or(o) => self.infix_chain("||", Precedence::Or, &o.0),
+            Expression::Call(c) if self.syntax(expr).method => self.method(c, true),
+            _ => self.arg(expr),
+        }
+    }
+
+    fn infix_chain(&mut self, symbol: &str, precedence: Precedence, args: &[Expression]) {
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.out.push(' ');
+                self.out.push_str(symbol);
+                self.out.push(' ');
+            }
+            self.infix_operand(arg, precedence, i > 0);
+        }
+    }
+
+    /// Writes a string in triple quotes, on the same line if it's a single line
+    /// that would be read back the same, or else on its own lines indented one
+    /// level further than the code around it.
+    fn text(&mut self, s: &str) {
+        if !s.contains('\n') && !s.starts_with(char::is_whitespace) && !s.ends_with('"') {
+            self.out.push_str(&format!("\"\"\"{}\"\"\"", s));
+            return;
+        }
+
+        self.out.push_str("\"\"\"\n");
+        self.indent += 1;
+        for line in s.split('\n') {
+            if !line.is_empty() {
+                self.pad();
+                self.out.push_str(line);
+            }
+            self.out.push('\n');
+        }
+        self.pad();
+        self.indent -= 1;
+        self.out.push_str("\"\"\"");
+    }
+
+    fn syntax(&self, expr: &Expression) -> Syntax {
+        self.syntax.get(&expr.id()).copied().unwrap_or_default()
+    }
+
+    /// Gets the comment on the line that opens the block of the function, block
+    /// or statement with the ID `id`.
+    fn opening(&self, id: NodeId) -> Option<&'a str> {
+        self.comments.get(&id)?.opening.as_deref()
+    }
+
+    fn comment(&mut self, comment: &str) {
+        self.pad();
+        self.out.push('#');
+        self.out.push_str(comment);
+        self.out.push('\n');
+    }
+
+    fn pad(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+}
+
+fn primitive(p: &Primitive) -> String {
+    match p {
+        // there are no negative literals, only negated ones
+        Primitive::Integer(v) if *v < 0 => format!("(- {})", v.unsigned_abs()),
+        Primitive::Integer(v) => v.to_string(),
+        #[cfg(feature = "bigint")]
+        Primitive::BigInt(v) if v.sign() == num_bigint::Sign::Minus => format!("(- {})", -v),
+        #[cfg(feature = "bigint")]
+        Primitive::BigInt(v) => v.to_string(),
+        #[cfg(feature = "decimal")]
+        Primitive::Decimal(v) if v.is_sign_negative() => format!("(- {}d)", -v),
+        #[cfg(feature = "decimal")]
+        Primitive::Decimal(v) => format!("{}d", v),
+        Primitive::Float(v) if v.is_sign_negative() => format!("(- {})", float(-v)),
+        Primitive::Float(v) => float(*v),
+        Primitive::String(v) => format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")),
+        Primitive::Boolean(v) => v.to_string(),
+        Primitive::Null => "()".to_string(),
+    }
+}
+
+/// Writes a float with a point, since one without would be read as an integer.
+fn float(v: f64) -> String {
+    let s = v.to_string();
+    if s.contains('.') || !v.is_finite() {
+        s
+    } else {
+        format!("{}.0", s)
+    }
+}
+
+/// Gets how tightly an expression binds as an operand in an infix expression,
+/// where anything that isn't an operator is as tight as a prefix operator.
+fn infix_precedence(expr: &Expression) -> Precedence {
+    match expr {
+        Expression::Operator(o) if o.args.len() == 2 => match o.kind {
+            OperatorKind::Equal
+            | OperatorKind::Greater
+            | OperatorKind::GreaterEqual
+            | OperatorKind::Less
+            | OperatorKind::LessEqual => Precedence::Compare,
+            OperatorKind::Add | OperatorKind::Subtract => Precedence::Sum,
+            OperatorKind::Multiply | OperatorKind::Divide | OperatorKind::FloorDivide => {
+                Precedence::Product
+            }
+            OperatorKind::Inverse => Precedence::Prefix,
+        },
+        Expression::And(_) => Precedence::And,
+        Expression::Or(_) => Precedence::Or,
+        _ => Precedence::Prefix,
+    }
+}
+
+fn operator(kind: &OperatorKind) -> &'static str {
+    match kind {
+        OperatorKind::Equal => "==",
+        OperatorKind::Greater => ">",
+        OperatorKind::GreaterEqual => ">=",
+        OperatorKind::Less => "<",
+        OperatorKind::LessEqual => "<=",
+        OperatorKind::Add => "+",
+        OperatorKind::Subtract => "-",
+        OperatorKind::Multiply => "*",
+        OperatorKind::Divide => "/",
+        OperatorKind::FloorDivide => "//",
+        OperatorKind::Inverse => "!",
+    }
+}
@@ -1,7 +1,22 @@
-use crate::{error::Error, lexer::token::Token};
-use ast::Program;
+use crate::{
+    error::Error,
+    lexer::{
+        token::{Location, Token, TokenValue},
+        Lexer,
+    },
+};
+use ast::{Expression, NodeId, Program, Statement, Syntax};
+use dialect::{Construct, Dialect};
+use pragma::Pragmas;
+use std::collections::BTreeMap;
 
 pub mod ast;
+pub mod comments;
+pub mod dialect;
+pub mod fold;
+pub mod format;
+pub mod pragma;
+pub mod sexpr;
 
 pub trait Parse
 where
@@ -10,31 +25,407 @@ where
     fn parse(p: &mut Parser) -> Result<Self, Error>;
 }
 
+/// How tightly an infix operator binds to its operands, from loosest to
+/// tightest.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Precedence {
+    Lowest,
+    Or,
+    And,
+    Compare,
+    Sum,
+    Product,
+    Prefix,
+}
+
+impl Precedence {
+    /// Gets the precedence of a token as an infix operator, which is `Lowest` for
+    /// tokens that aren't one.
+    pub fn of(value: &TokenValue) -> Self {
+        match value {
+            TokenValue::Or => Self::Or,
+            TokenValue::And => Self::And,
+            TokenValue::Equal
+            | TokenValue::Greater
+            | TokenValue::GreaterEqual
+            | TokenValue::Less
+            | TokenValue::LessEqual => Self::Compare,
+            TokenValue::Plus | TokenValue::Minus => Self::Sum,
+            TokenValue::Asterisk | TokenValue::Slash | TokenValue::DoubleSlash => Self::Product,
+            _ => Self::Lowest,
+        }
+    }
+}
+
+/// A saved state of a [`Parser`] to go back to with [`Parser::rewind`].
+#[derive(Clone, Copy, Debug)]
+pub struct Checkpoint {
+    pos: usize,
+    errors: usize,
+    depth: usize,
+    next_id: u32,
+    yields: Option<bool>,
+    warnings: usize,
+}
+
 #[derive(Debug)]
 pub struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    errors: Vec<Error>,
+    depth: usize,
+    max_depth: usize,
+    next_id: u32,
+    comments: Vec<Token>,
+    dialect: Dialect,
+    strict: bool,
+    pragmas: Pragmas,
+    /// Whether the function being parsed has a `yield` in it, or `None` outside
+    /// of functions, where `yield` isn't allowed.
+    yields: Option<bool>,
+    syntax: BTreeMap<NodeId, Syntax>,
+    warnings: Vec<Error>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, pos: 0 }
+    /// Creates a parser for a list of tokens or anything else that gives them,
+    /// such as a [`Lexer`] that hasn't been run yet. Use [`Parser::from_lexer`]
+    /// to have the lexer's errors reported along with the parser's.
+    pub fn new(tokens: impl IntoIterator<Item = Token>) -> Self {
+        // comments are set aside to be attached to statements after parsing, and
+        // pragmas are read before it
+        let (comments, tokens): (Vec<_>, Vec<_>) = tokens
+            .into_iter()
+            .filter(|t| !matches!(t.value, TokenValue::Whitespace(_)))
+            .partition(|t| matches!(t.value, TokenValue::Comment(_)));
+        let (pragmas, mut tokens): (Vec<_>, Vec<_>) = tokens
+            .into_iter()
+            .partition(|t| matches!(t.value, TokenValue::Pragma(_)));
+        let (pragmas, errors) = Pragmas::from_tokens(&pragmas);
+
+        // the cursor stops at the EOF token, so there has to be one at the end
+        if !matches!(tokens.last(), Some(t) if t.value == TokenValue::EOF) {
+            let loc = tokens.last().map_or(Location::default(), |t| t.loc.clone());
+            tokens.push(Token::new(TokenValue::EOF, loc));
+        }
+
+        Self {
+            tokens,
+            pos: 0,
+            errors,
+            depth: 0,
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            next_id: 0,
+            comments,
+            dialect: Dialect::default(),
+            strict: pragmas.strict,
+            pragmas,
+            yields: None,
+            syntax: BTreeMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Creates a parser for the tokens from a lexer, starting with the errors it
+    /// finds in them. Parsing carries on past the invalid parts of the source,
+    /// so they're reported together with the syntax errors around them.
+    pub fn from_lexer(mut lexer: Lexer<'_>) -> Self {
+        let tokens: Vec<_> = lexer.by_ref().collect();
+        Self::with_lex_errors(tokens, lexer.take_errors())
+    }
+
+    /// Creates a parser for tokens that have already been lexed, starting with
+    /// the errors that the lexer found in them like [`Parser::from_lexer`].
+    pub fn with_lex_errors(
+        tokens: impl IntoIterator<Item = Token>,
+        mut errors: Vec<Error>,
+    ) -> Self {
+        let mut parser = Self::new(tokens);
+        errors.append(&mut parser.errors);
+        parser.errors = errors;
+
+        parser
+    }
+
+    /// How deeply expressions and blocks can be nested by default, which is well
+    /// within the stack space of the parser and the passes that run after it.
+    pub const DEFAULT_MAX_DEPTH: usize = 512;
+
+    /// Sets how deeply expressions and blocks can be nested before parsing fails
+    /// with an "expression too deeply nested" error. Each parenthesis, list, block
+    /// and operator counts as a level, so the exact depth needed for a program
+    /// depends on its syntax.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Sets which constructs and names programs can use. Anything the dialect
+    /// doesn't allow fails to parse, so it never gets to be evaluated.
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    pub fn dialect(&self) -> &Dialect {
+        &self.dialect
+    }
+
+    /// Makes the parser reject code that it would otherwise guess the meaning of.
+    /// An argument that isn't a valid expression normally ends the arguments of
+    /// a call or operator, leaving the token to be reported later or dropped, and
+    /// operators can be given too few arguments, which only fails when they're
+    /// evaluated. In strict mode both are syntax errors where they happen.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Gets the pragmas from the `#!` lines at the top of the source, which are
+    /// also given to the parsed program. `#!strict` turns on strict mode when the
+    /// parser is created.
+    pub fn pragmas(&self) -> &Pragmas {
+        &self.pragmas
+    }
+
+    /// Fails if the dialect doesn't allow `construct`, which starts at the current
+    /// token.
+    pub fn expect_allowed(&self, construct: Construct) -> Result<(), Error> {
+        if self.dialect.allows(construct) {
+            return Ok(());
+        }
+
+        Err(Error::Dialect {
+            message: format!("{} are not allowed in this dialect", construct),
+            loc: self.tokens[self.pos].loc.clone(),
+        })
+    }
+
+    /// Parses something that may contain nested syntax one level deeper, failing
+    /// if that's past the limit.
+    pub fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.depth >= self.max_depth {
+            return Err(Error::TooDeep {
+                loc: self.tokens[self.pos].loc.clone(),
+            });
+        }
+
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+
+        result
+    }
+
+    /// Parses something with `yields` as whether `yield` is allowed and has been
+    /// used, like the body of a function, giving back whether it was used in it.
+    pub fn yielding<T>(
+        &mut self,
+        yields: Option<bool>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> (T, Option<bool>) {
+        let outer = std::mem::replace(&mut self.yields, yields);
+        let result = f(self);
+
+        (result, std::mem::replace(&mut self.yields, outer))
+    }
+
+    /// Records that a `yield` was parsed, giving whether it's allowed where it
+    /// is, which is only inside a function.
+    pub fn yielded(&mut self) -> bool {
+        match &mut self.yields {
+            Some(yields) => {
+                *yields = true;
+                true
+            }
+            None => false,
+        }
     }
 
+    /// Parses the program, failing with the first error if there are any.
     pub fn parse(&mut self) -> Result<Program, Error> {
-        Program::parse(self)
+        self.parse_all().map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Parses the program, skipping past any statements that are invalid so that
+    /// the errors in all of them are returned at once. Comment tokens, which the
+    /// lexer only gives when keeping comments, are attached to the statements
+    /// around them in [`Program::comments`].
+    pub fn parse_all(&mut self) -> Result<Program, Vec<Error>> {
+        let program = Program::parse(self).map(|mut program| {
+            comments::attach(&mut program, std::mem::take(&mut self.comments));
+            program
+        });
+        let mut errors = std::mem::take(&mut self.errors);
+        match program {
+            Ok(program) if errors.is_empty() => return Ok(program),
+            Ok(_) => {}
+            Err(e) => errors.push(e),
+        }
+
+        // lex errors come first, so they're put back in order with the others
+        errors.sort_by_key(|e| e.loc().map(|loc| loc.start));
+        Err(errors)
+    }
+
+    /// Parses a single expression, like the value of an assignment, failing if
+    /// anything other than newlines and semicolons comes after it.
+    pub fn parse_expression(&mut self) -> Result<Expression, Error> {
+        while matches!(
+            self.tokens[self.pos].value,
+            TokenValue::Semicolon | TokenValue::Newline
+        ) {
+            self.pos += 1;
+        }
+
+        let expr = Expression::parse(self)?;
+        if !self.errors.is_empty() {
+            return Err(self.errors.remove(0));
+        }
+
+        // functions leave the cursor after their closing brace rather than on it
+        if !matches!(expr, Expression::Function(_)) && self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+
+        match self.tokens[self.pos..].iter().find(|t| {
+            !matches!(
+                t.value,
+                TokenValue::Semicolon | TokenValue::Newline | TokenValue::EOF
+            )
+        }) {
+            Some(token) => Err(Error::unexpected(token)),
+            None => Ok(expr),
+        }
+    }
+
+    /// Parses one statement at a time, so that each can be used as soon as it's
+    /// parsed instead of after the whole program. An invalid statement gives its
+    /// errors and is skipped like with [`Parser::parse_all`], and the statements
+    /// after it are still parsed. Comments aren't attached to the statements.
+    pub fn statements(&mut self) -> Statements<'_> {
+        Statements(self)
+    }
+
+    /// Records an error in the statement starting at `start` and skips to its end,
+    /// which is the next newline or semicolon that isn't inside a block or list, or
+    /// the end of the block the statement is in. The cursor is left on the last
+    /// token of the statement, like after parsing one.
+    pub fn recover(&mut self, error: Error, start: usize) {
+        self.errors.push(error);
+        self.skip_statement(start);
+    }
+
+    /// Records that the block whose opening brace is at `open` isn't closed before
+    /// the end of the file. The block is treated as if it were closed there, so
+    /// that its statements are kept and the errors in them are still reported.
+    pub fn unclosed(&mut self, open: &Location) {
+        self.errors.push(Error::Parse {
+            expected: Some("block end to close this block".to_string()),
+            found: TokenValue::EOF,
+            loc: open.clone(),
+            help: None,
+        });
+    }
+
+    fn skip_statement(&mut self, start: usize) {
+        let mut depth = 0usize;
+        let mut pos = start;
+
+        while pos < self.tokens.len() {
+            match self.tokens[pos].value {
+                TokenValue::EOF => break,
+                TokenValue::Semicolon | TokenValue::Newline if depth == 0 && pos >= self.pos => {
+                    break
+                }
+                TokenValue::BlockStart | TokenValue::LeftBracket => depth += 1,
+                TokenValue::BlockEnd if depth == 0 && pos > start && pos >= self.pos => break,
+                TokenValue::BlockEnd | TokenValue::RightBracket => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            pos += 1;
+        }
+
+        self.pos = pos.min(self.tokens.len() - 1).saturating_sub(1).max(start);
+    }
+
+    /// Saves the state of the parser, so that something can be parsed one way
+    /// and then parsed another way from the same token if that fails.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            pos: self.pos,
+            errors: self.errors.len(),
+            depth: self.depth,
+            next_id: self.next_id,
+            yields: self.yields,
+            warnings: self.warnings.len(),
+        }
+    }
+
+    /// Goes back to a checkpoint, forgetting any errors and warnings recorded
+    /// since and giving out the same node IDs again.
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        self.pos = checkpoint.pos;
+        self.errors.truncate(checkpoint.errors);
+        self.depth = checkpoint.depth;
+        self.next_id = checkpoint.next_id;
+        self.yields = checkpoint.yields;
+        self.syntax.split_off(&NodeId(checkpoint.next_id));
+        self.warnings.truncate(checkpoint.warnings);
+    }
+
+    /// Gets the ID for a new node, which are given out in order starting from 0.
+    pub fn node_id(&mut self) -> NodeId {
+        let id = NodeId(self.next_id);
+        self.next_id += 1;
+
+        id
+    }
+
+    /// Records a warning about the source, which doesn't stop it from parsing.
+    pub fn warn(&mut self, warning: Error) {
+        self.warnings.push(warning);
+    }
+
+    /// Takes the warnings found so far, such as for deprecated syntax, which are
+    /// given separately from errors since the program still parses.
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Gets how the expression with the ID `id` was written, to record that it
+    /// was written a different way to how it's parsed.
+    pub fn syntax(&mut self, id: NodeId) -> &mut Syntax {
+        self.syntax.entry(id).or_default()
+    }
+
+    /// Takes how the expressions parsed so far were written, for the program.
+    pub fn take_syntax(&mut self) -> BTreeMap<NodeId, Syntax> {
+        std::mem::take(&mut self.syntax)
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
     }
 
     pub fn current_token(&self) -> Token {
         self.tokens[self.pos].clone()
     }
 
-    pub fn next_token(&mut self) -> &Token {
+    /// Moves to the next token, failing with an "unexpected end of file" error if
+    /// the cursor is already on the EOF token at the end.
+    pub fn next_token(&mut self) -> Result<&Token, Error> {
+        if self.pos + 1 >= self.tokens.len() {
+            return Err(Error::unexpected(&self.tokens[self.pos]));
+        }
         self.pos += 1;
 
-        &self.tokens[self.pos]
+        Ok(&self.tokens[self.pos])
     }
 
+    /// Gets the token after the cursor, which is the EOF token once the cursor is
+    /// at the end.
     pub fn peek_token(&self) -> &Token {
         if self.pos + 1 >= self.tokens.len() {
             &self.tokens[self.tokens.len() - 1]
@@ -44,6 +435,49 @@ impl Parser {
     }
 
     pub fn back_token(&mut self) {
-        self.pos -= 1;
+        self.pos = self.pos.saturating_sub(1);
+    }
+}
+
+/// An iterator over the statements of a program as they're parsed, from
+/// [`Parser::statements`].
+#[derive(Debug)]
+pub struct Statements<'a>(&'a mut Parser);
+
+impl Iterator for Statements<'_> {
+    type Item = Result<Statement, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let p = &mut *self.0;
+
+        // errors in the blocks of the last statement are given one at a time
+        if !p.errors.is_empty() {
+            return Some(Err(p.errors.remove(0)));
+        }
+
+        loop {
+            match p.tokens[p.pos].value {
+                TokenValue::EOF => return None,
+                TokenValue::Semicolon | TokenValue::Newline => p.pos += 1,
+                _ => break,
+            }
+        }
+
+        let start = p.pos;
+        let result = match Statement::parse(p) {
+            Err(e) => {
+                p.recover(e, start);
+                Err(p.errors.remove(0))
+            }
+            // a statement with errors in its blocks is left out, giving them instead
+            Ok(_) if !p.errors.is_empty() => Err(p.errors.remove(0)),
+            Ok(stmt) => Ok(stmt),
+        };
+
+        if p.tokens[p.pos].value != TokenValue::EOF {
+            p.pos += 1;
+        }
+
+        Some(result)
     }
 }
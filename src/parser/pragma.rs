@@ -0,0 +1,77 @@
+use crate::{
+    error::Error,
+    lexer::token::{Token, TokenValue},
+};
+
+/// Settings that a script gives itself with `#!` lines at its top, before any
+/// statements. They can only make evaluation stricter than what the host allows.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Pragmas {
+    /// The program from a shebang line like `#!/usr/bin/env clip`, which is kept
+    /// so that formatting the script doesn't lose it.
+    pub interpreter: Option<String>,
+    /// `#!strict`: parse the script in strict mode.
+    pub strict: bool,
+    /// `#!no-builtins`: don't let the script call builtin functions.
+    pub no_builtins: bool,
+    /// `#!fuel <steps>`: stop evaluation after this many steps, unless the host
+    /// has set a lower limit.
+    pub fuel: Option<u64>,
+}
+
+impl Pragmas {
+    /// Reads the pragmas from their tokens, giving an error for each one that
+    /// isn't known or is missing its value.
+    pub fn from_tokens(tokens: &[Token]) -> (Self, Vec<Error>) {
+        let mut pragmas = Self::default();
+        let mut errors = Vec::new();
+
+        for token in tokens {
+            let TokenValue::Pragma(text) = &token.value else {
+                continue;
+            };
+            if text.starts_with('/') {
+                pragmas.interpreter = Some(text.clone());
+                continue;
+            }
+
+            let mut words = text.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("strict"), None, _) => pragmas.strict = true,
+                (Some("no-builtins"), None, _) => pragmas.no_builtins = true,
+                (Some("fuel"), Some(steps), None) if steps.parse::<u64>().is_ok() => {
+                    pragmas.fuel = steps.parse().ok();
+                }
+                (Some("fuel"), ..) => {
+                    errors.push(Error::expected("a number of steps after fuel", token))
+                }
+                _ => errors.push(Error::expected(
+                    "a pragma: strict, no-builtins or fuel",
+                    token,
+                )),
+            }
+        }
+
+        (pragmas, errors)
+    }
+
+    /// Gets the `#!` lines for the pragmas, to put back at the top of a script.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(interpreter) = &self.interpreter {
+            lines.push(format!("#!{}", interpreter));
+        }
+        if self.strict {
+            lines.push("#!strict".to_string());
+        }
+        if self.no_builtins {
+            lines.push("#!no-builtins".to_string());
+        }
+        if let Some(fuel) = self.fuel {
+            lines.push(format!("#!fuel {}", fuel));
+        }
+
+        lines
+    }
+}
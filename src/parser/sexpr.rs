@@ -0,0 +1,109 @@
+use super::ast::{Expression, OperatorKind, Primitive, Program, Statement};
+
+/// Renders a program as S-expressions, one statement per line, such as
+/// `(assign x (add 1 2))`. Unlike the debug output this leaves out locations, so
+/// it only changes when the structure of the program does.
+pub fn program(program: &Program) -> String {
+    let mut out = String::new();
+    for stmt in &program.statements {
+        out.push_str(&statement(stmt));
+        out.push('\n');
+    }
+
+    out
+}
+
+pub fn statement(stmt: &Statement) -> String {
+    match stmt {
+        Statement::Assign(a) => format!("(assign {} {})", a.name.value, expression(&a.value)),
+        Statement::If(i) => {
+            let mut out = format!("(if {} {}", expression(&i.condition), block(&i.consequence));
+            if let Some(alternative) = &i.alternative {
+                out.push(' ');
+                out.push_str(&block(alternative));
+            }
+            out.push(')');
+
+            out
+        }
+        Statement::Assert(a) => match &a.message {
+            Some(message) => format!(
+                "(assert {} {})",
+                expression(&a.condition),
+                expression(message)
+            ),
+            None => format!("(assert {})", expression(&a.condition)),
+        },
+        Statement::Defer(d) => list("defer", d.body.iter().map(statement)),
+        Statement::Yield(y) => format!("(yield {})", expression(&y.value)),
+        Statement::Expression(e) => expression(e),
+    }
+}
+
+pub fn expression(expr: &Expression) -> String {
+    match expr {
+        Expression::Primitive(p, ..) => primitive(p),
+        Expression::Identifier(i) => i.value.to_string(),
+        Expression::Operator(o) => list(operator(&o.kind), o.args.iter().map(expression)),
+        Expression::Function(f) => {
+            let params: Vec<_> = f.params.iter().map(|p| p.value.to_string()).collect();
+            let head = format!("fn ({})", params.join(" "));
+            list(&head, f.body.iter().map(statement))
+        }
+        Expression::Block(b) => list("block", b.body.iter().map(statement)),
+        Expression::Call(c) => {
+            let call = if c.null_safe { "call?" } else { "call" };
+            let head = format!("{} {}", call, c.name.value);
+            list(&head, c.args.iter().map(expression))
+        }
+        Expression::List(items, ..) => list("list", items.iter().map(expression)),
+        Expression::And(a) => list("and", a.0.iter().map(expression)),
+        Expression::Or(o) => list("or", o.0.iter().map(expression)),
+    }
+}
+
+fn block(statements: &[Box<Statement>]) -> String {
+    list("do", statements.iter().map(|s| statement(s)))
+}
+
+fn list(head: &str, items: impl Iterator<Item = String>) -> String {
+    let mut out = format!("({}", head);
+    for item in items {
+        out.push(' ');
+        out.push_str(&item);
+    }
+    out.push(')');
+
+    out
+}
+
+fn primitive(p: &Primitive) -> String {
+    match p {
+        Primitive::Integer(v) => v.to_string(),
+        #[cfg(feature = "bigint")]
+        Primitive::BigInt(v) => v.to_string(),
+        #[cfg(feature = "decimal")]
+        Primitive::Decimal(v) => format!("{}d", v),
+        // keep the point so floats can be told apart from integers
+        Primitive::Float(v) => format!("{:?}", v),
+        Primitive::String(v) => format!("{:?}", v),
+        Primitive::Boolean(v) => v.to_string(),
+        Primitive::Null => "()".to_string(),
+    }
+}
+
+fn operator(kind: &OperatorKind) -> &'static str {
+    match kind {
+        OperatorKind::Equal => "equal",
+        OperatorKind::Greater => "greater",
+        OperatorKind::GreaterEqual => "greater-equal",
+        OperatorKind::Less => "less",
+        OperatorKind::LessEqual => "less-equal",
+        OperatorKind::Add => "add",
+        OperatorKind::Subtract => "subtract",
+        OperatorKind::Multiply => "multiply",
+        OperatorKind::Divide => "divide",
+        OperatorKind::FloorDivide => "floor-divide",
+        OperatorKind::Inverse => "inverse",
+    }
+}
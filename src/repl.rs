@@ -1,48 +1,278 @@
 use crate::{
-    eval::{eval, Scope},
-    lexer::Lexer,
+    error::Error,
+    eval::{
+        eval, eval_vm,
+        snapshot::{self, Snapshot},
+        Scope,
+    },
+    lexer::{
+        token::{Token, TokenValue},
+        Checkpoint, Lexer,
+    },
+    optimizer,
     parser::{ast::Statement, Parser},
+    resolver,
+    symbol::Symbol,
 };
-use std::io::{self, Write};
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// How the repl evaluates each line. The limits apply to each line separately.
+#[derive(Default)]
+pub struct Options {
+    pub vm: bool,
+    pub fold: bool,
+    pub check: bool,
+    /// Parse in strict mode, see [`Parser::set_strict`].
+    pub strict: bool,
+    pub fuel: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub memory_limit: Option<usize>,
+    /// Record snapshots of the variables after each statement, which can be
+    /// stepped through with the `:back`, `:forward` and `:trace` commands.
+    pub snapshots: bool,
+}
+
+/// The tokens of a statement that's missing the closing brace of a block, which
+/// is read over more lines until it's complete. Each line is only lexed once,
+/// starting from where the last one ended.
+#[derive(Default)]
+struct Pending {
+    tokens: Vec<Token>,
+    checkpoint: Option<Checkpoint>,
+}
+
+/// The snapshots recorded so far and the one being looked at.
+#[derive(Default)]
+struct History {
+    snapshots: Vec<Snapshot>,
+    cursor: usize,
+}
 
-pub fn repl(show_token: bool, show_parse: bool) {
+pub fn repl(show_token: bool, show_parse: bool, mut scope: Scope, options: Options) {
+    // every entry is kept, so lines are numbered across the whole session and
+    // errors and snapshots from different entries point to different lines
     let mut input = String::new();
-    let mut scope = Scope::default();
+    // where the last complete entry ended, which the next one is lexed after
+    let mut end: Option<Checkpoint> = None;
+    let mut history = History::default();
+    let mut pending = Pending::default();
+    scope.set_snapshots(options.snapshots);
 
     loop {
-        print!(">> ");
+        if pending.checkpoint.is_none() {
+            pending.tokens.clear();
+        }
+
+        print!(
+            "{}",
+            if pending.checkpoint.is_some() {
+                ".. "
+            } else {
+                ">> "
+            }
+        );
         io::stdout().flush().unwrap();
+        let start = input.len();
         io::stdin().read_line(&mut input).unwrap();
 
-        let tokens = Lexer::new(&input).lex();
+        let line = &input[start..];
+        if options.snapshots && pending.checkpoint.is_none() && line.trim_start().starts_with(':') {
+            history.command(line.trim());
+            // commands aren't kept, so they don't count as lines
+            input.truncate(start);
+            continue;
+        }
+
+        let mut lexer = match (pending.checkpoint.take(), &end) {
+            (Some(checkpoint), _) => Lexer::resume(&input, &checkpoint),
+            (None, Some(end)) => Lexer::after(&input, end),
+            (None, None) => Lexer::new(&input),
+        };
         if show_token {
-            for token in &tokens {
+            for token in lexer.by_ref() {
                 println!("{}", token);
             }
+            for e in lexer.take_errors() {
+                eprintln!("{}", e.render("repl", &input));
+            }
+            end = Some(lexer.checkpoint());
             continue;
         }
 
-        match Parser::new(tokens).parse() {
-            Ok(p) => {
+        // the EOF token is kept out of the pending tokens, so the next line can
+        // go after them
+        let mut eof = None;
+        for token in lexer.by_ref() {
+            match token.value {
+                TokenValue::EOF => eof = Some(token),
+                _ => pending.tokens.push(token),
+            }
+        }
+        let checkpoint = lexer.checkpoint();
+        let lex_errors = lexer.take_errors();
+        end = Some(checkpoint.clone());
+
+        let mut parser =
+            Parser::with_lex_errors(pending.tokens.iter().cloned().chain(eof), lex_errors);
+        if options.strict {
+            parser.set_strict(true);
+        }
+
+        match parser.parse_all() {
+            Err(errors) if is_incomplete(&errors) => pending.checkpoint = Some(checkpoint),
+            Ok(mut p) => {
+                for w in parser.take_warnings() {
+                    eprintln!("{}", w.render("repl", &input));
+                }
+
+                if options.fold {
+                    optimizer::fold_constants(&mut p);
+                }
+
                 if show_parse {
                     for stmt in &p.statements {
                         match stmt {
                             Statement::Assign(a) => println!("{:#?}", a),
                             Statement::If(_) => println!("if {{ ... }}"),
+                            Statement::Assert(a) => println!("{:#?}", a),
+                            Statement::Defer(_) => println!("defer {{ ... }}"),
+                            Statement::Yield(y) => println!("{:#?}", y),
                             Statement::Expression(e) => println!("{:#?}", e),
                         }
                     }
                     continue;
                 }
 
-                match eval(p, &mut scope) {
+                if options.check {
+                    let errors = resolver::check(&p, &scope);
+                    if !errors.is_empty() {
+                        for e in errors {
+                            eprintln!("{}", e.render("repl", &input));
+                        }
+                        continue;
+                    }
+                }
+
+                // Each line gets its own budget.
+                scope.set_fuel(options.fuel);
+                scope.set_memory_limit(options.memory_limit);
+                scope.set_deadline(options.timeout.map(|t| Instant::now() + t));
+                let result = if options.vm {
+                    eval_vm(p, &mut scope)
+                } else {
+                    eval(p, &mut scope)
+                };
+
+                match result {
                     Ok(v) => println!("{} : {}", v, v.value()),
-                    Err(e) => eprintln!("{}", e),
+                    Err(e) => eprintln!("{}", e.render("repl", &input)),
                 }
+
+                history.snapshots.extend(scope.take_snapshots());
+                history.cursor = history.snapshots.len();
+            }
+            Err(errors) => {
+                for e in errors {
+                    eprintln!("{}", e.render("repl", &input));
+                }
+            }
+        }
+    }
+}
+
+/// Whether the errors are from a block that isn't closed yet, which the next
+/// lines may close, rather than from something that's invalid already.
+fn is_incomplete(errors: &[Error]) -> bool {
+    !errors.iter().any(|e| matches!(e, Error::Lex { .. }))
+        && errors.iter().any(|e| {
+            matches!(
+                e,
+                Error::Parse {
+                    found: TokenValue::EOF,
+                    ..
+                }
+            )
+        })
+}
+
+impl History {
+    fn command(&mut self, line: &str) {
+        let mut parts = line.split_whitespace();
+        let command = parts.next().unwrap_or_default();
+        let arg = parts.next();
+
+        match command {
+            ":back" | ":forward" => {
+                let Ok(n) = arg.map_or(Ok(1), str::parse::<usize>) else {
+                    eprintln!("error: expected a number of statements");
+                    return;
+                };
+
+                self.cursor = if command == ":back" {
+                    self.cursor.saturating_sub(n)
+                } else {
+                    (self.cursor + n).min(self.snapshots.len())
+                };
+                self.show();
             }
-            Err(e) => eprintln!("{}", e),
+            ":trace" => match arg {
+                Some(name) => self.trace(Symbol::intern(name)),
+                None => eprintln!("error: expected a variable name"),
+            },
+            _ => eprintln!(
+                "error: unknown command {}; expected :back, :forward or :trace",
+                command
+            ),
         }
+    }
+
+    /// Prints the variables as they were after the statement at the cursor.
+    fn show(&self) {
+        let Some(snapshot) = self
+            .cursor
+            .checked_sub(1)
+            .and_then(|i| self.snapshots.get(i))
+        else {
+            println!("(before the first statement)");
+            return;
+        };
+
+        println!("#{}{}", self.cursor, location(snapshot));
+        for (name, value) in snapshot.sorted() {
+            println!("  {} = {}", name, value.value());
+        }
+    }
+
+    /// Prints every statement that gave a variable a new value.
+    fn trace(&self, name: Symbol) {
+        let changes = snapshot::changes(&self.snapshots, name);
+        if changes.is_empty() {
+            println!("{} has not been set", name);
+        }
+
+        for (i, value) in changes {
+            let snapshot = &self.snapshots[i];
+            println!(
+                "#{}{}: {} = {}",
+                i + 1,
+                location(snapshot),
+                name,
+                value.value()
+            );
+        }
+    }
+}
 
-        input.clear();
+fn location(snapshot: &Snapshot) -> String {
+    match &snapshot.loc {
+        Some(loc) => format!(
+            " at line {}, column {}",
+            loc.line_start + 1,
+            loc.col_start + 1
+        ),
+        None => String::new(),
     }
 }
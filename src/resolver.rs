@@ -0,0 +1,271 @@
+use crate::{
+    error::{Error, RuntimeErrorKind},
+    eval::{builtins, value::Value, Scope},
+    parser::ast::{And, Expression, Function, Identifier, Or, Primitive, Program, Statement},
+    symbol::Symbol,
+    sync::Shared,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Finds problems that would stop a program partway through before it runs:
+/// undefined variables and functions, duplicate parameters and calls with the
+/// wrong number of arguments. Names defined in `scope`, such as builtins, count
+/// as defined.
+///
+/// Function bodies see the variables of whoever calls them, so inside them any
+/// name assigned anywhere in the program is accepted. Names and arities aren't
+/// checked at all if the program calls `eval`, which can define anything.
+pub fn check(program: &Program, scope: &Scope) -> Vec<Error> {
+    let mut resolver = Resolver {
+        scope,
+        defined: HashSet::new(),
+        assigned: HashSet::new(),
+        functions: HashMap::new(),
+        check_names: true,
+        no_builtins: program.pragmas.no_builtins,
+        nested: 0,
+        errors: Vec::new(),
+    };
+
+    let mut counts = HashMap::new();
+    for stmt in &program.statements {
+        resolver.collect_statement(stmt, &mut counts);
+    }
+    // only functions that are never reassigned or shadowed have a known arity
+    resolver
+        .functions
+        .retain(|name, _| resolver.check_names && counts.get(name) == Some(&1));
+
+    for stmt in &program.statements {
+        resolver.statement(stmt);
+    }
+
+    resolver.errors
+}
+
+struct Resolver<'a> {
+    scope: &'a Scope,
+    /// Names assigned so far at the top level.
+    defined: HashSet<Symbol>,
+    /// Names assigned or used as a parameter anywhere in the program.
+    assigned: HashSet<Symbol>,
+    /// Functions assigned to names, to check the arity of calls to them.
+    functions: HashMap<Symbol, Function>,
+    check_names: bool,
+    /// Whether the program takes away the builtins in `scope` with a pragma.
+    no_builtins: bool,
+    /// How many function or deferred bodies the walk is inside.
+    nested: usize,
+    errors: Vec<Error>,
+}
+
+impl Resolver<'_> {
+    fn collect_statement(&mut self, stmt: &Statement, counts: &mut HashMap<Symbol, usize>) {
+        match stmt {
+            Statement::Assign(a) => {
+                self.assigned.insert(a.name.value);
+                *counts.entry(a.name.value).or_default() += 1;
+                if let Expression::Function(f) = &a.value {
+                    self.functions.insert(a.name.value, f.clone());
+                }
+                self.collect_expression(&a.value, counts);
+            }
+            Statement::If(i) => {
+                self.collect_expression(&i.condition, counts);
+                for stmt in i.consequence.iter().chain(i.alternative.iter().flatten()) {
+                    self.collect_statement(stmt, counts);
+                }
+            }
+            Statement::Assert(a) => {
+                self.collect_expression(&a.condition, counts);
+                if let Some(message) = &a.message {
+                    self.collect_expression(message, counts);
+                }
+            }
+            Statement::Defer(d) => {
+                for stmt in &d.body {
+                    self.collect_statement(stmt, counts);
+                }
+            }
+            Statement::Yield(y) => self.collect_expression(&y.value, counts),
+            Statement::Expression(e) => self.collect_expression(e, counts),
+        }
+    }
+
+    fn collect_expression(&mut self, expr: &Expression, counts: &mut HashMap<Symbol, usize>) {
+        match expr {
+            Expression::Primitive(..) | Expression::Identifier(_) => {}
+            Expression::Function(f) => {
+                for param in &f.params {
+                    self.assigned.insert(param.value);
+                    *counts.entry(param.value).or_default() += 1;
+                }
+                for stmt in f.body.iter() {
+                    self.collect_statement(stmt, counts);
+                }
+            }
+            Expression::Block(b) => {
+                for stmt in b.body.iter() {
+                    self.collect_statement(stmt, counts);
+                }
+            }
+            Expression::Call(c) => {
+                if &*c.name.value.as_str() == "eval" {
+                    self.check_names = false;
+                }
+                for arg in &c.args {
+                    self.collect_expression(arg, counts);
+                }
+            }
+            Expression::Operator(o) => {
+                for arg in &o.args {
+                    self.collect_expression(arg, counts);
+                }
+            }
+            Expression::List(items, ..)
+            | Expression::And(And(items, ..))
+            | Expression::Or(Or(items, ..)) => {
+                for item in items {
+                    self.collect_expression(item, counts);
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Assign(a) => {
+                self.expression(&a.value);
+                self.defined.insert(a.name.value);
+            }
+            Statement::If(i) => {
+                self.expression(&i.condition);
+                for stmt in i.consequence.iter().chain(i.alternative.iter().flatten()) {
+                    self.statement(stmt);
+                }
+            }
+            Statement::Assert(a) => {
+                self.expression(&a.condition);
+                if let Some(message) = &a.message {
+                    self.expression(message);
+                }
+            }
+            Statement::Defer(d) => {
+                self.nested += 1;
+                for stmt in &d.body {
+                    self.statement(stmt);
+                }
+                self.nested -= 1;
+            }
+            Statement::Yield(y) => self.expression(&y.value),
+            Statement::Expression(e) => self.expression(e),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Primitive(..) => {}
+            Expression::Identifier(i) => {
+                if !self.is_defined(i) {
+                    self.errors.push(
+                        Error::runtime(RuntimeErrorKind::UndefinedVariable(i.value.to_string()))
+                            .did_you_mean(&i.value.as_str(), self.names())
+                            .at(&i.loc),
+                    );
+                }
+            }
+            Expression::Function(f) => self.function(f),
+            Expression::Block(b) => {
+                // names assigned in a block go out of scope when it ends
+                let defined = self.defined.clone();
+                for stmt in b.body.iter() {
+                    self.statement(stmt);
+                }
+                self.defined = defined;
+            }
+            Expression::Call(c) => {
+                if !self.is_defined(&c.name) {
+                    self.errors.push(
+                        Error::runtime(RuntimeErrorKind::UndefinedFunction(
+                            c.name.value.to_string(),
+                        ))
+                        .did_you_mean(&c.name.value.as_str(), self.names())
+                        .at(&c.loc),
+                    );
+                } else if let Some(f) = self.functions.get(&c.name.value) {
+                    let unit = matches!(c.args[..], [Expression::Primitive(Primitive::Null, ..)]);
+                    if let Err(e) = Value::check_arity(f, c.name.value, c.args.len(), unit) {
+                        self.errors.push(e.at(&c.loc));
+                    }
+                }
+
+                for arg in &c.args {
+                    self.expression(arg);
+                }
+            }
+            Expression::Operator(o) => {
+                for arg in &o.args {
+                    self.expression(arg);
+                }
+            }
+            Expression::List(items, ..)
+            | Expression::And(And(items, ..))
+            | Expression::Or(Or(items, ..)) => {
+                for item in items {
+                    self.expression(item);
+                }
+            }
+        }
+    }
+
+    fn function(&mut self, f: &Function) {
+        let mut seen = HashSet::new();
+        for param in &f.params {
+            if !seen.insert(param.value) {
+                self.errors.push(
+                    Error::new(&format!("duplicate parameter {}", param.value)).at(&param.loc),
+                );
+            }
+        }
+
+        self.nested += 1;
+        for stmt in f.body.iter() {
+            self.statement(stmt);
+        }
+        self.nested -= 1;
+    }
+
+    /// Gets the names that can be used where the walk is, to suggest instead of
+    /// an undefined one.
+    fn names(&self) -> Vec<Shared<str>> {
+        let defined = if self.nested > 0 {
+            &self.assigned
+        } else {
+            &self.defined
+        };
+
+        let mut names = self.scope.names();
+        if self.no_builtins {
+            names.retain(|name| {
+                !builtins::BUILTINS
+                    .iter()
+                    .any(|b| b.name == name.as_str().as_ref())
+            });
+        }
+        names.extend(defined);
+        names.iter().map(Symbol::as_str).collect()
+    }
+
+    fn is_defined(&self, name: &Identifier) -> bool {
+        !self.check_names
+            || self
+                .scope
+                .get(name)
+                .is_some_and(|v| !(self.no_builtins && matches!(v, Value::Builtin(_))))
+            || if self.nested > 0 {
+                self.assigned.contains(&name.value)
+            } else {
+                self.defined.contains(&name.value)
+            }
+    }
+}
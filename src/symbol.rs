@@ -0,0 +1,81 @@
+use crate::sync::Shared;
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display, Formatter, Result},
+};
+
+/// An interned name. Symbols for the same name are equal, so comparing and
+/// hashing them is as cheap as for an integer.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<Shared<str>, Symbol>,
+    names: Vec<Shared<str>>,
+}
+
+#[cfg(not(feature = "sync"))]
+thread_local! {
+    static INTERNER: std::cell::RefCell<Interner> = Default::default();
+}
+
+/// With the `sync` feature symbols are shared by all threads, since values holding
+/// them can be sent between threads.
+#[cfg(feature = "sync")]
+static INTERNER: std::sync::LazyLock<std::sync::Mutex<Interner>> =
+    std::sync::LazyLock::new(Default::default);
+
+#[cfg(not(feature = "sync"))]
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+    INTERNER.with(|i| f(&mut i.borrow_mut()))
+}
+
+#[cfg(feature = "sync")]
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+    f(&mut INTERNER.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+impl Symbol {
+    /// Gets the symbol for a name, adding it to the symbol table if it is new.
+    pub fn intern(name: &str) -> Self {
+        with_interner(|i| {
+            if let Some(symbol) = i.symbols.get(name) {
+                return *symbol;
+            }
+
+            let symbol = Self(i.names.len() as u32);
+            let name: Shared<str> = name.into();
+            i.names.push(name.clone());
+            i.symbols.insert(name, symbol);
+
+            symbol
+        })
+    }
+
+    pub fn as_str(&self) -> Shared<str> {
+        with_interner(|i| i.names[self.0 as usize].clone())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.as_str())
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str(&self.as_str())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Debug::fmt(&*self.as_str(), f)
+    }
+}
@@ -0,0 +1,162 @@
+// The types used to share state between scopes and values are `Rc` and `RefCell`
+// by default, or `Arc` and a mutex with the `sync` feature so that scopes and
+// values can be sent between threads.
+
+#[cfg(not(feature = "sync"))]
+pub use std::rc::Rc as Shared;
+#[cfg(feature = "sync")]
+pub use std::sync::Arc as Shared;
+
+#[cfg(not(feature = "sync"))]
+pub type Lock<T> = std::cell::RefCell<T>;
+
+/// A mutex with the same methods as a `RefCell`. Unlike a `RefCell`, borrowing
+/// it while another thread has borrowed it blocks instead of panicking. Borrowing
+/// it again on the thread that has it, even just to read it, would block forever,
+/// so debug builds panic instead, as a `RefCell` would.
+#[cfg(feature = "sync")]
+#[derive(Debug, Default)]
+pub struct Lock<T> {
+    inner: std::sync::Mutex<T>,
+    /// The ID of the thread that has it borrowed, or 0 if none has.
+    #[cfg(debug_assertions)]
+    owner: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(feature = "sync")]
+impl<T> Lock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: std::sync::Mutex::new(value),
+            #[cfg(debug_assertions)]
+            owner: Default::default(),
+        }
+    }
+
+    pub fn borrow(&self) -> Guard<'_, T> {
+        #[cfg(debug_assertions)]
+        let thread = debug::thread_id();
+        #[cfg(debug_assertions)]
+        if self.owner.load(std::sync::atomic::Ordering::Relaxed) == thread {
+            panic!("already borrowed: a lock can't be borrowed again by the thread that has it");
+        }
+
+        let guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        #[cfg(debug_assertions)]
+        self.owner
+            .store(thread, std::sync::atomic::Ordering::Relaxed);
+
+        Guard {
+            guard,
+            #[cfg(debug_assertions)]
+            owner: &self.owner,
+        }
+    }
+
+    pub fn borrow_mut(&self) -> Guard<'_, T> {
+        self.borrow()
+    }
+}
+
+/// A borrow of a [`Lock`], which releases it when dropped.
+#[cfg(feature = "sync")]
+pub struct Guard<'a, T> {
+    guard: std::sync::MutexGuard<'a, T>,
+    #[cfg(debug_assertions)]
+    owner: &'a std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(feature = "sync")]
+impl<T> std::ops::Deref for Guard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T> std::ops::DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+#[cfg(all(feature = "sync", debug_assertions))]
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        // this runs before the mutex is unlocked, so no other thread can have
+        // taken it yet
+        self.owner.store(0, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(all(feature = "sync", debug_assertions))]
+mod debug {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+    thread_local! {
+        static ID: usize = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A number for the current thread that is never 0, which is cheaper to
+    /// compare than a `ThreadId`.
+    pub fn thread_id() -> usize {
+        ID.with(|id| *id)
+    }
+}
+
+/// Implemented by everything that can be sent between threads when the `sync`
+/// feature is enabled, and by everything otherwise.
+#[cfg(feature = "sync")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "sync")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// Implemented by everything that can be shared between threads when the `sync`
+/// feature is enabled, and by everything otherwise.
+#[cfg(feature = "sync")]
+pub trait MaybeSync: Sync {}
+#[cfg(feature = "sync")]
+impl<T: Sync + ?Sized> MaybeSync for T {}
+
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSync for T {}
+
+#[cfg(feature = "sync")]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<crate::eval::value::Value>();
+    assert_send_sync::<crate::eval::Scope>();
+    assert_send_sync::<crate::error::Error>();
+};
+
+#[cfg(all(test, feature = "sync", debug_assertions))]
+mod tests {
+    use super::Lock;
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn nested_borrow_panics() {
+        let lock = Lock::new(0);
+        let _outer = lock.borrow();
+        let _inner = lock.borrow();
+    }
+
+    #[test]
+    fn borrow_after_release() {
+        let lock = Lock::new(0);
+        *lock.borrow_mut() += 1;
+        assert_eq!(*lock.borrow(), 1);
+    }
+}
@@ -0,0 +1,34 @@
+//! Formats the programs in `tests/format`, which are already formatted, checking
+//! that they come back unchanged along with their comments.
+
+use clip::{
+    lexer::Lexer,
+    parser::{format, Parser},
+};
+use std::fs;
+
+#[test]
+fn formatted_programs_are_unchanged() {
+    let mut paths = fs::read_dir("tests/format")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty(), "there are no programs to format");
+
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        let mut lexer = Lexer::new(&source);
+        lexer.keep_comments(true);
+        let program = Parser::from_lexer(lexer)
+            .parse_all()
+            .unwrap_or_else(|_| panic!("{} doesn't parse", path.display()));
+
+        assert_eq!(
+            format::program(&program),
+            source,
+            "formatting changed {}",
+            path.display()
+        );
+    }
+}
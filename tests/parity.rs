@@ -0,0 +1,66 @@
+//! Runs the programs in `tests/programs` with both the tree-walking interpreter
+//! and the VM, checking that they print what the `.out` file next to each one
+//! has and fail with the same errors. Set `BLESS=1` to write the `.out` files
+//! from what the tree-walker gives instead.
+
+use clip::{
+    error::Error,
+    eval::{eval, eval_vm, runtime::Capture, value::Value, Scope},
+    lexer::Lexer,
+    optimizer,
+    parser::{ast::Program, Parser},
+};
+use std::{env, fs, path::Path};
+
+/// Runs the program at `path` with `run`, giving what it printed followed by its
+/// result or error, rendered the same way as `clip run` does.
+fn run(path: &Path, run: fn(Program, &mut Scope) -> Result<Value, Error>) -> String {
+    let source = fs::read_to_string(path).unwrap();
+    let name = path.display().to_string();
+    let mut program = match Parser::from_lexer(Lexer::new(&source)).parse_all() {
+        Ok(program) => program,
+        Err(errors) => panic!(
+            "{} doesn't parse: {}",
+            name,
+            errors[0].render(&name, &source)
+        ),
+    };
+    optimizer::fold_constants(&mut program);
+
+    let output = Capture::default();
+    let mut scope = Scope::new();
+    scope.set_output(output.clone());
+    scope.set_seed(0);
+
+    let result = match run(program, &mut scope) {
+        Ok(v) => format!("{} : {}", v, v.value()),
+        Err(e) => e.render(&name, &source),
+    };
+    output.contents() + &result
+}
+
+#[test]
+fn programs_give_expected_output() {
+    let mut paths = fs::read_dir("tests/programs")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "clp"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty(), "there are no programs to run");
+
+    for path in paths {
+        let tree = run(&path, eval);
+        let vm = run(&path, eval_vm);
+        assert_eq!(tree, vm, "backends disagree on {}", path.display());
+
+        let expected = path.with_extension("out");
+        if env::var_os("BLESS").is_some() {
+            fs::write(&expected, &tree).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&expected)
+            .unwrap_or_else(|_| panic!("{} is missing", expected.display()));
+        assert_eq!(tree, expected, "unexpected output from {}", path.display());
+    }
+}